@@ -0,0 +1,114 @@
+use std::ffi::OsString;
+use std::io;
+use std::path::PathBuf;
+
+/// The config file read if no other is given.
+const DEFAULT_CONFIG: &str = "fisherman.yml";
+
+/// The environment variable that can name the config file instead of an argument.
+pub const CONFIG_VARIABLE: &str = "FISHERMAN_CONFIG";
+
+/// The command line arguments of the binary.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    /// The config file given by `--config`, if there was one
+    pub config: Option<PathBuf>,
+    /// Whether to deploy every repository and exit, rather than waiting for webhooks
+    pub reconcile: bool,
+}
+
+impl Args {
+    /// Parses the arguments, excluding the name of the binary itself.
+    ///
+    /// The config can be given as `--config <path>` or `--config=<path>`, and `reconcile` can be
+    /// given as a subcommand. Anything else is rejected.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> io::Result<Self> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            if let Some(path) = arg.strip_prefix("--config=") {
+                parsed.config = Some(PathBuf::from(path));
+                continue;
+            }
+
+            match arg.as_str() {
+                "--config" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| invalid("`--config` requires a path to the config file"))?;
+
+                    parsed.config = Some(PathBuf::from(path));
+                }
+                "reconcile" => parsed.reconcile = true,
+                _ => return Err(invalid(&format!("Unknown argument `{}`", arg))),
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// Resolves the config file to read, preferring `--config`, then the `FISHERMAN_CONFIG`
+    /// variable and finally `fisherman.yml` in the working directory.
+    pub fn config_path(&self, variable: Option<OsString>) -> PathBuf {
+        self.config
+            .clone()
+            .or_else(|| variable.filter(|v| !v.is_empty()).map(PathBuf::from))
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+    use std::path::PathBuf;
+
+    use crate::args::Args;
+
+    fn parse(args: &[&str]) -> std::io::Result<Args> {
+        Args::parse(args.iter().map(|arg| String::from(*arg)))
+    }
+
+    #[test]
+    fn config_paths_can_be_given_as_arguments() {
+        let expected = Some(PathBuf::from("/etc/fisherman/fisherman.yml"));
+
+        assert_eq!(
+            parse(&["--config", "/etc/fisherman/fisherman.yml"])
+                .unwrap()
+                .config,
+            expected
+        );
+        assert_eq!(
+            parse(&["reconcile", "--config=/etc/fisherman/fisherman.yml"])
+                .unwrap()
+                .config,
+            expected
+        );
+
+        assert!(parse(&["--config"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+    }
+
+    #[test]
+    fn config_paths_fall_back_to_the_environment_then_the_default() {
+        let variable = Some(OsString::from("/etc/fisherman.yml"));
+
+        let args = parse(&["--config", "custom.yml"]).unwrap();
+        assert_eq!(
+            args.config_path(variable.clone()),
+            PathBuf::from("custom.yml")
+        );
+
+        let args = parse(&[]).unwrap();
+        assert_eq!(
+            args.config_path(variable),
+            PathBuf::from("/etc/fisherman.yml")
+        );
+        assert_eq!(args.config_path(None), PathBuf::from("fisherman.yml"));
+    }
+}
@@ -1,14 +1,120 @@
 use hmac::{Hmac, Mac};
+use sha1::Sha1;
 use sha2::Sha256;
 
 use crate::error::ServerError;
 
 type HmacSha256 = Hmac<Sha256>;
+type HmacSha1 = Hmac<Sha1>;
+
+/// Represents the ways in which the body of a webhook can be authenticated.
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureAlgorithm {
+    /// A HMAC-SHA256 of the body, as sent by GitHub in `X-Hub-Signature-256`
+    #[default]
+    Sha256,
+    /// A HMAC-SHA1 of the body, as sent by older senders in `X-Hub-Signature`
+    Sha1,
+    /// The secret itself, sent verbatim in `X-Gitlab-Token`
+    Token,
+    /// A HMAC-SHA256 of the body, as sent by Bitbucket in `X-Hub-Signature`
+    Bitbucket,
+}
+
+impl SignatureAlgorithm {
+    /// Gets the name of the header the signature is sent in.
+    pub fn header(self) -> &'static str {
+        match self {
+            Self::Sha256 => "X-Hub-Signature-256",
+            Self::Sha1 | Self::Bitbucket => "X-Hub-Signature",
+            Self::Token => "X-Gitlab-Token",
+        }
+    }
+
+    /// Gets the algorithm to fall back to if the header for this one is missing, as older senders
+    /// only sign payloads with HMAC-SHA1.
+    pub fn fallback(self) -> Option<Self> {
+        match self {
+            Self::Sha256 => Some(Self::Sha1),
+            _ => None,
+        }
+    }
+
+    /// Gets the signature from the header value, removing any `algorithm=` prefix before it.
+    ///
+    /// The prefix is found by splitting on the first `=`, so any `algorithm=hex` format is
+    /// supported as well as bare signatures. Tokens are used as they are, since they may contain
+    /// `=` themselves.
+    pub fn signature(self, value: &str) -> &str {
+        match self {
+            Self::Token => value,
+            _ => value
+                .split_once('=')
+                .map_or(value, |(_, signature)| signature),
+        }
+    }
+
+    /// Checks whether the expected value is correct for the body and secret.
+    fn verify(self, bytes: &[u8], secret: &[u8], expected: &[u8]) -> bool {
+        match self {
+            Self::Sha256 | Self::Bitbucket => verify_hmac::<HmacSha256>(bytes, secret, expected),
+            Self::Sha1 => verify_hmac::<HmacSha1>(bytes, secret, expected),
+            Self::Token => constant_time_eq(secret, expected),
+        }
+    }
+}
+
+/// Verifies a hex encoded HMAC of the body, using the secret as the key.
+fn verify_hmac<M: Mac + hmac::digest::KeyInit>(
+    bytes: &[u8],
+    secret: &[u8],
+    expected: &[u8],
+) -> bool {
+    // Decode the expected from hex to bytes
+    let decoded = match hex::decode(expected) {
+        Ok(decoded) => decoded,
+        Err(_) => return false,
+    };
+
+    let mut mac = <M as Mac>::new_from_slice(secret).expect("HMAC can take key of any size");
+
+    mac.update(bytes);
+    mac.verify_slice(&decoded).is_ok()
+}
+
+/// Compares two byte slices without short-circuiting on the first difference.
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    left.len() == right.len() && left.iter().zip(right).fold(0, |acc, (l, r)| acc | (l ^ r)) == 0
+}
+
+/// Validates the token provided to an administrative endpoint.
+///
+/// Administrative endpoints are disabled entirely unless a token has been configured, so a missing
+/// configured token always fails authentication.
+pub fn validate_admin_token(
+    configured: Option<&str>,
+    provided: Option<&str>,
+) -> Result<(), ServerError> {
+    match (configured, provided) {
+        (Some(configured), Some(provided))
+            if constant_time_eq(configured.as_bytes(), provided.as_bytes()) =>
+        {
+            Ok(())
+        }
+        (None, _) => {
+            tracing::warn!("Rejecting an administrative request as no `admin_token` is configured");
+            Err(ServerError::Unauthorized)
+        }
+        _ => Err(ServerError::Unauthorized),
+    }
+}
 
 pub fn validate_webhook_body(
     bytes: &[u8],
     secret: Option<&[u8]>,
     expected: Option<&[u8]>,
+    algorithm: SignatureAlgorithm,
 ) -> Result<(), ServerError> {
     // We don't have a secret and we didn't expect one either
     if secret.or(expected).is_none() {
@@ -17,16 +123,11 @@ pub fn validate_webhook_body(
 
     // We have a secret and something to check, so verify it
     if let (Some(secret), Some(expected)) = (secret, expected) {
-        // Decode the expected from hex to bytes
-        let decoded = hex::decode(expected).unwrap();
-
-        let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC can take key of any size");
-
-        mac.update(bytes);
-
-        return mac
-            .verify_slice(&decoded)
-            .map_err(|_| ServerError::Unauthorized);
+        return if algorithm.verify(bytes, secret, expected) {
+            Ok(())
+        } else {
+            Err(ServerError::Unauthorized)
+        };
     }
 
     tracing::warn!(has_secret = %secret.is_some(), has_expected = %expected.is_some(), "Either expected a value and did not receive one or received one without expecting it");
@@ -36,23 +137,24 @@ pub fn validate_webhook_body(
 
 #[cfg(test)]
 mod tests {
-    use crate::auth::validate_webhook_body;
+    use crate::auth::{validate_admin_token, validate_webhook_body, SignatureAlgorithm};
 
     static SAMPLE_PAYLOAD: &[u8] = include_bytes!("../sample_payload.json");
+    static SECRET: &[u8] = b"ac9045a77c15bd105cfa09a64635f9b006b3f845";
 
     #[test]
     fn missing_secret_and_expected_allows_access() {
-        assert!(validate_webhook_body(b"", None, None).is_ok());
+        assert!(validate_webhook_body(b"", None, None, SignatureAlgorithm::Sha256).is_ok());
     }
 
     #[test]
     fn secret_but_not_expected_fails_authentication() {
-        assert!(validate_webhook_body(b"", Some(b""), None).is_err());
+        assert!(validate_webhook_body(b"", Some(b""), None, SignatureAlgorithm::Sha256).is_err());
     }
 
     #[test]
     fn missing_secret_but_expected_fails_authentication() {
-        assert!(validate_webhook_body(b"", None, Some(b"")).is_err());
+        assert!(validate_webhook_body(b"", None, Some(b""), SignatureAlgorithm::Sha256).is_err());
     }
 
     #[test]
@@ -61,6 +163,101 @@ mod tests {
         let expected =
             Some("9e31091766db83d80ec93c84b24158d54839482e5566c1dfbe0dca45cfdc330b".as_bytes());
 
-        assert!(validate_webhook_body(SAMPLE_PAYLOAD, secret, expected).is_ok());
+        assert!(validate_webhook_body(
+            SAMPLE_PAYLOAD,
+            secret,
+            expected,
+            SignatureAlgorithm::Sha256
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn correct_sha1_payloads_are_validated() {
+        let expected = Some("c35ab7f1d22c8e59f5df88e6a6eeffe354333907".as_bytes());
+
+        assert!(validate_webhook_body(
+            SAMPLE_PAYLOAD,
+            Some(SECRET),
+            expected,
+            SignatureAlgorithm::Sha1
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn signatures_for_other_algorithms_are_rejected() {
+        let sha256 =
+            Some("9e31091766db83d80ec93c84b24158d54839482e5566c1dfbe0dca45cfdc330b".as_bytes());
+
+        assert!(validate_webhook_body(
+            SAMPLE_PAYLOAD,
+            Some(SECRET),
+            sha256,
+            SignatureAlgorithm::Sha1
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn invalid_hex_signatures_are_rejected() {
+        let expected = Some("not hex".as_bytes());
+
+        assert!(validate_webhook_body(
+            SAMPLE_PAYLOAD,
+            Some(SECRET),
+            expected,
+            SignatureAlgorithm::Sha256
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn matching_tokens_are_validated() {
+        assert!(validate_webhook_body(
+            SAMPLE_PAYLOAD,
+            Some(SECRET),
+            Some(SECRET),
+            SignatureAlgorithm::Token
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn mismatched_tokens_are_rejected() {
+        assert!(validate_webhook_body(
+            SAMPLE_PAYLOAD,
+            Some(SECRET),
+            Some(b"ac9045a77c15bd105cfa09a64635f9b006b3f846"),
+            SignatureAlgorithm::Token
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn admin_endpoints_are_disabled_without_a_token() {
+        assert!(validate_admin_token(None, None).is_err());
+        assert!(validate_admin_token(None, Some("token")).is_err());
+    }
+
+    #[test]
+    fn admin_tokens_must_match() {
+        assert!(validate_admin_token(Some("token"), Some("token")).is_ok());
+        assert!(validate_admin_token(Some("token"), Some("nekot")).is_err());
+        assert!(validate_admin_token(Some("token"), None).is_err());
+    }
+
+    #[test]
+    fn signature_prefixes_of_any_length_are_removed() {
+        assert_eq!(SignatureAlgorithm::Sha1.signature("sha1=abcdef"), "abcdef");
+        assert_eq!(
+            SignatureAlgorithm::Sha256.signature("sha256=abcdef"),
+            "abcdef"
+        );
+        assert_eq!(SignatureAlgorithm::Sha256.signature("abcdef"), "abcdef");
+        assert_eq!(
+            SignatureAlgorithm::Token.signature("c2VjcmV0=="),
+            "c2VjcmV0=="
+        );
     }
 }
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// The longest message Discord will accept.
+const MAX_MESSAGE_LENGTH: usize = 2000;
+
+/// Collects messages for each channel so that bursts of them can be sent together.
+#[derive(Debug, Default)]
+pub struct MessageBatches {
+    pending: Mutex<HashMap<u64, Vec<String>>>,
+}
+
+impl MessageBatches {
+    /// Adds a message to the batch for a channel.
+    ///
+    /// Returns whether this started a new batch, in which case the caller is responsible for
+    /// sending it once the batching window has passed.
+    pub fn push(&self, channel: u64, message: String) -> bool {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        let batch = pending.entry(channel).or_default();
+        batch.push(message);
+
+        batch.len() == 1
+    }
+
+    /// Takes every message batched for a channel, ending the batch.
+    pub fn take(&self, channel: u64) -> Vec<String> {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        pending.remove(&channel).unwrap_or_default()
+    }
+}
+
+/// Combines a batch of messages into as few as possible, without exceeding Discord's limit.
+pub fn summarize(messages: Vec<String>) -> Vec<String> {
+    if messages.len() <= 1 {
+        return messages;
+    }
+
+    let mut summaries = vec![format!("{} deploys finished:", messages.len())];
+
+    for message in messages {
+        let line = format!("- {}", message);
+        let current = summaries.last_mut().expect("Summaries are never empty");
+
+        if current.len() + 1 + line.len() <= MAX_MESSAGE_LENGTH {
+            current.push('\n');
+            current.push_str(&line);
+        } else {
+            summaries.push(line);
+        }
+    }
+
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::batch::{summarize, MessageBatches};
+
+    #[test]
+    fn batches_are_kept_per_channel() {
+        let batches = MessageBatches::default();
+
+        assert!(batches.push(1, String::from("first")));
+        assert!(!batches.push(1, String::from("second")));
+        assert!(batches.push(2, String::from("other")));
+
+        assert_eq!(batches.take(1), ["first", "second"]);
+        assert!(batches.take(1).is_empty());
+        assert!(batches.push(1, String::from("third")));
+    }
+
+    #[test]
+    fn batches_are_summarized_within_the_length_limit() {
+        assert_eq!(summarize(vec![String::from("only")]), ["only"]);
+
+        let summaries = summarize(vec![String::from("first"), String::from("second")]);
+        assert_eq!(summaries, ["2 deploys finished:\n- first\n- second"]);
+
+        let long = "a".repeat(1500);
+        let summaries = summarize(vec![long.clone(), long]);
+
+        assert_eq!(summaries.len(), 2);
+        assert!(summaries.iter().all(|summary| summary.len() <= 2000));
+    }
+}
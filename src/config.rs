@@ -1,31 +1,107 @@
 use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
 
 use anyhow::{bail, Result};
-use serenity::http::client::Http;
+use rand::Rng;
+use regex::Regex;
+use serenity::http::client::{Http, HttpBuilder};
 use serenity::model::id::ChannelId;
+use tokio::sync::{OwnedMutexGuard, OwnedSemaphorePermit, Semaphore, SemaphorePermit};
+
+use crate::auth::SignatureAlgorithm;
+use crate::batch::MessageBatches;
+use crate::events::StageLog;
+use crate::github::GitHubClient;
+use crate::inflight::InFlight;
+use crate::logs::LogStream;
+use crate::process;
+use crate::webhook::WebhookVariant;
 
 /// Represents any commands that should be run by the shell.
 #[derive(Debug, Deserialize)]
 pub struct Commands(Vec<Command>);
 
+/// Describes the deploy that commands are being run for.
+#[derive(Clone, Copy, Debug)]
+pub struct CommandContext<'a> {
+    /// The full name of the repository being deployed
+    pub repository: &'a str,
+    /// The branch or commit being deployed, if there is one
+    pub branch: Option<&'a str>,
+    /// The commit being deployed, if it is known
+    pub commit: Option<&'a str>,
+    /// The author of the commit being deployed, if it is known
+    pub author: Option<&'a str>,
+}
+
+impl<'a> CommandContext<'a> {
+    /// Creates a context for a repository, without knowing what is being deployed.
+    pub fn new(repository: &'a str) -> Self {
+        Self {
+            repository,
+            branch: None,
+            commit: None,
+            author: None,
+        }
+    }
+
+    /// Gets the environment variables that describe the deploy to commands, leaving out any that
+    /// are unknown.
+    pub fn env(&self) -> Vec<(&'static str, &'a str)> {
+        let mut env = vec![("FISHERMAN_REPO", self.repository)];
+
+        env.extend(self.branch.map(|branch| ("FISHERMAN_BRANCH", branch)));
+        env.extend(self.commit.map(|commit| ("FISHERMAN_COMMIT", commit)));
+        env.extend(self.author.map(|author| ("FISHERMAN_AUTHOR", author)));
+
+        env
+    }
+}
+
 impl Commands {
-    pub async fn execute(&self, repo_path: &Path) -> Result<()> {
+    /// Executes the commands, describing the deploy to them through environment variables.
+    pub async fn execute(
+        &self,
+        config: &Config,
+        context: &CommandContext<'_>,
+        repo_path: &Path,
+    ) -> Result<()> {
+        self.execute_with_env(config, context, repo_path, &[]).await
+    }
+
+    /// Executes the commands with additional environment variables, such as to describe why they
+    /// are being run.
+    pub async fn execute_with_env(
+        &self,
+        config: &Config,
+        context: &CommandContext<'_>,
+        repo_path: &Path,
+        env: &[(&str, &str)],
+    ) -> Result<()> {
+        let repository = context.repository;
+
         for command in &self.0 {
             let working_dir = repo_path.join(command.working_dir.clone().unwrap_or_default());
 
             tracing::info!(?command, ?working_dir, "Executing a user specified command");
 
-            let mut to_execute = tokio::process::Command::new(&command.program);
+            let mut to_execute = config.create_command(repository, &command.program);
 
             if let Some(args) = command.args.as_ref() {
                 to_execute.args(args);
             }
 
-            let status = to_execute.current_dir(&working_dir).spawn()?.wait().await?;
+            to_execute.current_dir(&working_dir);
+            to_execute.envs(context.env());
+            to_execute.envs(env.iter().copied());
 
-            if !status.success() {
+            if !config.run_command(repository, &mut to_execute).await? {
                 bail!("Failed to execute command: {:?}", command);
             }
         }
@@ -34,6 +110,237 @@ impl Commands {
     }
 }
 
+/// The stages of a deploy, which are run in this order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    #[serde(rename = "pre_pull")]
+    PrePull,
+    Pull,
+    Precommands,
+    Build,
+    Restart,
+    Commands,
+    Notify,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::PrePull => "pre_pull",
+            Self::Pull => "pull",
+            Self::Precommands => "precommands",
+            Self::Build => "build",
+            Self::Restart => "restart",
+            Self::Commands => "commands",
+            Self::Notify => "notify",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// The commit statuses that must pass on GitHub before a push is deployed.
+#[derive(Debug, Deserialize)]
+pub struct RequiredChecks {
+    /// The contexts of the statuses that must succeed, such as `ci/build`
+    pub contexts: Vec<String>,
+    /// The number of seconds to wait for the statuses to complete before giving up
+    pub timeout_secs: Option<u64>,
+    /// The number of seconds to wait between checking the statuses
+    pub interval_secs: Option<u64>,
+}
+
+impl RequiredChecks {
+    /// Gets how long to wait for the statuses to complete, defaulting to 10 minutes.
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs.unwrap_or(600))
+    }
+
+    /// Gets how long to wait between checking the statuses, defaulting to 15 seconds.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.unwrap_or(15))
+    }
+}
+
+/// How to confirm restarted programs are stable, rather than crashing shortly after starting.
+#[derive(Debug, Deserialize)]
+pub struct VerifyRestart {
+    /// The number of seconds programs have to become stable before giving up
+    pub timeout_secs: Option<u64>,
+    /// The number of seconds programs must stay running for to be considered stable
+    pub settle_secs: Option<u64>,
+    /// The number of seconds to wait between checking the status of programs
+    pub interval_secs: Option<u64>,
+}
+
+impl VerifyRestart {
+    /// Gets how long programs have to become stable, defaulting to 60 seconds.
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs.unwrap_or(60))
+    }
+
+    /// Gets how long programs must stay running for, defaulting to 10 seconds.
+    pub fn settle(&self) -> Duration {
+        Duration::from_secs(self.settle_secs.unwrap_or(10))
+    }
+
+    /// Gets how long to wait between checking the status of programs, defaulting to 1 second.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.unwrap_or(1))
+    }
+
+    /// Checks whether the output of `supervisorctl status` for a program reports it as running.
+    ///
+    /// Other status commands are trusted to fail for programs that are not running instead.
+    pub fn is_running(output: &str) -> bool {
+        output.split_whitespace().nth(1) == Some("RUNNING")
+    }
+}
+
+/// Additional checks for deciding whether a deploy succeeded, beyond exit codes.
+#[derive(Debug, Deserialize)]
+pub struct SuccessCriteria {
+    /// A pattern that must appear in the output of builds and commands
+    pub required_output: Option<String>,
+    /// A pattern that must not appear in the output of builds and commands
+    pub forbidden_output: Option<String>,
+    /// The status the `status_command` must report for restarted binaries, such as `RUNNING`
+    pub supervisor_status: Option<String>,
+    /// The number of seconds to wait after restarting before checking the status
+    pub supervisor_delay_secs: Option<u64>,
+    /// Whether every binary must have been written by the build, rather than left from before it
+    #[serde(default)]
+    pub fresh_binaries: bool,
+}
+
+impl SuccessCriteria {
+    /// Checks whether the output of builds and commands needs to be captured.
+    pub fn checks_output(&self) -> bool {
+        self.required_output.is_some() || self.forbidden_output.is_some()
+    }
+
+    /// Checks the output of a build or command against the criteria.
+    pub fn check_output(&self, output: &str) -> Result<()> {
+        if let Some(pattern) = self.required_output.as_deref() {
+            if !Regex::new(pattern)?.is_match(output) {
+                bail!("Output did not contain the required pattern: {}", pattern);
+            }
+        }
+
+        if let Some(pattern) = self.forbidden_output.as_deref() {
+            if Regex::new(pattern)?.is_match(output) {
+                bail!("Output contained the forbidden pattern: {}", pattern);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that a built binary exists and was written after the build started, if required.
+    pub fn check_binary(&self, path: &Path, build_started: SystemTime) -> Result<()> {
+        if !self.fresh_binaries {
+            return Ok(());
+        }
+
+        let modified = match std::fs::metadata(path) {
+            Ok(metadata) => metadata.modified()?,
+            Err(_) => bail!("Build did not produce the expected binary at {:?}", path),
+        };
+
+        if modified < build_started {
+            bail!(
+                "Build did not update the binary at {:?}, so it would restart an old version",
+                path
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Checks the output of `supervisorctl status` against the required status, if there is one.
+    pub fn check_supervisor_status(&self, output: &str) -> Result<()> {
+        if let Some(status) = self.supervisor_status.as_deref() {
+            if !output.split_whitespace().any(|word| word == status) {
+                bail!(
+                    "Process did not reach the {} status: {}",
+                    status,
+                    output.trim()
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Represents the branches a repository follows, either a single one or a priority list.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Follow {
+    Branch(String),
+    Branches(Vec<String>),
+}
+
+impl Follow {
+    /// Gets the followed branches in order of priority.
+    pub fn branches(&self) -> Vec<&str> {
+        match self {
+            Self::Branch(branch) => vec![branch.as_str()],
+            Self::Branches(branches) => branches.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+/// How fetched changes are applied to the local checkout of a repository.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    /// The fetched commit is merged into the local branch
+    #[default]
+    Merge,
+    /// The local branch is reset to exactly match the fetched commit
+    Reset,
+}
+
+/// How the followed branches of a repository are compared against the branch that was pushed to.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    /// The branch must be exactly the same as a followed branch
+    #[default]
+    Exact,
+    /// The branch must match a followed glob pattern, such as `release/*`
+    Glob,
+    /// The whole branch must match a followed regular expression, such as `release/\d+`
+    Regex,
+}
+
+impl MatchMode {
+    /// Checks whether a branch matches a followed branch or pattern.
+    ///
+    /// Invalid patterns never match.
+    pub fn matches(self, pattern: &str, branch: &str) -> bool {
+        match self {
+            Self::Exact => pattern == branch,
+            Self::Glob => glob::Pattern::new(pattern).is_ok_and(|p| p.matches(branch)),
+            Self::Regex => {
+                Regex::new(&format!("^(?:{})$", pattern)).is_ok_and(|r| r.is_match(branch))
+            }
+        }
+    }
+
+    /// Checks whether a followed branch only matches a branch with exactly the same name, rather
+    /// than being a pattern.
+    pub fn is_literal(self, pattern: &str) -> bool {
+        match self {
+            Self::Exact => true,
+            Self::Glob => glob::Pattern::escape(pattern) == pattern,
+            Self::Regex => !pattern.contains(|c| "\\.+*?()|[]{}^$".contains(c)),
+        }
+    }
+}
+
 /// Represents the configuration for Discord notifications
 #[derive(Debug, Deserialize)]
 pub struct DiscordConfig {
@@ -41,23 +348,169 @@ pub struct DiscordConfig {
     pub token: String,
     /// The channel identifier to send messages to
     pub channel_id: u64,
+    /// The number of times to retry sending a message after a transient failure
+    pub retries: Option<u32>,
+    /// The channel identifiers to send messages to for deploys of specific branches
+    pub branch_channels: Option<HashMap<String, u64>>,
+    /// The milliseconds to wait for further messages to a channel before sending them together
+    pub batch_window_ms: Option<u64>,
+    /// The most random milliseconds added to the batching window
+    pub batch_jitter_ms: Option<u64>,
+    /// The number of seconds to wait for a response from Discord before giving up
+    pub timeout_secs: Option<u64>,
+}
+
+impl DiscordConfig {
+    /// Gets how long to wait before sending a batch of messages, if they should be batched.
+    ///
+    /// A random jitter of up to `batch_jitter_ms` is added to the window, so that batches to
+    /// different channels are less likely to be sent at the same moment.
+    pub fn batch_delay(&self) -> Option<Duration> {
+        let window = self.batch_window_ms?;
+        let jitter = self.batch_jitter_ms.unwrap_or(0);
+
+        let random = rand::thread_rng().gen_range(0..=jitter);
+
+        Some(Duration::from_millis(window + random))
+    }
+
+    /// Resolves the channel to send messages about a deploy of the given branch to.
+    ///
+    /// If the branch has its own channel, that will be used, otherwise the default channel will be
+    /// used.
+    pub fn resolve_channel_id(&self, branch: Option<&str>) -> ChannelId {
+        let specific = branch.and_then(|branch| {
+            self.branch_channels
+                .as_ref()
+                .and_then(|channels| channels.get(branch))
+        });
+
+        ChannelId(*specific.unwrap_or(&self.channel_id))
+    }
+}
+
+/// Represents the configuration for the GitHub API
+#[derive(Debug, Deserialize)]
+pub struct GitHubConfig {
+    /// The token to authenticate with
+    pub token: Redacted,
+    /// The base URL of the API, defaulting to `https://api.github.com`
+    pub api_url: Option<String>,
+    /// The context to post commit statuses under, defaulting to `fisherman`
+    pub status_context: Option<String>,
+}
+
+/// A sensitive value that is hidden when the config is logged.
+#[derive(Deserialize)]
+#[serde(transparent)]
+pub struct Redacted(String);
+
+impl Redacted {
+    /// Gets the value itself, which should not be logged.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Redacted {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<redacted>")
+    }
 }
 
 /// Represents the available options that can be configured.
 #[derive(Debug, Deserialize)]
 pub struct Options {
+    /// The address to listen for messages on, defaulting to `127.0.0.1` if not specified
+    pub bind_address: Option<IpAddr>,
+    /// How the directory a repository is cloned to is named, defaulting to `name`
+    pub path_layout: Option<String>,
     /// The port to listen for messages on, defaulting to 5000 if not specified
     pub port: Option<u16>,
-    /// The path to the SSH private key to use for authentication
-    pub ssh_private_key: PathBuf,
+    /// The number of threads handling HTTP requests, defaulting to the number of CPUs
+    pub http_workers: Option<usize>,
+    /// The number of seconds to keep idle connections open, defaulting to 5
+    pub keep_alive_secs: Option<u64>,
+    /// The milliseconds a client has to send the headers of a request, defaulting to 5000
+    pub client_request_timeout_ms: Option<u64>,
+    /// The number of concurrent connections each worker accepts, defaulting to 25000
+    pub max_connections: Option<usize>,
+    /// The number of webhooks that can wait to be processed, defaulting to 100
+    pub queue_capacity: Option<usize>,
+    /// The milliseconds to wait for space in a full queue before accepting a webhook anyway and
+    /// queueing it in the background, rejecting it straight away if unset
+    pub queue_timeout_ms: Option<u64>,
+    /// The path to the SSH private key to use for authentication, relying on `ssh-agent` if unset
+    pub ssh_private_key: Option<PathBuf>,
+    /// The passphrase the SSH private key is encrypted with, if it is
+    pub ssh_private_key_passphrase: Option<Redacted>,
     /// The path that contains the repositories
     pub repo_root: PathBuf,
     /// The path to find `cargo` at
     pub cargo_path: PathBuf,
+    /// Whether builds should use `cargo` without network access, defaulting to `false`
+    pub cargo_offline: Option<bool>,
+    /// The `CARGO_HOME` for builds, such as one configured with a registry mirror
+    pub cargo_home: Option<PathBuf>,
+    /// How fetched changes are applied to the checkout, defaulting to merging them
+    pub strategy: Option<Strategy>,
+    /// The command that restarts each program, defaulting to `supervisorctl restart {binary}`
+    pub restart_command: Option<Command>,
+    /// The command that reports the status of each program, defaulting to
+    /// `supervisorctl status {binary}`
+    pub status_command: Option<Command>,
     /// The secret to use for validating payloads
     pub secret: Option<String>,
+    /// The shortest secret that isn't reported as a mistake, defaulting to 16 characters
+    pub min_secret_length: Option<usize>,
     /// The configuration to use for Discord notifications
     pub discord: Option<DiscordConfig>,
+    /// The configuration to use for the GitHub API
+    pub github: Option<GitHubConfig>,
+    /// The default logging filter to use if `RUST_LOG` is not set
+    pub log_filter: Option<String>,
+    /// The OpenTelemetry collector to export spans to over OTLP/HTTP, if any
+    pub otlp_endpoint: Option<String>,
+    /// The minimum amount of free disk space in megabytes required before building
+    pub min_free_disk_mb: Option<u64>,
+    /// The number of repositories that can be fetched at once, defaulting to no limit
+    pub max_concurrent_fetches: Option<usize>,
+    /// The number of repositories that can be fetched from the same host at once, defaulting to
+    /// no limit
+    pub max_connections_per_host: Option<usize>,
+    /// The number of seconds any spawned command may run for before being killed
+    pub command_timeout_secs: Option<u64>,
+    /// The bearer token required by administrative endpoints, which are disabled if unset
+    pub admin_token: Option<Redacted>,
+    /// The most events returned by a single request to `/events`, defaulting to 100
+    pub max_events_per_page: Option<usize>,
+    /// The hosts outbound notifications may be sent to, allowing any if unset
+    pub allowed_notify_hosts: Option<Vec<String>>,
+    /// Whether to reject webhooks for repositories without their own secret
+    pub require_specific_secrets: Option<bool>,
+    /// The author of pushes to ignore, such as a bot that pushes from deploy commands
+    pub ignore_author: Option<String>,
+    /// The key identifying deploys that must not run at the same time, defaulting to `{repo}`
+    pub deploy_key_template: Option<String>,
+    /// The file to write the result of each deploy to, where `{repo}` is the repository name
+    pub result_file: Option<String>,
+    /// Whether to refuse to start if there are potential mistakes in the config
+    pub strict: Option<bool>,
+    /// Whether reconciling notifies of each failed repository as well as sending a summary
+    pub notify_reconcile_failures: Option<bool>,
+    /// Whether to start even if another instance is using the same `repo_root`
+    pub allow_multiple_instances: Option<bool>,
+    /// The proxy to send outbound git and HTTP traffic through, overriding the environment
+    pub proxy_url: Option<String>,
+    /// The full name of the repository containing `fisherman` itself, which will replace the
+    /// running process with the newly built binary after a successful deploy
+    pub self_repository: Option<String>,
 }
 
 /// Components of a command to be run after restarting binaries.
@@ -78,316 +531,3004 @@ pub struct SpecificOptions {
     pub code_root: Option<PathBuf>,
     /// The names of the binaries
     pub binaries: Option<Vec<String>>,
+    /// The name of the `supervisor` program for each binary, with `{binary}` as a placeholder
+    pub supervisor_name_template: Option<String>,
+    /// The `supervisor` programs to restart for each binary, if any run several processes
+    pub supervisor_programs: Option<HashMap<String, Vec<String>>>,
+    /// The command that restarts each program, with `{binary}` as a placeholder
+    pub restart_command: Option<Command>,
+    /// The command that reports the status of each program, with `{binary}` as a placeholder
+    pub status_command: Option<Command>,
     /// The secret to use for validating payloads
     pub secret: Option<String>,
-    /// The branch to follow for this repository
-    pub follow: Option<String>,
+    /// The algorithm used to sign payloads with the secret
+    pub signature_algorithm: Option<SignatureAlgorithm>,
+    /// The branch to follow for this repository, or a list of them in priority order
+    pub follow: Option<Follow>,
+    /// How the followed branches are compared against pushed branches, defaulting to exactly
+    pub match_mode: Option<MatchMode>,
+    /// A file on the default branch of the repository containing the branch to follow instead
+    pub follow_file: Option<PathBuf>,
+    /// Glob patterns for branches that should never be deployed
+    pub ignore_branches: Option<Vec<String>>,
+    /// The author of pushes to ignore, such as a bot that pushes from deploy commands
+    pub ignore_author: Option<String>,
+    /// The key identifying deploys that must not run at the same time
+    pub deploy_key_template: Option<String>,
+    /// The file to write the result of each deploy to
+    pub result_file: Option<String>,
+    /// The kinds of webhook that are handled for the repository
+    pub allowed_events: Option<Vec<WebhookVariant>>,
+    /// The commands to execute before pulling any changes
+    pub pre_pull_commands: Option<Commands>,
     /// The commands to execute before processing
     pub precommands: Option<Commands>,
     /// Whether to build binaries with `cargo`.
     pub should_build_binaries: Option<bool>,
+    /// The commands to build the repository with in its `code_root`, instead of `cargo`
+    pub build_commands: Option<Commands>,
+    /// The stages of a deploy to run, superseding `should_build_binaries`
+    pub stages: Option<Vec<Stage>>,
+    /// Whether to remove untracked and ignored files before building
+    pub clean_build: Option<bool>,
+    /// Whether to keep building and restarting the other binaries when one of them fails
+    pub continue_on_binary_failure: Option<bool>,
     /// The commands to execute at the end of processing
     pub commands: Option<Commands>,
+    /// The commands to execute for every push, regardless of the branch
+    pub always_commands: Option<Commands>,
+    /// The commands to execute when handling a webhook fails
+    pub on_failure: Option<Commands>,
+    /// Whether to process webhooks before responding to them
+    pub synchronous: Option<bool>,
+    /// The number of seconds to wait for a synchronous deploy before responding anyway
+    pub synchronous_timeout_secs: Option<u64>,
+    /// Whether builds should use `cargo` without network access
+    pub cargo_offline: Option<bool>,
+    /// The `CARGO_HOME` for builds, such as one configured with a registry mirror
+    pub cargo_home: Option<PathBuf>,
+    /// The features to enable when building with `cargo`
+    pub cargo_features: Option<Vec<String>>,
+    /// The profile to build with, instead of `release`
+    pub cargo_profile: Option<String>,
+    /// The number of commits to clone and fetch, rather than the full history
+    pub clone_depth: Option<u32>,
+    /// How fetched changes are applied to the checkout
+    pub strategy: Option<Strategy>,
+    /// Other configuration keys to deploy with, selected by a `Deploy-To` commit trailer
+    pub targets: Option<HashMap<String, String>>,
+    /// A file that must exist in the repository after pulling for it to be deployed
+    pub require_file: Option<PathBuf>,
+    /// The user to run builds and commands as, through `sudo`
+    pub run_as: Option<String>,
+    /// The number of seconds to wait for further pushes before deploying
+    pub coalesce_window_secs: Option<u64>,
+    /// The number of seconds a deploy may take before it is cancelled and reported as failed
+    pub deploy_timeout_secs: Option<u64>,
+    /// The branches to deploy when a `repository_dispatch` with a given action is received
+    pub dispatch: Option<HashMap<String, String>>,
+    /// Whether to include the commit message in notifications
+    pub include_commit_message: Option<bool>,
+    /// Whether to report deploys through GitHub's Deployments API
+    pub github_deployments: Option<bool>,
+    /// Additional checks for deciding whether a deploy succeeded
+    pub success_criteria: Option<SuccessCriteria>,
+    /// The commit statuses that must pass on GitHub before deploying
+    pub required_checks: Option<RequiredChecks>,
+    /// How to confirm restarted programs are stable, if they should be checked
+    pub verify_restart: Option<VerifyRestart>,
 }
 
 impl SpecificOptions {
-    /// Checks whether there are any likely mistakes in the config.
-    pub fn check_for_potential_mistakes(&self, key: &str) {
+    /// Finds any likely mistakes in the config for the repository with the given key.
+    pub fn find_potential_mistakes(&self, key: &str) -> Vec<Mistake> {
+        let mut mistakes = Vec::new();
+        let mut mistake = |message: String| mistakes.push(Mistake::new(Some(key), message));
+
         if matches!(self.code_root.as_ref(), Some(path) if path.is_absolute()) {
-            tracing::warn!(?self.code_root, %key, "`code_root` values should be relative, encountered an absolute one");
+            mistake(format!(
+                "`code_root` values should be relative, encountered {:?}",
+                self.code_root
+            ));
+        }
+
+        let match_mode = self.match_mode.unwrap_or_default();
+
+        for pattern in self.follow.iter().flat_map(Follow::branches) {
+            let error = match match_mode {
+                MatchMode::Exact => None,
+                MatchMode::Glob => glob::Pattern::new(pattern).err().map(|e| e.to_string()),
+                MatchMode::Regex => Regex::new(pattern).err().map(|e| e.to_string()),
+            };
+
+            if let Some(e) = error {
+                mistake(format!(
+                    "`follow` contains an invalid pattern `{}`, it will never match: {}",
+                    pattern, e
+                ));
+            }
+        }
+
+        for pattern in self.ignore_branches.iter().flatten() {
+            if let Err(e) = glob::Pattern::new(pattern) {
+                mistake(format!(
+                    "`ignore_branches` contains an invalid pattern `{}`, it will never match: {}",
+                    pattern, e
+                ));
+            }
+        }
+
+        if let Some(criteria) = self.success_criteria.as_ref() {
+            let patterns = [&criteria.required_output, &criteria.forbidden_output];
+
+            for pattern in patterns.into_iter().flatten() {
+                if let Err(e) = Regex::new(pattern) {
+                    mistake(format!(
+                        "`success_criteria` contains an invalid pattern `{}`, deploys will fail: {}",
+                        pattern, e
+                    ));
+                }
+            }
+        }
+
+        let commands = [
+            ("pre_pull_commands", &self.pre_pull_commands),
+            ("precommands", &self.precommands),
+            ("build_commands", &self.build_commands),
+            ("commands", &self.commands),
+            ("always_commands", &self.always_commands),
+            ("on_failure", &self.on_failure),
+        ];
+
+        for (field, commands) in commands {
+            for command in commands.iter().flat_map(|c| &c.0) {
+                if !program_exists(&command.program) {
+                    mistake(format!(
+                        "`{}` program `{}` could not be found, it will fail when run",
+                        field, command.program
+                    ));
+                }
+            }
+        }
+
+        mistakes
+    }
+}
+
+/// Renders a command run for each program of a repository, defaulting to the `supervisorctl`
+/// subcommand with the given name.
+fn render_program_command(
+    command: Option<&Command>,
+    subcommand: &str,
+    program: &str,
+) -> (String, Vec<String>) {
+    let (executable, args) = match command {
+        Some(command) => (
+            command.program.clone(),
+            command.args.clone().unwrap_or_default(),
+        ),
+        None => (
+            String::from("supervisorctl"),
+            vec![subcommand.to_owned(), String::from("{binary}")],
+        ),
+    };
+
+    let args = args
+        .iter()
+        .map(|arg| arg.replace("{binary}", program))
+        .collect();
+
+    (executable, args)
+}
+
+/// A likely mistake in the config.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Mistake {
+    /// The repository the mistake was found in, or nothing if it was in the `default` section
+    pub key: Option<String>,
+    /// A description of the mistake and its consequences
+    pub message: String,
+}
+
+impl Mistake {
+    fn new(key: Option<&str>, message: String) -> Self {
+        Self {
+            key: key.map(String::from),
+            message,
         }
     }
 }
 
+/// Checks whether a program can be found, either at its path or in one of the `PATH` directories.
+///
+/// Relative paths containing directories depend on the working directory of the command, so they
+/// are assumed to exist.
+fn program_exists(program: &str) -> bool {
+    let path = Path::new(program);
+
+    if path.is_absolute() {
+        return path.is_file();
+    }
+
+    if path.components().count() > 1 {
+        return true;
+    }
+
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+/// Upgrades a config from one version of the schema to the next, where the migration at index `i`
+/// upgrades from version `i + 1` to `i + 2`.
+type Migration = fn(&mut serde_yaml::Value);
+
+/// The migrations applied to older configs as they are parsed, so that they keep working after the
+/// schema changes.
+const MIGRATIONS: &[Migration] = &[];
+
+/// The version of the config schema, which configs without a `version` are assumed to be.
+pub const CURRENT_VERSION: u64 = MIGRATIONS.len() as u64 + 1;
+
+/// Upgrades a parsed config to the latest version of the schema the migrations lead to, failing
+/// if it is from a newer version of fisherman or the version is invalid.
+///
+/// Returns whether any migrations were applied.
+fn migrate(
+    value: &mut serde_yaml::Value,
+    migrations: &[Migration],
+) -> Result<bool, serde_yaml::Error> {
+    let current = migrations.len() as u64 + 1;
+
+    let version = match value.get("version") {
+        None => current,
+        Some(version) => match version.as_u64() {
+            Some(version) if version > 0 => version,
+            _ => {
+                return Err(serde::de::Error::custom(
+                    "the config `version` must be a positive integer",
+                ))
+            }
+        },
+    };
+
+    if version > current {
+        return Err(serde::de::Error::custom(format!(
+            "the config is version {version}, but this version of fisherman only supports up to \
+             version {current}, upgrade fisherman or use an older config"
+        )));
+    }
+
+    if version == current {
+        return Ok(false);
+    }
+
+    for migration in &migrations[version as usize - 1..] {
+        migration(value);
+    }
+
+    if let Some(mapping) = value.as_mapping_mut() {
+        mapping.insert("version".into(), current.into());
+    }
+
+    Ok(true)
+}
+
+/// Gets the current version of the config schema, for configs without a `version`.
+fn current_version() -> u64 {
+    CURRENT_VERSION
+}
+
 /// Represents the structure of the configuration file.
 #[derive(Debug, Deserialize)]
 pub struct Config {
+    /// The version of the schema the config was written for, upgraded to the current version as it
+    /// is parsed
+    #[serde(default = "current_version")]
+    pub version: u64,
     pub default: Options,
     pub specific: Option<HashMap<String, SpecificOptions>>,
+    /// The previous names of renamed repositories, keyed by their new names
+    pub aliases: Option<HashMap<String, String>>,
+    /// The output of deploys, for anyone watching them live
+    #[serde(skip)]
+    pub logs: LogStream,
+    /// The stages of the most recent deploys, for anyone checking what was done
+    #[serde(skip)]
+    pub stages: StageLog,
+    /// The notifications waiting to be sent together
+    #[serde(skip)]
+    pub notifications: MessageBatches,
+    /// The requests currently being handled, which are waited for before the process replaces
+    /// itself
+    #[serde(skip)]
+    pub in_flight: InFlight,
+    /// Limits the number of concurrent fetches, created when first needed
+    #[serde(skip)]
+    fetches: OnceLock<Semaphore>,
+    /// Limits the number of concurrent fetches from each host, keyed by the host
+    #[serde(skip)]
+    host_fetches: Mutex<HashMap<String, Arc<Semaphore>>>,
+    /// The locks held by running deploys, keyed by their deploy key
+    #[serde(skip)]
+    deploys: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    /// The client for sending Discord messages, created when first needed
+    #[serde(skip)]
+    discord_client: OnceLock<Arc<Http>>,
 }
 
 impl Config {
+    /// Resolves the name a repository is configured and stored under.
+    ///
+    /// If the repository has been renamed and has an alias to its previous name, the previous
+    /// name will be used, unless there is also specific configuration under the new name.
+    pub fn resolve_alias<'a>(&'a self, repository: &'a str) -> &'a str {
+        let configured = self
+            .specific
+            .as_ref()
+            .is_some_and(|s| s.contains_key(repository));
+
+        if configured {
+            return repository;
+        }
+
+        self.aliases
+            .as_ref()
+            .and_then(|aliases| aliases.get(repository))
+            .map_or(repository, String::as_str)
+    }
+
     /// Gets a specific configuration for a repository if it exists.
-    fn get_specific_config(&self, repository: &str) -> Option<&SpecificOptions> {
+    pub(crate) fn get_specific_config(&self, repository: &str) -> Option<&SpecificOptions> {
+        let repository = self.resolve_alias(repository);
+
         self.specific.as_ref().and_then(|s| s.get(repository))
     }
 
-    /// Checks whether there are any likely mistakes in the config.
-    pub fn check_for_potential_mistakes(&self) {
+    /// Resolves the path a repository is cloned to within the `repo_root`.
+    ///
+    /// The directory is named by the `path_layout`, which is either `name` (the default), where
+    /// repositories with the same name but different owners share a directory, `full_name`, where
+    /// they are nested under a directory for their owner, or a template using `{owner}`, `{name}`
+    /// and `{repo}` as placeholders.
+    pub fn resolve_repository_path(&self, repository: &str) -> PathBuf {
+        let repository = self.resolve_alias(repository);
+        let (owner, name) = repository.split_once('/').unwrap_or(("", repository));
+
+        let directory = match self.default.path_layout.as_deref().unwrap_or("name") {
+            "name" => name.to_owned(),
+            "full_name" => repository.to_owned(),
+            template => template
+                .replace("{owner}", owner)
+                .replace("{name}", name)
+                .replace("{repo}", repository),
+        };
+
+        self.default.repo_root.join(directory)
+    }
+
+    /// Finds any likely mistakes in the config, such as paths that do not exist.
+    pub fn find_potential_mistakes(&self) -> Vec<Mistake> {
         let default = &self.default;
+        let mut mistakes = Vec::new();
 
         // Check the key, root and Cargo binary exist
-        if !default.ssh_private_key.is_file() {
-            tracing::warn!(?default.ssh_private_key, "`ssh_private_key` either does not exist or is not a file");
+        if let Some(key) = default
+            .ssh_private_key
+            .as_ref()
+            .filter(|key| !key.is_file())
+        {
+            mistakes.push(Mistake::new(
+                None,
+                format!(
+                    "`ssh_private_key` {:?} either does not exist or is not a file",
+                    key
+                ),
+            ));
         }
 
         if !default.repo_root.is_dir() {
-            tracing::warn!(?default.repo_root, "`repo_root` either does not exist or is not a directory");
+            mistakes.push(Mistake::new(
+                None,
+                format!(
+                    "`repo_root` {:?} either does not exist or is not a directory",
+                    default.repo_root
+                ),
+            ));
         }
 
         if !default.cargo_path.is_file() {
-            tracing::warn!(?default.cargo_path, "`cargo_path` either does not exist or is not a file");
+            mistakes.push(Mistake::new(
+                None,
+                format!(
+                    "`cargo_path` {:?} either does not exist or is not a file",
+                    default.cargo_path
+                ),
+            ));
+        }
+
+        // Templates without placeholders, such as a misspelled layout, put every repository in the
+        // same directory
+        if let Some(layout) = default
+            .path_layout
+            .as_deref()
+            .filter(|layout| !matches!(*layout, "name" | "full_name"))
+        {
+            if !["{owner}", "{name}", "{repo}"]
+                .iter()
+                .any(|placeholder| layout.contains(placeholder))
+            {
+                mistakes.push(Mistake::new(
+                    None,
+                    format!(
+                        "`path_layout` {:?} is not `name` or `full_name` and has no `{{owner}}`, `{{name}}` or `{{repo}}` placeholder, every repository will be cloned to the same directory",
+                        layout
+                    ),
+                ));
+            }
+        }
+
+        if let Err(e) = self.http_client_builder() {
+            mistakes.push(Mistake::new(
+                None,
+                format!(
+                    "`proxy_url` is invalid, notifications and spans will not be sent: {}",
+                    e
+                ),
+            ));
         }
 
         if let Some(specific) = self.specific.as_ref() {
             for (key, options) in specific {
-                options.check_for_potential_mistakes(key);
+                mistakes.extend(options.find_potential_mistakes(key));
             }
         }
-    }
 
-    /// Creates a new client and gets the channel identifier from the config, if it exists.
-    pub fn get_client_and_channel_id(&self) -> Option<(Http, ChannelId)> {
-        let discord = self.default.discord.as_ref()?;
+        // Check secrets are strong enough to stop payloads being forged
+        let secrets = std::iter::once((None, default.secret.as_deref())).chain(
+            self.specific
+                .iter()
+                .flatten()
+                .map(|(key, options)| (Some(key.as_str()), options.secret.as_deref())),
+        );
 
-        // Create a new instance of the client
-        let client = Http::new(&discord.token);
-        let channel_id = ChannelId(discord.channel_id);
+        for (key, secret) in secrets {
+            let weakness = secret.and_then(|secret| self.find_secret_weakness(secret));
 
-        Some((client, channel_id))
-    }
+            if let Some(weakness) = weakness {
+                mistakes.push(Mistake::new(key, weakness));
+            }
+        }
 
-    /// Checks whether this repository should be built with `cargo`.
-    pub fn should_build_binaries(&self, repository: &str) -> bool {
-        self.get_specific_config(repository)
-            .and_then(|s| s.should_build_binaries)
-            .unwrap_or(true)
-    }
+        // Restarts go through `supervisor` by default for any repository that builds binaries
+        let restarted: Vec<_> = self
+            .specific
+            .iter()
+            .flatten()
+            .map(|(key, _)| key.as_str())
+            .filter(|key| {
+                self.should_run_stage(key, Stage::Restart) && !self.is_self_repository(key)
+            })
+            .collect();
+
+        let checked = restarted.iter().filter(|key| self.checks_status(key));
+
+        let mut restart_programs: Vec<_> = restarted
+            .iter()
+            .map(|key| self.resolve_restart_command(key, "").0)
+            .chain(
+                checked
+                    .clone()
+                    .map(|key| self.resolve_status_command(key, "").0),
+            )
+            .collect();
+
+        // Statuses come from `supervisorctl` unless told otherwise, even if restarts do not
+        for key in checked {
+            if !self.has_supervisor_restarts(key) && self.has_supervisor_status(key) {
+                mistakes.push(Mistake::new(
+                    Some(key),
+                    String::from(
+                        "`restart_command` is set without `status_command`, so `verify_restart` and `supervisor_status` will check `supervisorctl status` instead",
+                    ),
+                ));
+            }
+        }
 
-    /// Resolves the value of the `code_root` directive.
-    ///
-    /// If a specific value exists for the given repository, that will be used, otherwise the root
-    /// directory of the project will be used, as denoted by an empty [`PathBuf`].
-    pub fn resolve_code_root(&self, repository: &str) -> PathBuf {
-        self.get_specific_config(repository)
-            .and_then(|s| s.code_root.clone())
-            .unwrap_or_default()
-    }
+        restart_programs.sort_unstable();
+        restart_programs.dedup();
 
-    /// Resolves the value of the `binaries` directive.
-    ///
-    /// If a specific value exists for the given repository, that will be used, otherwise the name
-    /// of the repository itself will be used.
-    pub fn resolve_binaries(&self, repository: &str) -> Vec<String> {
-        self.get_specific_config(repository)
-            .and_then(|s| s.binaries.clone())
-            .unwrap_or_else(|| vec![String::from(repository.split('/').nth(1).unwrap())])
+        for program in restart_programs {
+            if !program_exists(&program) {
+                mistakes.push(Mistake::new(
+                    None,
+                    format!("`{}` could not be found, restarts will fail", program),
+                ));
+            }
+        }
+
+        // Checking commit statuses requires access to the GitHub API
+        if default.github.is_none() {
+            for (key, options) in self.specific.iter().flatten() {
+                if options.required_checks.is_some() {
+                    mistakes.push(Mistake::new(
+                        Some(key),
+                        String::from(
+                            "`required_checks` are configured without `github`, pushes will not be deployed",
+                        ),
+                    ));
+                }
+            }
+        }
+
+        mistakes
     }
 
-    /// Resolves the value of the `secret` directive.
+    /// Describes why a secret is too weak to rely on, if it is.
     ///
-    /// If a specific value exists for the given repository, that will be used, otherwise no secret
-    /// will be used (as webhooks do not have to have this).
-    pub fn resolve_secret(&self, repository: &str) -> Option<&str> {
-        self.get_specific_config(repository)
-            .and_then(|s| s.secret.as_deref())
-            .or(self.default.secret.as_deref())
+    /// Secrets are weak if they are shorter than `min_secret_length` or look like a placeholder
+    /// that was copied from an example, such as `"<some secret value>"`.
+    fn find_secret_weakness(&self, secret: &str) -> Option<String> {
+        let lowercase = secret.to_lowercase();
+        let placeholders = ["<", ">", "secret", "changeme"];
+
+        if placeholders.iter().any(|p| lowercase.contains(p)) {
+            return Some(String::from(
+                "`secret` looks like a placeholder, payloads could be forged",
+            ));
+        }
+
+        let min_length = self.default.min_secret_length.unwrap_or(16);
+
+        if secret.chars().count() < min_length {
+            return Some(format!(
+                "`secret` is shorter than {} characters, payloads could be forged",
+                min_length
+            ));
+        }
+
+        None
     }
 
-    /// Resolves the value of the `follow` directive.
+    /// Checks whether there are any likely mistakes in the config, logging a warning for each.
     ///
-    /// If a specific value exists for the given repository, that will be used, otherwise the
-    /// `master` branch will be used.
-    pub fn resolve_follow_branch(&self, repository: &str) -> &str {
-        let specific = self
-            .get_specific_config(repository)
-            .and_then(|s| s.follow.as_deref());
+    /// Returns the number of potential mistakes that were found.
+    pub fn check_for_potential_mistakes(&self) -> usize {
+        let mistakes = self.find_potential_mistakes();
+
+        for mistake in &mistakes {
+            match mistake.key.as_deref() {
+                Some(key) => tracing::warn!(%key, "{}", mistake.message),
+                None => tracing::warn!("{}", mistake.message),
+            }
+        }
 
-        specific.unwrap_or("master")
+        mistakes.len()
     }
 
-    /// Resolves the value of the `precommands` directive.
-    ///
-    /// If a specific value exists, it will be returned, otherwise nothing will be returned.
-    pub fn resolve_precommands(&self, repository: &str) -> Option<&Commands> {
-        self.get_specific_config(repository)
-            .and_then(|s| s.precommands.as_ref())
+    /// Checks whether potential mistakes in the config should prevent `fisherman` from starting.
+    pub fn is_strict(&self) -> bool {
+        self.default.strict.unwrap_or(false)
     }
 
-    /// Resolves the value of the `commands` directive.
-    ///
-    /// If a specific value exists, it will be returned, otherwise nothing will be returned.
-    pub fn resolve_commands(&self, repository: &str) -> Option<&Commands> {
-        self.get_specific_config(repository)
-            .and_then(|s| s.commands.as_ref())
+    /// Checks whether several instances may share the same `repo_root`, defaulting to `false`.
+    pub fn allows_multiple_instances(&self) -> bool {
+        self.default.allow_multiple_instances.unwrap_or(false)
     }
-}
 
-impl FromStr for Config {
-    type Err = serde_yaml::Error;
+    /// Gets the passphrase of the SSH private key, if it is encrypted.
+    pub fn ssh_private_key_passphrase(&self) -> Option<&str> {
+        self.default
+            .ssh_private_key_passphrase
+            .as_ref()
+            .map(Redacted::as_str)
+    }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        serde_yaml::from_str(s)
+    /// Gets the bearer token required by administrative endpoints, if they are enabled.
+    pub fn admin_token(&self) -> Option<&str> {
+        self.default.admin_token.as_ref().map(Redacted::as_str)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::path::PathBuf;
-    use std::str::FromStr;
+    /// Gets the most events returned by a single request to `/events`, defaulting to 100.
+    pub fn max_events_per_page(&self) -> usize {
+        self.default.max_events_per_page.unwrap_or(100)
+    }
 
-    use crate::config::Config;
+    /// Gets the client for Discord and the channel identifier for a branch from the config, if it
+    /// exists.
+    ///
+    /// The client is created the first time it is needed and reused for later messages. If it
+    /// cannot be created, it will be tried again for the next message.
+    pub fn get_client_and_channel_id(
+        &self,
+        branch: Option<&str>,
+    ) -> Option<(Arc<Http>, ChannelId)> {
+        let discord = self.default.discord.as_ref()?;
+        let channel_id = discord.resolve_channel_id(branch);
 
-    static CONFIG: &str = r#"
-default:
-    ssh_private_key: "/root/.ssh/id_rsa"
-    repo_root: "/root"
-    cargo_path: "/root/.cargo/bin/cargo"
+        if let Some(client) = self.discord_client.get() {
+            return Some((Arc::clone(client), channel_id));
+        }
 
-specific:
-    FreddieBrown/dodona:
-        follow: "develop"
+        // Create a new instance of the client, using the proxy if there is one
+        let mut builder = match self.http_client_builder() {
+            Ok(builder) => builder,
+            Err(e) => {
+                tracing::error!(%e, "Failed to create a client for Discord");
+                return None;
+            }
+        };
+
+        if let Some(timeout) = discord.timeout_secs {
+            builder = builder.timeout(Duration::from_secs(timeout));
+        }
+
+        let client = match builder.build() {
+            Ok(client) => HttpBuilder::new(&discord.token).client(client).build(),
+            Err(e) => {
+                tracing::error!(%e, "Failed to create a client for Discord");
+                return None;
+            }
+        };
+
+        let client = self.discord_client.get_or_init(|| Arc::new(client));
+
+        Some((Arc::clone(client), channel_id))
+    }
+
+    /// Gets the socket address to listen for messages on, defaulting to port 5000 on `127.0.0.1`.
+    pub fn socket_address(&self) -> SocketAddr {
+        let address = self
+            .default
+            .bind_address
+            .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        SocketAddr::new(address, self.default.port.unwrap_or(5000))
+    }
+
+    /// Gets the number of webhooks that can wait to be processed, defaulting to 100.
+    ///
+    /// A capacity of 0 is treated as 1, since webhooks could otherwise never be queued.
+    pub fn resolve_queue_capacity(&self) -> usize {
+        self.default.queue_capacity.unwrap_or(100).max(1)
+    }
+
+    /// Gets how long to wait for space in a full queue before queueing a webhook in the
+    /// background, if webhooks should not be rejected when the queue is full.
+    pub fn queue_timeout(&self) -> Option<Duration> {
+        self.default.queue_timeout_ms.map(Duration::from_millis)
+    }
+
+    /// Gets the maximum duration of any spawned command, if one is configured.
+    pub fn command_timeout(&self) -> Option<Duration> {
+        self.default.command_timeout_secs.map(Duration::from_secs)
+    }
+
+    /// Creates a builder for HTTP clients that routes requests through the configured proxy.
+    ///
+    /// If no proxy is configured, the standard `HTTP_PROXY` and `HTTPS_PROXY` environment
+    /// variables will be respected instead.
+    pub fn http_client_builder(&self) -> Result<reqwest::ClientBuilder> {
+        let builder = reqwest::Client::builder();
+
+        match self.default.proxy_url.as_deref() {
+            Some(url) => Ok(builder.proxy(reqwest::Proxy::all(url)?)),
+            None => Ok(builder),
+        }
+    }
+
+    /// Creates a new GitHub client from the config, if it exists.
+    pub fn get_github_client(&self) -> Option<GitHubClient> {
+        let github = self.default.github.as_ref()?;
+
+        let client = match self
+            .http_client_builder()
+            .and_then(|builder| GitHubClient::new(github, builder))
+        {
+            Ok(client) => client,
+            Err(e) => {
+                tracing::error!(%e, "Failed to create a client for the GitHub API");
+                return None;
+            }
+        };
+
+        if !self.is_notify_url_allowed(client.api_url()) {
+            tracing::error!(api_url = %client.api_url(), "Refusing to use a GitHub API whose host is not in `allowed_notify_hosts`");
+            return None;
+        }
+
+        Some(client)
+    }
+
+    /// Checks whether outbound notifications may be sent to the given URL.
+    ///
+    /// If no allowlist is configured, any host is allowed, as URLs only come from this config.
+    /// Otherwise, the host of the URL must be one of those listed.
+    pub fn is_notify_url_allowed(&self, url: &str) -> bool {
+        let allowed = match self.default.allowed_notify_hosts.as_ref() {
+            Some(allowed) => allowed,
+            None => return true,
+        };
+
+        reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_owned))
+            .is_some_and(|host| allowed.iter().any(|a| a.eq_ignore_ascii_case(&host)))
+    }
+
+    /// Checks whether deploys of this repository should be reported to GitHub.
+    pub fn should_create_github_deployments(&self, repository: &str) -> bool {
+        self.get_specific_config(repository)
+            .and_then(|s| s.github_deployments)
+            .unwrap_or(false)
+    }
+
+    /// Checks whether this repository should be built with `cargo`.
+    pub fn should_build_binaries(&self, repository: &str) -> bool {
+        self.get_specific_config(repository)
+            .and_then(|s| s.should_build_binaries)
+            .unwrap_or(true)
+    }
+
+    /// Checks whether a stage of deploys should be run for this repository.
+    ///
+    /// If a specific list of stages exists for the given repository, only those will be run,
+    /// otherwise every stage will be, except for restarting if binaries should not be built and
+    /// building if there are also no `build_commands`.
+    pub fn should_run_stage(&self, repository: &str, stage: Stage) -> bool {
+        if let Some(stages) = self
+            .get_specific_config(repository)
+            .and_then(|s| s.stages.as_ref())
+        {
+            return stages.contains(&stage);
+        }
+
+        match stage {
+            Stage::Build => {
+                self.resolve_build_commands(repository).is_some()
+                    || self.should_build_binaries(repository)
+            }
+            Stage::Restart => self.should_build_binaries(repository),
+            _ => true,
+        }
+    }
+
+    /// Checks whether notifications for this repository should include the commit message.
+    pub fn should_include_commit_message(&self, repository: &str) -> bool {
+        self.get_specific_config(repository)
+            .and_then(|s| s.include_commit_message)
+            .unwrap_or(true)
+    }
+
+    /// Checks whether a failure to build or restart one binary of this repository should leave
+    /// the others to be deployed, rather than failing the deploy straight away.
+    pub fn should_continue_on_binary_failure(&self, repository: &str) -> bool {
+        self.get_specific_config(repository)
+            .and_then(|s| s.continue_on_binary_failure)
+            .unwrap_or(false)
+    }
+
+    /// Checks whether this repository should be cleaned of untracked files before building.
+    pub fn should_clean_build(&self, repository: &str) -> bool {
+        self.get_specific_config(repository)
+            .and_then(|s| s.clean_build)
+            .unwrap_or(false)
+    }
+
+    /// Resolves the value of the `success_criteria` directive.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise success
+    /// is decided by exit codes alone.
+    pub fn resolve_success_criteria(&self, repository: &str) -> Option<&SuccessCriteria> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.success_criteria.as_ref())
+    }
+
+    /// Gets the commit statuses that must pass before deploying a repository, if there are any.
+    pub fn resolve_required_checks(&self, repository: &str) -> Option<&RequiredChecks> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.required_checks.as_ref())
+    }
+
+    /// Checks whether the status of the restarted programs of a repository is checked at all, by
+    /// either `verify_restart` or the `supervisor_status` of its success criteria.
+    fn checks_status(&self, repository: &str) -> bool {
+        self.resolve_verify_restart(repository).is_some()
+            || self
+                .resolve_success_criteria(repository)
+                .is_some_and(|criteria| criteria.supervisor_status.is_some())
+    }
+
+    /// Gets how to confirm the restarted programs of a repository are stable, if they should be.
+    pub fn resolve_verify_restart(&self, repository: &str) -> Option<&VerifyRestart> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.verify_restart.as_ref())
+    }
+
+    /// Creates a command to run a program for a repository.
+    ///
+    /// If the repository should be built as another user, the program will be run through `sudo`
+    /// without prompting for a password, otherwise it will be run directly.
+    pub fn create_command(
+        &self,
+        repository: &str,
+        program: impl AsRef<OsStr>,
+    ) -> tokio::process::Command {
+        let user = self
+            .get_specific_config(repository)
+            .and_then(|s| s.run_as.as_deref());
+
+        match user {
+            Some(user) => {
+                let mut command = tokio::process::Command::new("sudo");
+                command.args(["-n", "-u", user, "--"]).arg(program);
+                command
+            }
+            None => tokio::process::Command::new(program),
+        }
+    }
+
+    /// Runs a command for a repository, returning whether it succeeded.
+    ///
+    /// The output of the command is logged line by line and sent to anyone watching deploys live.
+    /// If the repository has criteria for the output of commands, it will be checked against them,
+    /// failing if they are not met.
+    pub async fn run_command(
+        &self,
+        repository: &str,
+        command: &mut tokio::process::Command,
+    ) -> Result<bool> {
+        let output = process::run_streaming(command, self.command_timeout(), |line| {
+            tracing::info!(%line, "Command output");
+            self.logs.send(repository, line);
+        })
+        .await?;
+
+        if let Some(criteria) = self
+            .resolve_success_criteria(repository)
+            .filter(|c| c.checks_output())
+        {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+
+            criteria.check_output(&format!("{stdout}{stderr}"))?;
+        }
+
+        Ok(output.status.success())
+    }
+
+    /// Checks whether this repository is the one containing `fisherman` itself.
+    pub fn is_self_repository(&self, repository: &str) -> bool {
+        self.default.self_repository.as_deref() == Some(repository)
+    }
+
+    /// Resolves the path to the newly built binary of `fisherman` after a deploy of its own
+    /// repository.
+    ///
+    /// If the repository is not the one containing `fisherman`, there is nothing to update and no
+    /// path will be returned, otherwise the first of its binaries in the release directory will be
+    /// used.
+    pub fn resolve_self_update_binary(&self, repository: &str) -> Option<PathBuf> {
+        if !self.is_self_repository(repository) {
+            return None;
+        }
+
+        let binary = self.resolve_binaries(repository).into_iter().next()?;
+
+        Some(self.resolve_binary_path(repository, &binary))
+    }
+
+    /// Resolves where a binary of a repository is written to by a build with its `cargo_profile`.
+    pub fn resolve_binary_path(&self, repository: &str, binary: &str) -> PathBuf {
+        // `cargo` writes the builtin profiles to directories of other names
+        let directory = match self.resolve_cargo_profile(repository) {
+            None | Some("release" | "bench") => "release",
+            Some("dev" | "test") => "debug",
+            Some(profile) => profile,
+        };
+
+        self.resolve_repository_path(repository)
+            .join(self.resolve_code_root(repository))
+            .join("target")
+            .join(directory)
+            .join(binary)
+    }
+
+    /// Checks whether webhooks for this repository should be processed before responding.
+    pub fn is_synchronous(&self, repository: &str) -> bool {
+        self.get_specific_config(repository)
+            .and_then(|s| s.synchronous)
+            .unwrap_or(false)
+    }
+
+    /// Resolves the configuration key to deploy a repository with for a target.
+    ///
+    /// If a specific value exists for the given repository and target, that will be used,
+    /// otherwise the target is unknown.
+    pub fn resolve_target(&self, repository: &str, target: &str) -> Option<&str> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.targets.as_ref())
+            .and_then(|targets| targets.get(target))
+            .map(String::as_str)
+    }
+
+    /// Resolves the value of the `cargo_offline` directive.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise the
+    /// global value will be used, defaulting to `false`.
+    pub fn is_cargo_offline(&self, repository: &str) -> bool {
+        self.get_specific_config(repository)
+            .and_then(|s| s.cargo_offline)
+            .or(self.default.cargo_offline)
+            .unwrap_or(false)
+    }
+
+    /// Resolves the value of the `cargo_home` directive.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise the
+    /// global value will be used, if any.
+    pub fn resolve_cargo_home(&self, repository: &str) -> Option<&Path> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.cargo_home.as_deref())
+            .or(self.default.cargo_home.as_deref())
+    }
+
+    /// Resolves the value of the `cargo_features` directive.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise no
+    /// features will be enabled.
+    pub fn resolve_cargo_features(&self, repository: &str) -> &[String] {
+        self.get_specific_config(repository)
+            .and_then(|s| s.cargo_features.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Resolves the value of the `cargo_profile` directive.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise builds
+    /// will use `--release`.
+    pub fn resolve_cargo_profile(&self, repository: &str) -> Option<&str> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.cargo_profile.as_deref())
+    }
+
+    /// Resolves the value of the `clone_depth` directive.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise the full
+    /// history will be cloned and fetched.
+    pub fn resolve_clone_depth(&self, repository: &str) -> Option<u32> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.clone_depth)
+    }
+
+    /// Resolves the value of the `require_file` directive.
+    ///
+    /// If a specific value exists for the given repository, that file must exist for it to be
+    /// deployed, otherwise it will always be deployed.
+    pub fn resolve_required_file(&self, repository: &str) -> Option<&Path> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.require_file.as_deref())
+    }
+
+    /// Resolves the value of the `coalesce_window_secs` directive.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise pushes
+    /// will be processed immediately.
+    pub fn resolve_coalesce_window(&self, repository: &str) -> Duration {
+        let secs = self
+            .get_specific_config(repository)
+            .and_then(|s| s.coalesce_window_secs)
+            .unwrap_or(0);
+
+        Duration::from_secs(secs)
+    }
+
+    /// Waits until another repository can be fetched, if `max_concurrent_fetches` is set.
+    ///
+    /// The fetch should hold the returned permit until it has finished.
+    pub async fn acquire_fetch_permit(&self) -> Option<SemaphorePermit<'_>> {
+        let limit = self.default.max_concurrent_fetches?;
+        let semaphore = self.fetches.get_or_init(|| Semaphore::new(limit));
+
+        // The semaphore is never closed, so this cannot fail
+        semaphore.acquire().await.ok()
+    }
+
+    /// Waits until another repository can be fetched from the given host, if
+    /// `max_connections_per_host` is set.
+    ///
+    /// The fetch should hold the returned permit until it has finished, as each one opens its own
+    /// connection to the host.
+    pub async fn acquire_host_permit(&self, host: &str) -> Option<OwnedSemaphorePermit> {
+        let limit = self.default.max_connections_per_host?;
+
+        let semaphore = {
+            let mut hosts = self.host_fetches.lock().unwrap_or_else(|e| e.into_inner());
+            let semaphore = hosts
+                .entry(host.to_owned())
+                .or_insert_with(|| Arc::new(Semaphore::new(limit)));
+
+            Arc::clone(semaphore)
+        };
+
+        // The semaphore is never closed, so this cannot fail
+        semaphore.acquire_owned().await.ok()
+    }
+
+    /// Waits until no other deploy with the same key is running, returning a guard that prevents
+    /// any from starting until it is dropped.
+    pub async fn lock_deploy(&self, key: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut deploys = self.deploys.lock().unwrap_or_else(|e| e.into_inner());
+
+            // Forget the locks of any keys that are no longer being deployed
+            deploys.retain(|_, lock| Arc::strong_count(lock) > 1);
+
+            Arc::clone(deploys.entry(key.to_owned()).or_default())
+        };
+
+        lock.lock_owned().await
+    }
+
+    /// Resolves the value of the `deploy_timeout_secs` directive.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise deploys
+    /// can take as long as they need.
+    pub fn resolve_deploy_timeout(&self, repository: &str) -> Option<Duration> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.deploy_timeout_secs)
+            .map(Duration::from_secs)
+    }
+
+    /// Resolves the value of the `synchronous_timeout_secs` directive.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise 10
+    /// seconds will be used to match the delivery timeout GitHub applies.
+    pub fn resolve_synchronous_timeout(&self, repository: &str) -> Duration {
+        let secs = self
+            .get_specific_config(repository)
+            .and_then(|s| s.synchronous_timeout_secs)
+            .unwrap_or(10);
+
+        Duration::from_secs(secs)
+    }
+
+    /// Resolves the value of the `code_root` directive.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise the root
+    /// directory of the project will be used, as denoted by an empty [`PathBuf`].
+    pub fn resolve_code_root(&self, repository: &str) -> PathBuf {
+        self.get_specific_config(repository)
+            .and_then(|s| s.code_root.clone())
+            .unwrap_or_default()
+    }
+
+    /// Resolves the value of the `binaries` directive.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise the name
+    /// of the repository itself will be used.
+    pub fn resolve_binaries(&self, repository: &str) -> Vec<String> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.binaries.clone())
+            .unwrap_or_else(|| {
+                let repository = self.resolve_alias(repository);
+                vec![String::from(repository.split('/').nth(1).unwrap())]
+            })
+    }
+
+    /// Resolves the names of the `supervisor` programs that run a binary, in the order they should
+    /// be restarted.
+    ///
+    /// If the repository lists the programs for the binary, those will be used. Otherwise, if a
+    /// specific template exists for the given repository, `{binary}` will be replaced in it with
+    /// the name of the binary, falling back to the binary name itself.
+    pub fn resolve_supervisor_programs(&self, repository: &str, binary: &str) -> Vec<String> {
+        let specific = self.get_specific_config(repository);
+
+        if let Some(programs) = specific
+            .and_then(|s| s.supervisor_programs.as_ref())
+            .and_then(|programs| programs.get(binary))
+        {
+            return programs.clone();
+        }
+
+        let program = specific
+            .and_then(|s| s.supervisor_name_template.as_deref())
+            .map_or_else(
+                || binary.to_owned(),
+                |template| template.replace("{binary}", binary),
+            );
+
+        vec![program]
+    }
+
+    /// Resolves the program and arguments that restart a program of a repository.
+    ///
+    /// If a specific command exists for the given repository, that will be used, otherwise the
+    /// global command will be used, defaulting to `supervisorctl restart {binary}`. `{binary}` is
+    /// replaced in the arguments with the name of the program to restart.
+    pub fn resolve_restart_command(
+        &self,
+        repository: &str,
+        program: &str,
+    ) -> (String, Vec<String>) {
+        let command = self
+            .get_specific_config(repository)
+            .and_then(|s| s.restart_command.as_ref())
+            .or(self.default.restart_command.as_ref());
+
+        render_program_command(command, "restart", program)
+    }
+
+    /// Resolves the program and arguments that report the status of a program of a repository.
+    ///
+    /// If a specific command exists for the given repository, that will be used, otherwise the
+    /// global command will be used, defaulting to `supervisorctl status {binary}`. `{binary}` is
+    /// replaced in the arguments with the name of the program to check.
+    pub fn resolve_status_command(&self, repository: &str, program: &str) -> (String, Vec<String>) {
+        render_program_command(self.find_status_command(repository), "status", program)
+    }
+
+    /// Checks whether the status of the programs of a repository comes from `supervisorctl`, in
+    /// which case its output must also say they are `RUNNING`.
+    pub fn has_supervisor_status(&self, repository: &str) -> bool {
+        self.find_status_command(repository).is_none()
+    }
+
+    /// Checks whether the programs of a repository are restarted with `supervisorctl`.
+    pub fn has_supervisor_restarts(&self, repository: &str) -> bool {
+        self.get_specific_config(repository)
+            .and_then(|s| s.restart_command.as_ref())
+            .or(self.default.restart_command.as_ref())
+            .is_none()
+    }
+
+    fn find_status_command(&self, repository: &str) -> Option<&Command> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.status_command.as_ref())
+            .or(self.default.status_command.as_ref())
+    }
+
+    /// Resolves the value of the `secret` directive.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise no secret
+    /// will be used (as webhooks do not have to have this).
+    pub fn resolve_secret(&self, repository: &str) -> Option<&str> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.secret.as_deref())
+            .or(self.default.secret.as_deref())
+    }
+
+    /// Checks whether the given repository has its own secret, rather than using the global one.
+    pub fn has_specific_secret(&self, repository: &str) -> bool {
+        self.get_specific_config(repository)
+            .is_some_and(|s| s.secret.is_some())
+    }
+
+    /// Checks whether the given repository would fall back to the global secret despite having
+    /// its own specific configuration, suggesting it should have its own secret.
+    pub fn falls_back_to_global_secret(&self, repository: &str) -> bool {
+        self.get_specific_config(repository).is_some()
+            && !self.has_specific_secret(repository)
+            && self.default.secret.is_some()
+    }
+
+    /// Checks whether webhooks must be signed with a repository's own secret.
+    pub fn requires_specific_secrets(&self) -> bool {
+        self.default.require_specific_secrets.unwrap_or(false)
+    }
+
+    /// Resolves the value of the `signature_algorithm` directive.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise payloads
+    /// are expected to be signed with HMAC-SHA256 as GitHub does.
+    pub fn resolve_signature_algorithm(&self, repository: &str) -> SignatureAlgorithm {
+        self.get_specific_config(repository)
+            .and_then(|s| s.signature_algorithm)
+            .unwrap_or_default()
+    }
+
+    /// Resolves the value of the `follow` directive as a list of branches in priority order.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise only the
+    /// `master` branch will be followed.
+    pub fn resolve_follow_branches(&self, repository: &str) -> Vec<&str> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.follow.as_ref())
+            .map(Follow::branches)
+            .filter(|branches| !branches.is_empty())
+            .unwrap_or_else(|| vec!["master"])
+    }
+
+    /// Checks whether reconciling should notify of each failed repository, rather than only
+    /// sending a summary once every repository has been reconciled.
+    pub fn should_notify_reconcile_failures(&self) -> bool {
+        self.default.notify_reconcile_failures.unwrap_or(false)
+    }
+
+    /// Resolves the branch a repository is deployed from when there is no push to choose one, which
+    /// is the first of its followed branches.
+    ///
+    /// Fails if the first followed branch is a pattern under the repository's `match_mode`, as it
+    /// does not name a single branch that can be deployed.
+    pub fn resolve_primary_branch(&self, repository: &str) -> Result<&str> {
+        let branch = self.resolve_follow_branches(repository)[0];
+
+        if !self.resolve_match_mode(repository).is_literal(branch) {
+            bail!(
+                "The first followed branch of {} is the pattern `{}`, so there is no single branch to deploy",
+                repository,
+                branch
+            );
+        }
+
+        Ok(branch)
+    }
+
+    /// Resolves how fetched changes are applied to the checkout of a repository.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise the global
+    /// value will be used, defaulting to merging the changes.
+    pub fn resolve_strategy(&self, repository: &str) -> Strategy {
+        self.get_specific_config(repository)
+            .and_then(|s| s.strategy)
+            .or(self.default.strategy)
+            .unwrap_or_default()
+    }
+
+    /// Resolves how the followed branches of a repository are compared against pushed branches.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise branches
+    /// must match exactly.
+    pub fn resolve_match_mode(&self, repository: &str) -> MatchMode {
+        self.get_specific_config(repository)
+            .and_then(|s| s.match_mode)
+            .unwrap_or_default()
+    }
+
+    /// Resolves the value of the `follow_file` directive.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise the
+    /// followed branches come from the config alone.
+    pub fn resolve_follow_file(&self, repository: &str) -> Option<&Path> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.follow_file.as_deref())
+    }
+
+    /// Checks whether a branch matches any of the `ignore_branches` patterns for a repository.
+    ///
+    /// If no patterns are specified, no branches are ignored.
+    pub fn is_branch_ignored(&self, repository: &str, branch: &str) -> bool {
+        self.get_specific_config(repository)
+            .and_then(|s| s.ignore_branches.as_ref())
+            .is_some_and(|patterns| {
+                patterns
+                    .iter()
+                    .filter_map(|pattern| glob::Pattern::new(pattern).ok())
+                    .any(|pattern| pattern.matches(branch))
+            })
+    }
+
+    /// Resolves the value of the `deploy_key_template` directive.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise the
+    /// global value will be used, defaulting to `{repo}` so deploys of a repository never overlap.
+    pub fn resolve_deploy_key_template(&self, repository: &str) -> &str {
+        self.get_specific_config(repository)
+            .and_then(|s| s.deploy_key_template.as_deref())
+            .or(self.default.deploy_key_template.as_deref())
+            .unwrap_or("{repo}")
+    }
+
+    /// Resolves the file the result of each deploy of a repository should be written to.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise the
+    /// global value will be used, with `{repo}` replaced by the repository name. If neither
+    /// exists, no results are written.
+    pub fn resolve_result_file(&self, repository: &str) -> Option<PathBuf> {
+        let template = self
+            .get_specific_config(repository)
+            .and_then(|s| s.result_file.as_deref())
+            .or(self.default.result_file.as_deref())?;
+
+        Some(PathBuf::from(template.replace("{repo}", repository)))
+    }
+
+    /// Checks whether a kind of webhook is in the `allowed_events` of a repository.
+    ///
+    /// If no events are specified, every kind of webhook is allowed.
+    pub fn is_event_allowed(&self, repository: &str, variant: WebhookVariant) -> bool {
+        self.get_specific_config(repository)
+            .and_then(|s| s.allowed_events.as_ref())
+            .is_none_or(|allowed| allowed.contains(&variant))
+    }
+
+    /// Resolves the value of the `ignore_author` directive.
+    ///
+    /// If a specific value exists for the given repository, that will be used, otherwise the
+    /// global value will be used, if any.
+    pub fn resolve_ignored_author(&self, repository: &str) -> Option<&str> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.ignore_author.as_deref())
+            .or(self.default.ignore_author.as_deref())
+    }
+
+    /// Resolves the branch to deploy for a `repository_dispatch` with the given action.
+    ///
+    /// If the repository maps the action to a branch, that will be returned, otherwise nothing
+    /// will be returned and the dispatch should be ignored.
+    pub fn resolve_dispatch_branch(&self, repository: &str, action: &str) -> Option<&str> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.dispatch.as_ref())
+            .and_then(|d| d.get(action))
+            .map(String::as_str)
+    }
+
+    /// Resolves the value of the `pre_pull_commands` directive.
+    ///
+    /// If a specific value exists, it will be returned, otherwise nothing will be returned.
+    pub fn resolve_pre_pull_commands(&self, repository: &str) -> Option<&Commands> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.pre_pull_commands.as_ref())
+    }
+
+    /// Resolves the value of the `precommands` directive.
+    ///
+    /// If a specific value exists, it will be returned, otherwise nothing will be returned.
+    pub fn resolve_precommands(&self, repository: &str) -> Option<&Commands> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.precommands.as_ref())
+    }
+
+    /// Resolves the value of the `build_commands` directive.
+    ///
+    /// If a specific value exists, it will be returned, otherwise nothing will be returned.
+    pub fn resolve_build_commands(&self, repository: &str) -> Option<&Commands> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.build_commands.as_ref())
+    }
+
+    /// Resolves the value of the `commands` directive.
+    ///
+    /// If a specific value exists, it will be returned, otherwise nothing will be returned.
+    pub fn resolve_commands(&self, repository: &str) -> Option<&Commands> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.commands.as_ref())
+    }
+
+    /// Resolves the value of the `on_failure` directive.
+    ///
+    /// If a specific value exists, it will be returned, otherwise nothing will be returned.
+    pub fn resolve_on_failure_commands(&self, repository: &str) -> Option<&Commands> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.on_failure.as_ref())
+    }
+
+    /// Resolves the value of the `always_commands` directive.
+    ///
+    /// If a specific value exists, it will be returned, otherwise nothing will be returned.
+    pub fn resolve_always_commands(&self, repository: &str) -> Option<&Commands> {
+        self.get_specific_config(repository)
+            .and_then(|s| s.always_commands.as_ref())
+    }
+}
+
+impl FromStr for Config {
+    type Err = serde_yaml::Error;
+
+    /// Parses a config, failing with a dedicated error if it is empty or only has comments, rather
+    /// than one about the missing `default` section.
+    ///
+    /// Configs written for older versions of the schema are migrated to the current version first.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut value: serde_yaml::Value = serde_yaml::from_str(s)?;
+
+        if value.is_null() {
+            return Err(serde::de::Error::custom(
+                "the config is empty, it needs at least a `default` section",
+            ));
+        }
+
+        // Errors from the parsed value have no location, so only use it if it had to be migrated
+        if migrate(&mut value, MIGRATIONS)? {
+            return serde_yaml::from_value(value);
+        }
+
+        serde_yaml::from_str(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    use serenity::model::id::ChannelId;
+
+    use crate::auth::SignatureAlgorithm;
+    use crate::config::{
+        migrate, program_exists, CommandContext, Config, MatchMode, Migration, Stage, Strategy,
+        SuccessCriteria, VerifyRestart, CURRENT_VERSION,
+    };
+    use crate::webhook::WebhookVariant;
+
+    static CONFIG: &str = r#"
+default:
+    ssh_private_key: "/root/.ssh/id_rsa"
+    repo_root: "/root"
+    cargo_path: "/root/.cargo/bin/cargo"
+
+specific:
+    FreddieBrown/dodona:
+        follow: "develop"
+        ignore_branches: ["dependabot/*"]
         code_root: "/backend"
         binaries: ["api-server", "dcl"]
+        supervisor_name_template: "prod-{binary}"
+        supervisor_programs:
+            api-server: ["prod-api-web", "prod-api-worker"]
+        result_file: "/var/run/dodona/deploy.json"
+        allowed_events: ["ping", "repository_dispatch"]
+
+    alexander-jackson/locker:
+        binaries: ["locker", "zipper"]
+        stages: ["pull", "build"]
+        run_as: "builder"
+        signature_algorithm: "sha1"
+        include_commit_message: false
+        clone_depth: 1
+        cargo_offline: true
+        cargo_home: "/opt/cargo"
+
+    alexander-jackson/ptc:
+        code_root: "/ptc"
+        clean_build: true
+        coalesce_window_secs: 30
+        deploy_timeout_secs: 600
+        follow: ["main", "master"]
+        stages: ["pre_pull", "pull", "build"]
+        pre_pull_commands:
+            - program: "true"
+        on_failure:
+            - program: "./scripts/rollback.sh"
+
+    alexander-jackson/se-powerlifting-website:
+        should_build_binaries: false
+        github_deployments: true
+        require_file: "DEPLOYABLE"
+        required_checks:
+            contexts: ["ci/build", "ci/test"]
+            timeout_secs: 300
+        verify_restart:
+            settle_secs: 30
+
+    alexander-jackson/fisherman:
+        signature_algorithm: "token"
+        synchronous: true
+        synchronous_timeout_secs: 30
+        dispatch:
+            deploy-staging: "staging"
+"#;
+
+    #[test]
+    fn config_can_be_parsed_from_a_string() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert_eq!(
+            config.default.ssh_private_key,
+            Some(PathBuf::from("/root/.ssh/id_rsa"))
+        );
+
+        assert_eq!(config.default.repo_root, PathBuf::from("/root"));
+
+        assert_eq!(
+            config.default.cargo_path,
+            PathBuf::from("/root/.cargo/bin/cargo")
+        );
+
+        assert!(config.specific.is_some());
+    }
+
+    #[test]
+    fn repository_specific_settings_can_be_fetched() {
+        let config = Config::from_str(CONFIG).unwrap();
+        assert!(config.get_specific_config("FreddieBrown/dodona").is_some());
+    }
+
+    #[test]
+    fn no_specific_settings_exist_if_not_defined() {
+        let config = Config::from_str(CONFIG).unwrap();
+        assert!(config
+            .get_specific_config("FreddieBrown/not-dodona")
+            .is_none());
+    }
+
+    #[test]
+    fn code_root_can_be_fetched_if_it_exists() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let code_root = config.resolve_code_root("FreddieBrown/dodona");
+
+        assert_eq!(code_root, PathBuf::from("/backend"));
+    }
+
+    #[test]
+    fn default_code_root_is_the_repository_root() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let code_root = config.resolve_code_root("alexander-jackson/locker");
+
+        assert_eq!(code_root, PathBuf::new());
+    }
+
+    #[test]
+    fn binaries_resolve_correctly() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let binaries = config.resolve_binaries("FreddieBrown/dodona");
+
+        assert_eq!(binaries, vec!["api-server", "dcl"]);
+    }
+
+    #[test]
+    fn binaries_are_assumed_to_be_repository_name_if_not_specified() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let binaries = config.resolve_binaries("alexander-jackson/ptc");
+
+        assert_eq!(binaries, vec!["ptc"]);
+    }
+
+    #[test]
+    fn config_with_no_secret_assumes_no_security() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let secret = config.resolve_secret("alexander-jackson/ptc");
+
+        assert!(secret.is_none());
+    }
+
+    #[test]
+    fn no_specific_secret_assumes_global_value() {
+        let config = r#"
+        default:
+            ssh_private_key: "/root/.ssh/id_rsa"
+            repo_root: "/root"
+            cargo_path: "/root/.cargo/bin/cargo"
+            secret: "<some secret value>"
+        "#;
+
+        let config = Config::from_str(config).unwrap();
+        let secret = config.resolve_secret("alexander-jackson/ptc");
+
+        assert_eq!(secret, Some("<some secret value>"));
+    }
+
+    #[test]
+    fn specific_secrets_are_used_if_they_exist() {
+        let config = r#"
+        default:
+            ssh_private_key: "/root/.ssh/id_rsa"
+            repo_root: "/root"
+            cargo_path: "/root/.cargo/bin/cargo"
+
+        specific:
+            alexander-jackson/ptc:
+                secret: "<repository specific>"
+        "#;
+
+        let config = Config::from_str(config).unwrap();
+        let secret = config.resolve_secret("alexander-jackson/ptc");
+
+        assert_eq!(secret, Some("<repository specific>"));
+    }
+
+    #[test]
+    fn master_is_followed_if_unspecified() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let follow_branches = config.resolve_follow_branches("alexander-jackson/locker");
+
+        assert_eq!(follow_branches, vec!["master"]);
+    }
+
+    #[test]
+    fn specific_branches_can_be_followed() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let follow_branches = config.resolve_follow_branches("FreddieBrown/dodona");
+
+        assert_eq!(follow_branches, vec!["develop"]);
+    }
+
+    #[test]
+    fn binaries_are_built_if_not_specified() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let should_build_binaries = config.should_build_binaries("FreddieBrown/dodona");
+
+        assert!(should_build_binaries);
+    }
+
+    #[test]
+    fn binaries_are_not_built_based_on_config() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let should_build_binaries =
+            config.should_build_binaries("alexander-jackson/se-powerlifting-website");
+
+        assert!(!should_build_binaries);
+    }
+
+    #[test]
+    fn webhooks_are_processed_asynchronously_by_default() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert!(!config.is_synchronous("FreddieBrown/dodona"));
+        assert_eq!(
+            config.resolve_synchronous_timeout("FreddieBrown/dodona"),
+            Duration::from_secs(10)
+        );
+    }
+
+    #[test]
+    fn synchronous_processing_can_be_enabled() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert!(config.is_synchronous("alexander-jackson/fisherman"));
+        assert_eq!(
+            config.resolve_synchronous_timeout("alexander-jackson/fisherman"),
+            Duration::from_secs(30)
+        );
+    }
+
+    #[test]
+    fn dispatch_actions_resolve_to_their_branch() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let branch =
+            config.resolve_dispatch_branch("alexander-jackson/fisherman", "deploy-staging");
+
+        assert_eq!(branch, Some("staging"));
+    }
+
+    #[test]
+    fn unconfigured_dispatch_actions_are_ignored() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert!(config
+            .resolve_dispatch_branch("alexander-jackson/fisherman", "deploy-production")
+            .is_none());
+        assert!(config
+            .resolve_dispatch_branch("FreddieBrown/dodona", "deploy-staging")
+            .is_none());
+    }
+
+    #[test]
+    fn commit_messages_are_included_if_not_specified() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert!(config.should_include_commit_message("FreddieBrown/dodona"));
+    }
+
+    #[test]
+    fn commit_messages_can_be_excluded_from_notifications() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert!(!config.should_include_commit_message("alexander-jackson/locker"));
+    }
+
+    #[test]
+    fn follow_branches_can_be_a_priority_list() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let follow_branches = config.resolve_follow_branches("alexander-jackson/ptc");
+
+        assert_eq!(follow_branches, vec!["main", "master"]);
+    }
+
+    #[test]
+    fn signatures_use_sha256_if_unspecified() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let algorithm = config.resolve_signature_algorithm("FreddieBrown/dodona");
+
+        assert_eq!(algorithm, SignatureAlgorithm::Sha256);
+    }
+
+    #[test]
+    fn signature_algorithms_can_be_chosen_per_repository() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert_eq!(
+            config.resolve_signature_algorithm("alexander-jackson/locker"),
+            SignatureAlgorithm::Sha1
+        );
+        assert_eq!(
+            config.resolve_signature_algorithm("alexander-jackson/fisherman"),
+            SignatureAlgorithm::Token
+        );
+    }
+
+    #[test]
+    fn github_deployments_are_disabled_by_default() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert!(!config.should_create_github_deployments("FreddieBrown/dodona"));
+        assert!(config.get_github_client().is_none());
+    }
+
+    #[test]
+    fn github_deployments_can_be_enabled() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert!(
+            config.should_create_github_deployments("alexander-jackson/se-powerlifting-website")
+        );
+    }
+
+    #[test]
+    fn no_branches_are_ignored_if_unspecified() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert!(!config.is_branch_ignored("alexander-jackson/ptc", "dependabot/cargo/serde"));
+    }
+
+    #[test]
+    fn branches_matching_ignore_patterns_are_ignored() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert!(config.is_branch_ignored("FreddieBrown/dodona", "dependabot/cargo/serde"));
+        assert!(!config.is_branch_ignored("FreddieBrown/dodona", "develop"));
+    }
+
+    #[test]
+    fn builds_are_not_cleaned_by_default() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert!(!config.should_clean_build("FreddieBrown/dodona"));
+        assert!(config.should_clean_build("alexander-jackson/ptc"));
+    }
+
+    #[test]
+    fn notifications_are_routed_by_branch() {
+        let config = r#"
+        default:
+            ssh_private_key: "/root/.ssh/id_rsa"
+            repo_root: "/root"
+            cargo_path: "/root/.cargo/bin/cargo"
+            discord:
+                token: "<token>"
+                channel_id: 1
+                branch_channels:
+                    staging: 2
+        "#;
+
+        let config = Config::from_str(config).unwrap();
+        let discord = config.default.discord.unwrap();
+
+        assert_eq!(discord.resolve_channel_id(Some("staging")), ChannelId(2));
+        assert_eq!(discord.resolve_channel_id(Some("master")), ChannelId(1));
+        assert_eq!(discord.resolve_channel_id(None), ChannelId(1));
+    }
+
+    #[test]
+    fn notifications_are_batched_with_jitter_if_configured() {
+        let config = r#"
+        default:
+            ssh_private_key: "/root/.ssh/id_rsa"
+            repo_root: "/root"
+            cargo_path: "/root/.cargo/bin/cargo"
+            discord:
+                token: "<token>"
+                channel_id: 1
+                batch_window_ms: 1000
+                batch_jitter_ms: 200
+        "#;
+
+        let mut config = Config::from_str(config).unwrap();
+        let discord = config.default.discord.as_mut().unwrap();
+
+        let delay = discord.batch_delay().unwrap();
+        assert!(delay >= Duration::from_millis(1000) && delay <= Duration::from_millis(1200));
+
+        discord.batch_window_ms = None;
+        assert_eq!(discord.batch_delay(), None);
+    }
+
+    #[test]
+    fn self_update_binaries_are_resolved_for_the_self_repository() {
+        let config = r#"
+        default:
+            ssh_private_key: "/root/.ssh/id_rsa"
+            repo_root: "/root"
+            cargo_path: "/root/.cargo/bin/cargo"
+            self_repository: "alexander-jackson/fisherman"
+        "#;
+
+        let config = Config::from_str(config).unwrap();
+
+        let expected = PathBuf::from("/root/fisherman/target/release/fisherman");
+
+        assert_eq!(
+            config.resolve_self_update_binary("alexander-jackson/fisherman"),
+            Some(expected)
+        );
+        assert_eq!(
+            config.resolve_self_update_binary("alexander-jackson/locker"),
+            None
+        );
+    }
+
+    #[test]
+    fn output_is_checked_against_success_criteria() {
+        let criteria = SuccessCriteria {
+            required_output: Some(String::from("Finished")),
+            forbidden_output: Some(String::from("warning: .+")),
+            supervisor_status: None,
+            supervisor_delay_secs: None,
+            fresh_binaries: false,
+        };
+
+        assert!(criteria.checks_output());
+        assert!(criteria.check_output("Finished release").is_ok());
+        assert!(criteria.check_output("Compiling fisherman").is_err());
+        assert!(criteria
+            .check_output("warning: unused import\nFinished release")
+            .is_err());
+    }
+
+    #[test]
+    fn supervisor_status_is_checked_against_success_criteria() {
+        let criteria = SuccessCriteria {
+            required_output: None,
+            forbidden_output: None,
+            supervisor_status: Some(String::from("RUNNING")),
+            supervisor_delay_secs: None,
+            fresh_binaries: false,
+        };
+
+        let running = "fisherman    RUNNING   pid 1234, uptime 0:00:05";
+        let exited = "fisherman    EXITED    Oct 16 12:00 PM";
+
+        assert!(!criteria.checks_output());
+        assert!(criteria.check_supervisor_status(running).is_ok());
+        assert!(criteria.check_supervisor_status(exited).is_err());
+    }
+
+    #[test]
+    fn proxies_are_validated() {
+        let config = r#"
+        default:
+            ssh_private_key: "/root/.ssh/id_rsa"
+            repo_root: "/root"
+            cargo_path: "/root/.cargo/bin/cargo"
+            proxy_url: "http://proxy.internal:3128"
+        "#;
+
+        let mut config = Config::from_str(config).unwrap();
+        assert!(config.http_client_builder().is_ok());
+
+        config.default.proxy_url = Some(String::from("http://[invalid"));
+        assert!(config.http_client_builder().is_err());
+    }
+
+    #[test]
+    fn programs_are_found_on_the_path() {
+        assert!(program_exists("sh"));
+        assert!(program_exists("/bin/sh"));
+        assert!(program_exists("./scripts/deploy.sh"));
+
+        assert!(!program_exists("suprvisorctl"));
+        assert!(!program_exists("/bin/suprvisorctl"));
+    }
+
+    #[test]
+    fn deploy_timeouts_can_be_set() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert_eq!(
+            config.resolve_deploy_timeout("alexander-jackson/ptc"),
+            Some(Duration::from_secs(600))
+        );
+        assert_eq!(config.resolve_deploy_timeout("FreddieBrown/dodona"), None);
+    }
+
+    #[test]
+    fn notify_urls_are_checked_against_the_allowlist() {
+        let mut config = Config::from_str(CONFIG).unwrap();
+
+        assert!(config.is_notify_url_allowed("http://169.254.169.254/latest"));
+
+        config.default.allowed_notify_hosts = Some(vec![String::from("api.github.com")]);
+
+        assert!(config.is_notify_url_allowed("https://api.github.com"));
+        assert!(config.is_notify_url_allowed("https://API.GitHub.com/repos"));
+        assert!(!config.is_notify_url_allowed("http://169.254.169.254/latest"));
+        assert!(!config.is_notify_url_allowed("not a url"));
+    }
+
+    #[test]
+    fn commands_can_be_run_as_another_user() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        let command = config.create_command("alexander-jackson/locker", "cargo");
+        let command = command.as_std();
+
+        assert_eq!(command.get_program(), "sudo");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["-n", "-u", "builder", "--", "cargo"]
+        );
+
+        let command = config.create_command("FreddieBrown/dodona", "cargo");
+
+        assert_eq!(command.as_std().get_program(), "cargo");
+    }
+
+    #[test]
+    fn pushes_are_not_coalesced_by_default() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert_eq!(
+            config.resolve_coalesce_window("alexander-jackson/ptc"),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            config.resolve_coalesce_window("FreddieBrown/dodona"),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn every_stage_is_run_by_default() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert!(config.should_run_stage("FreddieBrown/dodona", Stage::Build));
+        assert!(config.should_run_stage("FreddieBrown/dodona", Stage::Notify));
+    }
+
+    #[test]
+    fn binaries_are_not_built_or_restarted_if_disabled() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let repository = "alexander-jackson/se-powerlifting-website";
+
+        assert!(config.should_run_stage(repository, Stage::Pull));
+        assert!(!config.should_run_stage(repository, Stage::Build));
+        assert!(!config.should_run_stage(repository, Stage::Restart));
+    }
+
+    #[test]
+    fn only_specified_stages_are_run() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let repository = "alexander-jackson/locker";
+
+        assert!(!config.should_run_stage(repository, Stage::PrePull));
+        assert!(config.should_run_stage(repository, Stage::Pull));
+        assert!(config.should_run_stage(repository, Stage::Build));
+        assert!(!config.should_run_stage(repository, Stage::Restart));
+        assert!(!config.should_run_stage(repository, Stage::Notify));
+    }
+
+    #[test]
+    fn supervisor_programs_are_named_by_the_template() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert_eq!(
+            config.resolve_supervisor_programs("FreddieBrown/dodona", "dcl"),
+            vec!["prod-dcl"]
+        );
+        assert_eq!(
+            config.resolve_supervisor_programs("alexander-jackson/locker", "zipper"),
+            vec!["zipper"]
+        );
+    }
+
+    #[test]
+    fn binaries_can_run_several_supervisor_programs() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert_eq!(
+            config.resolve_supervisor_programs("FreddieBrown/dodona", "api-server"),
+            vec!["prod-api-web", "prod-api-worker"]
+        );
+    }
+
+    #[test]
+    fn fallbacks_to_the_global_secret_are_detected() {
+        let config = r#"
+        default:
+            ssh_private_key: "/root/.ssh/id_rsa"
+            repo_root: "/root"
+            cargo_path: "/root/.cargo/bin/cargo"
+            secret: "global"
+
+        specific:
+            alexander-jackson/fisherman:
+                secret: "specific"
+
+            alexander-jackson/locker:
+                binaries: ["locker"]
+        "#;
+
+        let config = Config::from_str(config).unwrap();
+
+        assert!(config.has_specific_secret("alexander-jackson/fisherman"));
+        assert!(!config.falls_back_to_global_secret("alexander-jackson/fisherman"));
+
+        assert!(!config.has_specific_secret("alexander-jackson/locker"));
+        assert!(config.falls_back_to_global_secret("alexander-jackson/locker"));
+
+        assert!(!config.falls_back_to_global_secret("alexander-jackson/ptc"));
+    }
+
+    #[test]
+    fn required_files_can_be_set() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert_eq!(
+            config.resolve_required_file("alexander-jackson/se-powerlifting-website"),
+            Some(Path::new("DEPLOYABLE"))
+        );
+        assert_eq!(config.resolve_required_file("FreddieBrown/dodona"), None);
+    }
+
+    #[test]
+    fn renamed_repositories_follow_their_aliases() {
+        let config = r#"
+        default:
+            ssh_private_key: "/root/.ssh/id_rsa"
+            repo_root: "/root"
+            cargo_path: "/root/.cargo/bin/cargo"
+
+        specific:
+            alexander-jackson/locker:
+                binaries: ["locker", "zipper"]
+
+            alexander-jackson/fisherman:
+                binaries: ["fisherman"]
+
+        aliases:
+            alexander-jackson/vault: "alexander-jackson/locker"
+            alexander-jackson/fisher: "alexander-jackson/fisherman"
+            alexander-jackson/fisherman: "alexander-jackson/angler"
+        "#;
+
+        let config = Config::from_str(config).unwrap();
+
+        assert_eq!(
+            config.resolve_binaries("alexander-jackson/vault"),
+            vec!["locker", "zipper"]
+        );
+        assert_eq!(
+            config.resolve_repository_path("alexander-jackson/vault"),
+            PathBuf::from("/root/locker")
+        );
+
+        // Specific configuration under the new name takes precedence over the alias
+        assert_eq!(
+            config.resolve_alias("alexander-jackson/fisherman"),
+            "alexander-jackson/fisherman"
+        );
+        assert_eq!(
+            config.resolve_alias("alexander-jackson/fisher"),
+            "alexander-jackson/fisherman"
+        );
+
+        // Repositories without aliases are unaffected
+        assert_eq!(
+            config.resolve_repository_path("alexander-jackson/ptc"),
+            PathBuf::from("/root/ptc")
+        );
+        assert_eq!(
+            config.resolve_binaries("alexander-jackson/ptc"),
+            vec!["ptc"]
+        );
+    }
+
+    #[tokio::test]
+    async fn fetches_are_limited_when_configured() {
+        let config = Config::from_str(CONFIG).unwrap();
+        assert!(config.acquire_fetch_permit().await.is_none());
+
+        let mut config = Config::from_str(CONFIG).unwrap();
+        config.default.max_concurrent_fetches = Some(1);
+
+        let permit = config.acquire_fetch_permit().await;
+        assert!(permit.is_some());
+
+        let semaphore = config.fetches.get().unwrap();
+        assert_eq!(semaphore.available_permits(), 0);
+
+        drop(permit);
+        assert_eq!(semaphore.available_permits(), 1);
+    }
+
+    #[tokio::test]
+    async fn fetches_from_each_host_are_limited_when_configured() {
+        let config = Config::from_str(CONFIG).unwrap();
+        assert!(config.acquire_host_permit("github.com").await.is_none());
+
+        let mut config = Config::from_str(CONFIG).unwrap();
+        config.default.max_connections_per_host = Some(1);
+
+        let permit = config.acquire_host_permit("github.com").await;
+        assert!(permit.is_some());
+
+        // Other hosts have their own limits
+        assert!(config.acquire_host_permit("gitlab.com").await.is_some());
+
+        let waiting = config.acquire_host_permit("github.com");
+        let timeout = Duration::from_millis(10);
+        assert!(tokio::time::timeout(timeout, waiting).await.is_err());
+
+        drop(permit);
+        assert!(config.acquire_host_permit("github.com").await.is_some());
+    }
+
+    #[test]
+    fn full_history_is_cloned_unless_a_depth_is_given() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert_eq!(
+            config.resolve_clone_depth("alexander-jackson/locker"),
+            Some(1)
+        );
+        assert_eq!(config.resolve_clone_depth("alexander-jackson/ptc"), None);
+    }
+
+    #[test]
+    fn builds_are_online_with_the_default_cargo_home_if_unspecified() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert!(!config.is_cargo_offline("alexander-jackson/ptc"));
+        assert_eq!(config.resolve_cargo_home("alexander-jackson/ptc"), None);
+
+        assert!(config.is_cargo_offline("alexander-jackson/locker"));
+        assert_eq!(
+            config.resolve_cargo_home("alexander-jackson/locker"),
+            Some(Path::new("/opt/cargo"))
+        );
+    }
+
+    #[tokio::test]
+    async fn deploys_with_the_same_key_are_serialized() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        let guard = config.lock_deploy("alexander-jackson/fisherman").await;
+        let _other = config.lock_deploy("alexander-jackson/locker").await;
+
+        let waiting = tokio::time::timeout(
+            Duration::from_millis(50),
+            config.lock_deploy("alexander-jackson/fisherman"),
+        );
+
+        assert!(waiting.await.is_err());
+
+        drop(guard);
+        let _guard = config.lock_deploy("alexander-jackson/fisherman").await;
+    }
+
+    #[test]
+    fn deploys_are_keyed_by_repository_unless_specified() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert_eq!(
+            config.resolve_deploy_key_template("alexander-jackson/ptc"),
+            "{repo}"
+        );
+    }
+
+    #[test]
+    fn only_allowed_events_are_handled() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert!(config.is_event_allowed("alexander-jackson/ptc", WebhookVariant::Push));
+
+        let repository = "FreddieBrown/dodona";
+
+        assert!(!config.is_event_allowed(repository, WebhookVariant::Push));
+        assert!(config.is_event_allowed(repository, WebhookVariant::RepositoryDispatch));
+    }
+
+    #[test]
+    fn required_checks_can_be_configured() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let checks = config
+            .resolve_required_checks("alexander-jackson/se-powerlifting-website")
+            .unwrap();
+
+        assert_eq!(checks.contexts, ["ci/build", "ci/test"]);
+        assert_eq!(checks.timeout(), Duration::from_secs(300));
+        assert_eq!(checks.interval(), Duration::from_secs(15));
+
+        assert!(config
+            .resolve_required_checks("FreddieBrown/dodona")
+            .is_none());
+    }
+
+    #[test]
+    fn required_checks_without_github_are_mistakes() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let mistakes = config.find_potential_mistakes();
+
+        assert!(mistakes.iter().any(|mistake| {
+            mistake.key.as_deref() == Some("alexander-jackson/se-powerlifting-website")
+                && mistake.message.contains("`required_checks`")
+        }));
+    }
+
+    #[test]
+    fn pre_pull_commands_can_be_resolved() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let repository = "alexander-jackson/ptc";
+
+        assert!(config.resolve_pre_pull_commands(repository).is_some());
+        assert!(config.should_run_stage(repository, Stage::PrePull));
+
+        assert!(config
+            .resolve_pre_pull_commands("FreddieBrown/dodona")
+            .is_none());
+    }
+
+    #[test]
+    fn result_files_are_only_written_if_configured() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert_eq!(
+            config.resolve_result_file("FreddieBrown/dodona"),
+            Some(PathBuf::from("/var/run/dodona/deploy.json"))
+        );
+        assert!(config
+            .resolve_result_file("alexander-jackson/ptc")
+            .is_none());
+    }
+
+    #[test]
+    fn result_files_can_be_templated_by_repository() {
+        let config = Config::from_str(
+            r#"
+default:
+    ssh_private_key: "/root/.ssh/id_rsa"
+    repo_root: "/root"
+    cargo_path: "/root/.cargo/bin/cargo"
+    result_file: "/var/lib/fisherman/{repo}.json"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.resolve_result_file("alexander-jackson/ptc"),
+            Some(PathBuf::from(
+                "/var/lib/fisherman/alexander-jackson/ptc.json"
+            ))
+        );
+    }
+
+    #[test]
+    fn discord_clients_are_reused() {
+        let config = Config::from_str(
+            r#"
+default:
+    ssh_private_key: "/root/.ssh/id_rsa"
+    repo_root: "/root"
+    cargo_path: "/root/.cargo/bin/cargo"
+    discord:
+        token: "<token>"
+        channel_id: 1234
+        timeout_secs: 10
+"#,
+        )
+        .unwrap();
+
+        let (first, channel_id) = config.get_client_and_channel_id(None).unwrap();
+        let (second, _) = config.get_client_and_channel_id(Some("master")).unwrap();
+
+        assert_eq!(channel_id, ChannelId(1234));
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn binaries_must_be_written_by_the_build_if_required() {
+        let criteria = SuccessCriteria {
+            required_output: None,
+            forbidden_output: None,
+            supervisor_status: None,
+            supervisor_delay_secs: None,
+            fresh_binaries: true,
+        };
+
+        let path = std::env::temp_dir().join(format!("fisherman-binary-{}", std::process::id()));
+        let before = SystemTime::now() - Duration::from_secs(60);
+
+        assert!(criteria.check_binary(&path, before).is_err());
+
+        std::fs::write(&path, b"binary").unwrap();
+        let fresh = criteria.check_binary(&path, before);
+        let stale = criteria.check_binary(&path, SystemTime::now() + Duration::from_secs(60));
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(fresh.is_ok());
+        assert!(stale.is_err());
+    }
+
+    #[test]
+    fn failure_commands_can_be_resolved() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert!(config
+            .resolve_on_failure_commands("alexander-jackson/ptc")
+            .is_some());
+        assert!(config
+            .resolve_on_failure_commands("FreddieBrown/dodona")
+            .is_none());
+    }
+
+    #[test]
+    fn stages_are_displayed_as_configured() {
+        assert_eq!(Stage::PrePull.to_string(), "pre_pull");
+        assert_eq!(Stage::Build.to_string(), "build");
+    }
+
+    #[test]
+    fn the_first_followed_branch_is_the_primary_one() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert_eq!(
+            config
+                .resolve_primary_branch("alexander-jackson/ptc")
+                .unwrap(),
+            "main"
+        );
+        assert_eq!(
+            config
+                .resolve_primary_branch("FreddieBrown/dodona")
+                .unwrap(),
+            "develop"
+        );
+        assert_eq!(
+            config
+                .resolve_primary_branch("alexander-jackson/locker")
+                .unwrap(),
+            "master"
+        );
+    }
+
+    #[test]
+    fn the_listen_address_defaults_to_localhost() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert_eq!(
+            config.socket_address(),
+            "127.0.0.1:5000".parse::<SocketAddr>().unwrap()
+        );
+    }
 
-    alexander-jackson/locker:
-        binaries: ["locker", "zipper"]
+    #[test]
+    fn the_listen_address_can_be_ipv4_or_ipv6() {
+        let config = |address: &str| {
+            let config = format!(
+                r#"
+default:
+    ssh_private_key: "/root/.ssh/id_rsa"
+    repo_root: "/root"
+    cargo_path: "/root/.cargo/bin/cargo"
+    bind_address: "{}"
+    port: 8080
+"#,
+                address
+            );
 
-    alexander-jackson/ptc:
-        code_root: "/ptc"
+            Config::from_str(&config).unwrap()
+        };
 
-    alexander-jackson/se-powerlifting-website:
-        should_build_binaries: false
-"#;
+        assert_eq!(
+            config("0.0.0.0").socket_address(),
+            "0.0.0.0:8080".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            config("::").socket_address(),
+            "[::]:8080".parse::<SocketAddr>().unwrap()
+        );
+    }
 
     #[test]
-    fn config_can_be_parsed_from_a_string() {
+    fn followed_branches_match_exactly_by_default() {
         let config = Config::from_str(CONFIG).unwrap();
 
         assert_eq!(
-            config.default.ssh_private_key,
-            PathBuf::from("/root/.ssh/id_rsa")
+            config.resolve_match_mode("FreddieBrown/dodona"),
+            MatchMode::Exact
         );
 
-        assert_eq!(config.default.repo_root, PathBuf::from("/root"));
+        assert!(MatchMode::Exact.matches("master", "master"));
+        assert!(!MatchMode::Exact.matches("release/*", "release/1.2"));
+    }
+
+    #[test]
+    fn followed_branches_can_be_glob_patterns() {
+        assert!(MatchMode::Glob.matches("release/*", "release/1.2"));
+        assert!(MatchMode::Glob.matches("master", "master"));
+        assert!(!MatchMode::Glob.matches("release/*", "feature/release"));
+        assert!(!MatchMode::Glob.matches("[", "["));
+    }
+
+    #[test]
+    fn followed_branches_can_be_regular_expressions() {
+        assert!(MatchMode::Regex.matches(r"release/\d+", "release/12"));
+        assert!(!MatchMode::Regex.matches(r"release/\d+", "release/12-rc"));
+        assert!(!MatchMode::Regex.matches(r"release/\d+", "old-release/12"));
+        assert!(!MatchMode::Regex.matches("(", "("));
+    }
+
+    #[test]
+    fn match_modes_can_be_set_per_repository() {
+        let config = Config::from_str(
+            r#"
+default:
+    ssh_private_key: "/root/.ssh/id_rsa"
+    repo_root: "/root"
+    cargo_path: "/root/.cargo/bin/cargo"
+
+specific:
+    alexander-jackson/ptc:
+        follow: ["release/(", "main"]
+        match_mode: "regex"
+"#,
+        )
+        .unwrap();
 
         assert_eq!(
-            config.default.cargo_path,
-            PathBuf::from("/root/.cargo/bin/cargo")
+            config.resolve_match_mode("alexander-jackson/ptc"),
+            MatchMode::Regex
         );
 
-        assert!(config.specific.is_some());
+        let mistakes = config.find_potential_mistakes();
+        assert!(mistakes.iter().any(|mistake| mistake
+            .message
+            .contains("`follow` contains an invalid pattern")));
     }
 
     #[test]
-    fn repository_specific_settings_can_be_fetched() {
-        let config = Config::from_str(CONFIG).unwrap();
-        assert!(config.get_specific_config("FreddieBrown/dodona").is_some());
+    fn repository_paths_follow_the_path_layout() {
+        let config = |layout: &str| {
+            let config = format!(
+                r#"
+default:
+    ssh_private_key: "/root/.ssh/id_rsa"
+    repo_root: "/root"
+    cargo_path: "/root/.cargo/bin/cargo"
+    path_layout: "{}"
+"#,
+                layout
+            );
+
+            Config::from_str(&config).unwrap()
+        };
+
+        let repository = "alexander-jackson/ptc";
+
+        assert_eq!(
+            config("name").resolve_repository_path(repository),
+            PathBuf::from("/root/ptc")
+        );
+        assert_eq!(
+            config("full_name").resolve_repository_path(repository),
+            PathBuf::from("/root/alexander-jackson/ptc")
+        );
+        assert_eq!(
+            config("{owner}-{name}").resolve_repository_path(repository),
+            PathBuf::from("/root/alexander-jackson-ptc")
+        );
     }
 
     #[test]
-    fn no_specific_settings_exist_if_not_defined() {
-        let config = Config::from_str(CONFIG).unwrap();
-        assert!(config
-            .get_specific_config("FreddieBrown/not-dodona")
-            .is_none());
+    fn path_layouts_without_placeholders_are_mistakes() {
+        let mistakes = |layout: &str| {
+            let config = format!(
+                r#"
+default:
+    ssh_private_key: "/root/.ssh/id_rsa"
+    repo_root: "/root"
+    cargo_path: "/root/.cargo/bin/cargo"
+    path_layout: "{}"
+"#,
+                layout
+            );
+
+            Config::from_str(&config)
+                .unwrap()
+                .find_potential_mistakes()
+                .into_iter()
+                .filter(|mistake| mistake.message.contains("`path_layout`"))
+                .count()
+        };
+
+        assert_eq!(mistakes("fullname"), 1);
+        assert_eq!(mistakes("repositories"), 1);
+        assert_eq!(mistakes("full_name"), 0);
+        assert_eq!(mistakes("{owner}-{name}"), 0);
     }
 
     #[test]
-    fn code_root_can_be_fetched_if_it_exists() {
-        let config = Config::from_str(CONFIG).unwrap();
-        let code_root = config.resolve_code_root("FreddieBrown/dodona");
+    fn weak_secrets_are_mistakes() {
+        let config = Config::from_str(
+            r#"
+default:
+    ssh_private_key: "/root/.ssh/id_rsa"
+    repo_root: "/root"
+    cargo_path: "/root/.cargo/bin/cargo"
+    secret: "<some secret value>"
+    min_secret_length: 20
 
-        assert_eq!(code_root, PathBuf::from("/backend"));
+specific:
+    alexander-jackson/ptc:
+        secret: "tooshort"
+    alexander-jackson/locker:
+        secret: "ac9045a77c15bd105cfa09a64635f9b006b3f845"
+"#,
+        )
+        .unwrap();
+
+        let mistakes = config.find_potential_mistakes();
+        let weakness = |key: Option<&str>| {
+            mistakes
+                .iter()
+                .find(|mistake| {
+                    mistake.key.as_deref() == key && mistake.message.contains("`secret`")
+                })
+                .map(|mistake| mistake.message.as_str())
+        };
+
+        assert!(weakness(None).is_some_and(|message| message.contains("placeholder")));
+        assert!(weakness(Some("alexander-jackson/ptc"))
+            .is_some_and(|message| message.contains("shorter than 20 characters")));
+        assert!(weakness(Some("alexander-jackson/locker")).is_none());
     }
 
     #[test]
-    fn default_code_root_is_the_repository_root() {
-        let config = Config::from_str(CONFIG).unwrap();
-        let code_root = config.resolve_code_root("alexander-jackson/locker");
+    fn github_tokens_are_redacted() {
+        let config = Config::from_str(
+            r#"
+default:
+    ssh_private_key: "/root/.ssh/id_rsa"
+    repo_root: "/root"
+    cargo_path: "/root/.cargo/bin/cargo"
+    github:
+        token: "ghp_0123456789abcdef"
+"#,
+        )
+        .unwrap();
 
-        assert_eq!(code_root, PathBuf::new());
+        let github = config.default.github.as_ref().unwrap();
+
+        assert_eq!(github.token.as_str(), "ghp_0123456789abcdef");
+        assert!(!format!("{:?}", config).contains("ghp_0123456789abcdef"));
     }
 
     #[test]
-    fn binaries_resolve_correctly() {
-        let config = Config::from_str(CONFIG).unwrap();
-        let binaries = config.resolve_binaries("FreddieBrown/dodona");
+    fn admin_tokens_are_redacted() {
+        let config = Config::from_str(
+            r#"
+default:
+    ssh_private_key: "/root/.ssh/id_rsa"
+    repo_root: "/root"
+    cargo_path: "/root/.cargo/bin/cargo"
+    admin_token: "8a1c5e0f2b7d4936"
+"#,
+        )
+        .unwrap();
 
-        assert_eq!(binaries, vec!["api-server", "dcl"]);
+        assert_eq!(config.admin_token(), Some("8a1c5e0f2b7d4936"));
+        assert!(!format!("{:?}", config).contains("8a1c5e0f2b7d4936"));
     }
 
     #[test]
-    fn binaries_are_assumed_to_be_repository_name_if_not_specified() {
-        let config = Config::from_str(CONFIG).unwrap();
-        let binaries = config.resolve_binaries("alexander-jackson/ptc");
+    fn ssh_key_passphrases_are_redacted() {
+        let config = Config::from_str(
+            r#"
+default:
+    ssh_private_key: "/root/.ssh/id_rsa"
+    ssh_private_key_passphrase: "correct horse battery staple"
+    repo_root: "/root"
+    cargo_path: "/root/.cargo/bin/cargo"
 
-        assert_eq!(binaries, vec!["ptc"]);
+specific:
+    alexander-jackson/ptc:
+        clone_depth: 1
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.ssh_private_key_passphrase(),
+            Some("correct horse battery staple")
+        );
+        assert!(!format!("{:?}", config).contains("correct horse battery staple"));
+
+        // Shallow fetches authenticate in the same way, so they can use the passphrase too
+        let mistakes = config.find_potential_mistakes();
+        assert!(!mistakes
+            .iter()
+            .any(|mistake| mistake.message.contains("`clone_depth`")));
     }
 
     #[test]
-    fn config_with_no_secret_assumes_no_security() {
+    fn command_contexts_leave_out_unknown_variables() {
+        let mut context = CommandContext::new("alexander-jackson/ptc");
+        assert_eq!(context.env(), [("FISHERMAN_REPO", "alexander-jackson/ptc")]);
+
+        context.branch = Some("master");
+        context.author = Some("Alexander Jackson");
+
+        assert_eq!(
+            context.env(),
+            [
+                ("FISHERMAN_REPO", "alexander-jackson/ptc"),
+                ("FISHERMAN_BRANCH", "master"),
+                ("FISHERMAN_AUTHOR", "Alexander Jackson"),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_configs_are_reported_as_empty() {
+        for content in ["", "  \n\n", "# fisherman.yml\n# default:\n"] {
+            let error = Config::from_str(content).unwrap_err();
+            assert!(error.to_string().starts_with("the config is empty"));
+        }
+
+        let error = Config::from_str("specific: {}").unwrap_err();
+        assert!(!error.to_string().starts_with("the config is empty"));
+    }
+
+    #[test]
+    fn ssh_private_keys_can_be_left_to_the_agent() {
+        let config = Config::from_str(
+            r#"
+default:
+    repo_root: "/root"
+    cargo_path: "/root/.cargo/bin/cargo"
+"#,
+        )
+        .unwrap();
+
+        assert!(config.default.ssh_private_key.is_none());
+
+        let mistakes = config.find_potential_mistakes();
+        assert!(!mistakes
+            .iter()
+            .any(|mistake| mistake.message.contains("`ssh_private_key`")));
+    }
+
+    #[test]
+    fn restarts_can_be_verified() {
         let config = Config::from_str(CONFIG).unwrap();
-        let secret = config.resolve_secret("alexander-jackson/ptc");
+        let verify = config
+            .resolve_verify_restart("alexander-jackson/se-powerlifting-website")
+            .unwrap();
 
-        assert!(secret.is_none());
+        assert_eq!(verify.timeout(), Duration::from_secs(60));
+        assert_eq!(verify.settle(), Duration::from_secs(30));
+        assert_eq!(verify.interval(), Duration::from_secs(1));
+
+        assert!(config
+            .resolve_verify_restart("FreddieBrown/dodona")
+            .is_none());
+
+        assert!(VerifyRestart::is_running(
+            "ptc                              RUNNING   pid 1234, uptime 0:00:12"
+        ));
+        assert!(!VerifyRestart::is_running(
+            "ptc                              BACKOFF   Exited too quickly (process log may have details)"
+        ));
+        assert!(!VerifyRestart::is_running("ptc: ERROR (no such process)"));
     }
 
     #[test]
-    fn no_specific_secret_assumes_global_value() {
-        let config = r#"
-        default:
-            ssh_private_key: "/root/.ssh/id_rsa"
-            repo_root: "/root"
-            cargo_path: "/root/.cargo/bin/cargo"
-            secret: "<some secret value>"
-        "#;
+    fn strategies_fall_back_to_the_global_value() {
+        let config = Config::from_str(
+            r#"
+default:
+    ssh_private_key: "/root/.ssh/id_rsa"
+    repo_root: "/root"
+    cargo_path: "/root/.cargo/bin/cargo"
+    strategy: "reset"
 
-        let config = Config::from_str(config).unwrap();
-        let secret = config.resolve_secret("alexander-jackson/ptc");
+specific:
+    alexander-jackson/ptc:
+        strategy: "merge"
+    alexander-jackson/locker:
+        clone_depth: 1
+"#,
+        )
+        .unwrap();
 
-        assert_eq!(secret, Some("<some secret value>"));
+        assert_eq!(
+            config.resolve_strategy("alexander-jackson/ptc"),
+            Strategy::Merge
+        );
+        assert_eq!(
+            config.resolve_strategy("alexander-jackson/locker"),
+            Strategy::Reset
+        );
+
+        let config = Config::from_str(CONFIG).unwrap();
+        assert_eq!(
+            config.resolve_strategy("alexander-jackson/ptc"),
+            Strategy::Merge
+        );
     }
 
     #[test]
-    fn specific_secrets_are_used_if_they_exist() {
-        let config = r#"
-        default:
-            ssh_private_key: "/root/.ssh/id_rsa"
-            repo_root: "/root"
-            cargo_path: "/root/.cargo/bin/cargo"
+    fn status_commands_default_to_supervisor() {
+        let config = Config::from_str(
+            r#"
+default:
+    ssh_private_key: "/root/.ssh/id_rsa"
+    repo_root: "/root"
+    cargo_path: "/root/.cargo/bin/cargo"
+    restart_command:
+        program: "systemctl"
+        args: ["restart", "{binary}.service"]
 
-        specific:
-            alexander-jackson/ptc:
-                secret: "<repository specific>"
-        "#;
+specific:
+    alexander-jackson/ptc:
+        status_command:
+            program: "systemctl"
+            args: ["is-active", "{binary}.service"]
+        verify_restart:
+            settle_secs: 30
+    alexander-jackson/locker:
+        verify_restart:
+            settle_secs: 30
+"#,
+        )
+        .unwrap();
 
-        let config = Config::from_str(config).unwrap();
-        let secret = config.resolve_secret("alexander-jackson/ptc");
+        assert_eq!(
+            config.resolve_status_command("alexander-jackson/ptc", "ptc"),
+            (
+                String::from("systemctl"),
+                vec![String::from("is-active"), String::from("ptc.service")]
+            )
+        );
+        assert_eq!(
+            config.resolve_status_command("alexander-jackson/locker", "locker"),
+            (
+                String::from("supervisorctl"),
+                vec![String::from("status"), String::from("locker")]
+            )
+        );
+        assert!(!config.has_supervisor_status("alexander-jackson/ptc"));
+        assert!(!config.has_supervisor_restarts("alexander-jackson/ptc"));
 
-        assert_eq!(secret, Some("<repository specific>"));
+        // Restarting with `systemctl` but checking with `supervisorctl` will fail every deploy
+        let mismatched: Vec<_> = config
+            .find_potential_mistakes()
+            .into_iter()
+            .filter(|mistake| mistake.message.contains("`status_command`"))
+            .map(|mistake| mistake.key)
+            .collect();
+
+        assert_eq!(
+            mismatched,
+            vec![Some(String::from("alexander-jackson/locker"))]
+        );
     }
 
     #[test]
-    fn master_is_followed_if_unspecified() {
+    fn restart_commands_default_to_supervisor() {
+        let config = Config::from_str(
+            r#"
+default:
+    ssh_private_key: "/root/.ssh/id_rsa"
+    repo_root: "/root"
+    cargo_path: "/root/.cargo/bin/cargo"
+
+specific:
+    alexander-jackson/ptc:
+        restart_command:
+            program: "systemctl"
+            args: ["restart", "{binary}.service"]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.resolve_restart_command("alexander-jackson/ptc", "ptc"),
+            (
+                String::from("systemctl"),
+                vec![String::from("restart"), String::from("ptc.service")]
+            )
+        );
+        assert_eq!(
+            config.resolve_restart_command("alexander-jackson/locker", "locker"),
+            (
+                String::from("supervisorctl"),
+                vec![String::from("restart"), String::from("locker")]
+            )
+        );
+    }
+
+    #[test]
+    fn configs_are_upgraded_to_the_current_version() {
         let config = Config::from_str(CONFIG).unwrap();
-        let follow_branch = config.resolve_follow_branch("alexander-jackson/ptc");
+        assert_eq!(config.version, CURRENT_VERSION);
 
-        assert_eq!(follow_branch, "master");
+        let versioned = format!("version: {CURRENT_VERSION}\n{CONFIG}");
+        let config = Config::from_str(&versioned).unwrap();
+        assert_eq!(config.version, CURRENT_VERSION);
     }
 
     #[test]
-    fn specific_branches_can_be_followed() {
+    fn migrations_are_applied_from_the_version_of_the_config() {
+        fn rename_listen_port(value: &mut serde_yaml::Value) {
+            let default = value
+                .get_mut("default")
+                .and_then(serde_yaml::Value::as_mapping_mut);
+
+            if let Some(default) = default {
+                if let Some(port) = default.remove("listen_port") {
+                    default.insert("port".into(), port);
+                }
+            }
+        }
+
+        fn add_bind_address(value: &mut serde_yaml::Value) {
+            value["default"]["bind_address"] = "0.0.0.0".into();
+        }
+
+        let migrations: &[Migration] = &[rename_listen_port, add_bind_address];
+
+        let mut value: serde_yaml::Value =
+            serde_yaml::from_str("version: 1\ndefault:\n    listen_port: 8000").unwrap();
+        assert!(migrate(&mut value, migrations).unwrap());
+        assert_eq!(value["version"], 3);
+        assert_eq!(value["default"]["port"], 8000);
+        assert_eq!(value["default"]["bind_address"], "0.0.0.0");
+
+        // Only the migrations after the version of the config are applied
+        let mut value: serde_yaml::Value =
+            serde_yaml::from_str("version: 2\ndefault:\n    listen_port: 8000").unwrap();
+        assert!(migrate(&mut value, migrations).unwrap());
+        assert_eq!(value["default"]["listen_port"], 8000);
+        assert_eq!(value["default"]["bind_address"], "0.0.0.0");
+
+        let mut value: serde_yaml::Value = serde_yaml::from_str("version: 3").unwrap();
+        assert!(!migrate(&mut value, migrations).unwrap());
+    }
+
+    #[test]
+    fn errors_in_current_configs_have_locations() {
+        let invalid = CONFIG.replace(
+            "    cargo_path: \"/root/.cargo/bin/cargo\"\n",
+            "    cargo_path: \"/root/.cargo/bin/cargo\"\n    port: \"http\"\n",
+        );
+        let error = Config::from_str(&invalid).unwrap_err();
+
+        assert!(error.location().is_some());
+    }
+
+    #[test]
+    fn configs_from_newer_versions_are_rejected() {
+        let newer = format!("version: {}\n{CONFIG}", CURRENT_VERSION + 1);
+        let error = Config::from_str(&newer).unwrap_err();
+        assert!(error.to_string().contains("upgrade fisherman"));
+
+        for version in ["0", "latest", "-1"] {
+            let invalid = format!("version: {version}\n{CONFIG}");
+            assert!(Config::from_str(&invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn build_commands_run_the_build_stage_without_cargo() {
         let config = Config::from_str(CONFIG).unwrap();
-        let follow_branch = config.resolve_follow_branch("FreddieBrown/dodona");
+        let repository = "alexander-jackson/se-powerlifting-website";
+
+        assert!(config.resolve_build_commands(repository).is_none());
+        assert!(!config.should_run_stage(repository, Stage::Build));
+
+        let config = Config::from_str(&CONFIG.replace(
+            "should_build_binaries: false",
+            "should_build_binaries: false
+        build_commands:
+            - program: \"make\"
+              args: [\"release\"]",
+        ))
+        .unwrap();
+
+        assert!(config.resolve_build_commands(repository).is_some());
+        assert!(config.should_run_stage(repository, Stage::Build));
+        assert!(!config.should_run_stage(repository, Stage::Restart));
+    }
 
-        assert_eq!(follow_branch, "develop");
+    #[test]
+    fn missing_build_programs_are_mistakes() {
+        let config = Config::from_str(&CONFIG.replace(
+            "should_build_binaries: false",
+            "should_build_binaries: false
+        build_commands:
+            - program: \"mkae\"
+              args: [\"release\"]",
+        ))
+        .unwrap();
+
+        let mistakes = config.find_potential_mistakes();
+
+        assert!(mistakes.iter().any(|mistake| {
+            mistake.key.as_deref() == Some("alexander-jackson/se-powerlifting-website")
+                && mistake.message
+                    == "`build_commands` program `mkae` could not be found, it will fail when run"
+        }));
     }
 
     #[test]
-    fn binaries_are_built_if_not_specified() {
+    fn cargo_features_and_profiles_can_be_specified() {
         let config = Config::from_str(CONFIG).unwrap();
-        let should_build_binaries = config.should_build_binaries("FreddieBrown/dodona");
+        let repository = "alexander-jackson/fisherman";
 
-        assert!(should_build_binaries);
+        assert!(config.resolve_cargo_features(repository).is_empty());
+        assert_eq!(config.resolve_cargo_profile(repository), None);
+
+        let config = Config::from_str(&CONFIG.replace(
+            "should_build_binaries: false",
+            "should_build_binaries: false
+        cargo_features: [\"production\", \"metrics\"]
+        cargo_profile: \"production\"",
+        ))
+        .unwrap();
+        let repository = "alexander-jackson/se-powerlifting-website";
+
+        assert_eq!(
+            config.resolve_cargo_features(repository),
+            ["production", "metrics"]
+        );
+        assert_eq!(config.resolve_cargo_profile(repository), Some("production"));
+        assert!(config
+            .resolve_binary_path(repository, "server")
+            .ends_with("target/production/server"));
     }
 
     #[test]
-    fn binaries_are_not_built_based_on_config() {
+    fn binary_failures_fail_deploys_by_default() {
         let config = Config::from_str(CONFIG).unwrap();
-        let should_build_binaries =
-            config.should_build_binaries("alexander-jackson/se-powerlifting-website");
+        assert!(!config.should_continue_on_binary_failure("alexander-jackson/fisherman"));
 
-        assert!(!should_build_binaries);
+        let config = Config::from_str(&CONFIG.replace(
+            "should_build_binaries: false",
+            "should_build_binaries: false
+        continue_on_binary_failure: true",
+        ))
+        .unwrap();
+
+        assert!(
+            config.should_continue_on_binary_failure("alexander-jackson/se-powerlifting-website")
+        );
+    }
+
+    #[test]
+    fn followed_patterns_are_not_primary_branches() {
+        let config = Config::from_str(
+            r#"
+default:
+    ssh_private_key: "/root/.ssh/id_rsa"
+    repo_root: "/root"
+    cargo_path: "/root/.cargo/bin/cargo"
+
+specific:
+    alexander-jackson/ptc:
+        follow: ["release/*", "main"]
+        match_mode: "glob"
+    alexander-jackson/locker:
+        follow: ["release-1", "main"]
+        match_mode: "regex"
+"#,
+        )
+        .unwrap();
+
+        let error = config
+            .resolve_primary_branch("alexander-jackson/ptc")
+            .unwrap_err();
+        assert!(error.to_string().contains("`release/*`"));
+
+        assert_eq!(
+            config
+                .resolve_primary_branch("alexander-jackson/locker")
+                .unwrap(),
+            "release-1"
+        );
     }
 }
@@ -2,11 +2,16 @@ use std::fmt;
 
 use actix_web::{body::BoxBody, http::StatusCode, HttpResponse, ResponseError};
 
+use crate::config::Stage;
+
 #[derive(Copy, Clone, Debug)]
 pub enum ServerError {
     BadRequest,
     Unauthorized,
+    NotFound,
+    Conflict,
     UnprocessableEntity,
+    ServiceUnavailable,
 }
 
 impl fmt::Display for ServerError {
@@ -14,7 +19,10 @@ impl fmt::Display for ServerError {
         let message = match self {
             Self::BadRequest => "Bad Request",
             Self::Unauthorized => "Unauthorized",
+            Self::NotFound => "Not Found",
+            Self::Conflict => "Conflict",
             Self::UnprocessableEntity => "Unprocessable Entity",
+            Self::ServiceUnavailable => "Service Unavailable",
         };
 
         write!(f, "{}", message)
@@ -26,7 +34,10 @@ impl ResponseError for ServerError {
         match self {
             Self::BadRequest => StatusCode::BAD_REQUEST,
             Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Conflict => StatusCode::CONFLICT,
             Self::UnprocessableEntity => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
         }
     }
 
@@ -34,3 +45,24 @@ impl ResponseError for ServerError {
         HttpResponse::build(self.status_code()).body(self.to_string())
     }
 }
+
+/// An error that occurred while running a specific stage of a deploy.
+#[derive(Debug)]
+pub struct DeployError {
+    /// The stage that failed
+    pub stage: Stage,
+    /// Why the stage failed
+    pub source: anyhow::Error,
+}
+
+impl fmt::Display for DeployError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl std::error::Error for DeployError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
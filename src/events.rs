@@ -0,0 +1,563 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::NaiveDateTime;
+
+use rand::Rng;
+use tokio::sync::oneshot;
+
+use crate::webhook::{Outcome, Webhook, WebhookVariant};
+
+/// The number of events to keep before the oldest are discarded.
+const DEFAULT_CAPACITY: usize = 100;
+
+tokio::task_local! {
+    /// The correlation identifier of the delivery being processed by the current task, if any.
+    pub static CORRELATION: String;
+}
+
+/// Creates a short identifier that ties together the activity caused by a single delivery.
+///
+/// This is the start of the delivery identifier if there is one, as GitHub's are UUIDs, otherwise
+/// it is generated randomly.
+pub fn correlation_id(delivery_id: Option<&str>) -> String {
+    match delivery_id.and_then(|id| id.get(..8)) {
+        Some(prefix) => prefix.to_owned(),
+        None => format!("{:08x}", rand::thread_rng().gen::<u32>()),
+    }
+}
+
+/// A verified webhook waiting to be processed, along with the correlation identifier of its
+/// delivery.
+#[derive(Debug)]
+pub struct Delivery {
+    /// Ties together the activity caused by the delivery, such as in logs and events
+    pub correlation: String,
+    /// The webhook to process
+    pub webhook: Webhook,
+    /// The requests waiting for the outcome of processing the webhook, such as synchronous ones
+    pub replies: Vec<oneshot::Sender<Outcome>>,
+}
+
+impl Delivery {
+    /// Creates a delivery that nothing is waiting for the outcome of.
+    pub fn new(correlation: String, webhook: Webhook) -> Self {
+        Self {
+            correlation,
+            webhook,
+            replies: Vec::new(),
+        }
+    }
+}
+
+/// A webhook that was received and verified, along with when it arrived.
+#[derive(Clone, Debug, Serialize)]
+pub struct Event {
+    /// The identifier GitHub assigned to the delivery, if one was provided
+    pub delivery_id: Option<String>,
+    /// Ties together the activity caused by the delivery, such as in logs
+    pub correlation: String,
+    /// When the webhook was received, in seconds since the Unix epoch
+    pub received_at: u64,
+    /// The full name of the repository the webhook relates to
+    pub repository: String,
+    /// The kind of webhook that was received
+    pub kind: WebhookVariant,
+    /// The webhook itself, which can be processed again to replay the event
+    #[serde(skip)]
+    pub webhook: Webhook,
+}
+
+/// Restricts which events are returned when querying the log.
+#[derive(Debug, Default, Deserialize)]
+pub struct EventFilter {
+    /// Only include events for this repository
+    pub repository: Option<String>,
+    /// Only include events of this kind
+    pub kind: Option<WebhookVariant>,
+    /// Only include events with this correlation identifier
+    pub correlation: Option<String>,
+    /// Only include events received at or after this time, in seconds since the Unix epoch
+    pub since: Option<u64>,
+    /// Skip this many of the most recent matching events, for fetching later pages
+    pub offset: Option<usize>,
+    /// Only include this many of the most recent matching events
+    pub limit: Option<usize>,
+}
+
+/// Something a deploy did, recorded once it has finished.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "stage", content = "binary", rename_all = "snake_case")]
+pub enum Activity {
+    /// The new changes were pulled
+    Pull,
+    /// A binary was built
+    Build(String),
+    /// A binary was restarted
+    Restart(String),
+}
+
+impl fmt::Display for Activity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pull => write!(f, "pulled"),
+            Self::Build(binary) => write!(f, "built {}", binary),
+            Self::Restart(binary) => write!(f, "restarted {}", binary),
+        }
+    }
+}
+
+/// A stage of a deploy that finished, along with the delivery that caused it.
+#[derive(Clone, Debug, Serialize)]
+pub struct StageEvent {
+    /// Ties the stage to the delivery that caused it, unless it was deployed without one
+    pub correlation: Option<String>,
+    /// When the stage finished, in seconds since the Unix epoch
+    pub finished_at: u64,
+    /// The full name of the repository that was deployed
+    pub repository: String,
+    /// What the deploy did
+    #[serde(flatten)]
+    pub activity: Activity,
+}
+
+/// A page of the events matching a filter.
+#[derive(Debug, Serialize)]
+pub struct EventPage<'a> {
+    /// The number of events matching the filter, across every page
+    pub total: usize,
+    /// The number of more recent matching events that were skipped
+    pub offset: usize,
+    /// The events on this page, from oldest to newest
+    pub events: Vec<&'a Event>,
+    /// The stages of deploys on this page, from oldest to newest
+    pub stages: Vec<StageEvent>,
+}
+
+impl fmt::Display for EventPage<'_> {
+    /// Renders a line for each event and stage on the page, from oldest to newest.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let events = self.events.iter().map(|event| {
+            let line = format!(
+                "{} {} received {}",
+                event.correlation,
+                event.repository,
+                kind_name(&event.kind)
+            );
+
+            (event.received_at, line)
+        });
+
+        let stages = self.stages.iter().map(|stage| {
+            let line = format!(
+                "{} {} {}",
+                stage.correlation.as_deref().unwrap_or("-"),
+                stage.repository,
+                stage.activity
+            );
+
+            (stage.finished_at, line)
+        });
+
+        let mut lines: Vec<_> = events.chain(stages).collect();
+
+        // Webhooks are always received before the stages they cause
+        lines.sort_by_key(|(time, _)| *time);
+
+        for (time, line) in lines {
+            let time = NaiveDateTime::from_timestamp_opt(time as i64, 0).unwrap_or_default();
+            writeln!(f, "{} {}", time.format("%Y-%m-%d %H:%M:%S"), line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Gets the name a webhook variant is serialized with, such as `push`.
+fn kind_name(kind: &WebhookVariant) -> String {
+    serde_json::to_value(kind)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_owned))
+        .unwrap_or_default()
+}
+
+impl EventFilter {
+    /// Checks whether an event should be included.
+    fn matches(&self, event: &Event) -> bool {
+        self.repository
+            .as_deref()
+            .is_none_or(|repository| event.repository == repository)
+            && self.kind.is_none_or(|kind| event.kind == kind)
+            && self
+                .correlation
+                .as_deref()
+                .is_none_or(|correlation| event.correlation == correlation)
+            && self.since.is_none_or(|since| event.received_at >= since)
+    }
+
+    /// Checks whether a stage of a deploy should be included.
+    ///
+    /// Stages are not webhooks, so filtering by `kind` excludes all of them.
+    fn matches_stage(&self, stage: &StageEvent) -> bool {
+        self.repository
+            .as_deref()
+            .is_none_or(|repository| stage.repository == repository)
+            && self.kind.is_none()
+            && self
+                .correlation
+                .as_deref()
+                .is_none_or(|correlation| stage.correlation.as_deref() == Some(correlation))
+            && self.since.is_none_or(|since| stage.finished_at >= since)
+    }
+}
+
+/// An in-memory log of the most recently received webhooks.
+#[derive(Debug)]
+pub struct EventLog {
+    capacity: usize,
+    events: VecDeque<Event>,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl EventLog {
+    /// Creates an empty log that holds at most `capacity` events.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity,
+            events: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Records a delivery in the log, discarding the oldest event if the log is full.
+    pub fn record(&mut self, delivery_id: Option<String>, delivery: &Delivery) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+
+        let received_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+
+        let webhook = delivery.webhook.clone();
+
+        self.events.push_back(Event {
+            delivery_id,
+            correlation: delivery.correlation.clone(),
+            received_at,
+            repository: webhook.get_full_name().to_owned(),
+            kind: webhook.variant(),
+            webhook,
+        });
+    }
+
+    /// Finds the most recent event with the given delivery identifier.
+    pub fn find(&self, delivery_id: &str) -> Option<&Event> {
+        self.events
+            .iter()
+            .rev()
+            .find(|event| event.delivery_id.as_deref() == Some(delivery_id))
+    }
+
+    /// Gets a page of the events matching a filter.
+    ///
+    /// Pages start from the most recent events, skipping `offset` of them before taking at most
+    /// `limit`, but the events within a page are ordered from oldest to newest.
+    pub fn query(&self, filter: &EventFilter) -> EventPage<'_> {
+        let matching: Vec<_> = self
+            .events
+            .iter()
+            .rev()
+            .filter(|event| filter.matches(event))
+            .collect();
+
+        let offset = filter.offset.unwrap_or(0);
+
+        let mut events: Vec<_> = matching
+            .iter()
+            .copied()
+            .skip(offset)
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .collect();
+
+        events.reverse();
+
+        EventPage {
+            total: matching.len(),
+            offset,
+            events,
+            stages: Vec::new(),
+        }
+    }
+}
+
+/// An in-memory log of the stages of the most recent deploys.
+///
+/// Deploys record their stages here as they finish, tagged with the correlation identifier of the
+/// delivery being processed.
+#[derive(Debug)]
+pub struct StageLog {
+    capacity: usize,
+    stages: Mutex<VecDeque<StageEvent>>,
+}
+
+impl Default for StageLog {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl StageLog {
+    /// Creates an empty log that holds at most `capacity` stages.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity,
+            stages: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Records a finished stage of a deploy, discarding the oldest if the log is full.
+    pub fn record(&self, repository: &str, activity: Activity) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut stages = self.stages.lock().unwrap_or_else(|e| e.into_inner());
+
+        if stages.len() == self.capacity {
+            stages.pop_front();
+        }
+
+        let finished_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+
+        stages.push_back(StageEvent {
+            correlation: CORRELATION.try_with(Clone::clone).ok(),
+            finished_at,
+            repository: repository.to_owned(),
+            activity,
+        });
+    }
+
+    /// Gets the stages matching a filter, paged in the same way as [`EventLog::query`].
+    pub fn query(&self, filter: &EventFilter) -> Vec<StageEvent> {
+        let stages = self.stages.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut matching: Vec<_> = stages
+            .iter()
+            .rev()
+            .filter(|stage| filter.matches_stage(stage))
+            .skip(filter.offset.unwrap_or(0))
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect();
+
+        matching.reverse();
+        matching
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::events::{
+        correlation_id, Activity, Delivery, EventFilter, EventLog, StageLog, CORRELATION,
+    };
+    use crate::webhook::{Webhook, WebhookVariant};
+
+    static SAMPLE_PAYLOAD: &[u8] = include_bytes!("../sample_payload.json");
+
+    fn ping() -> Delivery {
+        Delivery::new(
+            String::from("72d3162e"),
+            Webhook::from_slice(WebhookVariant::Ping, SAMPLE_PAYLOAD).unwrap(),
+        )
+    }
+
+    fn delivery(id: &str) -> Option<String> {
+        Some(String::from(id))
+    }
+
+    #[test]
+    fn events_can_be_found_by_delivery_id() {
+        let mut log = EventLog::default();
+
+        log.record(delivery("first"), &ping());
+        log.record(None, &ping());
+
+        assert!(log.find("first").is_some());
+        assert!(log.find("second").is_none());
+    }
+
+    #[test]
+    fn oldest_events_are_discarded_when_full() {
+        let mut log = EventLog::with_capacity(2);
+
+        log.record(delivery("first"), &ping());
+        log.record(delivery("second"), &ping());
+        log.record(delivery("third"), &ping());
+
+        assert!(log.find("first").is_none());
+        assert!(log.find("second").is_some());
+        assert!(log.find("third").is_some());
+    }
+
+    #[test]
+    fn events_can_be_filtered() {
+        let mut log = EventLog::default();
+
+        log.record(delivery("first"), &ping());
+        log.record(delivery("second"), &ping());
+
+        let matching = |filter: EventFilter| log.query(&filter).events.len();
+
+        assert_eq!(matching(EventFilter::default()), 2);
+
+        let filter = EventFilter {
+            repository: Some(String::from("alexander-jackson/simple")),
+            kind: Some(WebhookVariant::Ping),
+            correlation: Some(String::from("72d3162e")),
+            since: Some(0),
+            offset: None,
+            limit: None,
+        };
+
+        assert_eq!(matching(filter), 2);
+
+        let filter = EventFilter {
+            repository: Some(String::from("alexander-jackson/fisherman")),
+            ..EventFilter::default()
+        };
+
+        assert_eq!(matching(filter), 0);
+
+        let filter = EventFilter {
+            kind: Some(WebhookVariant::Push),
+            ..EventFilter::default()
+        };
+
+        assert_eq!(matching(filter), 0);
+
+        let filter = EventFilter {
+            correlation: Some(String::from("0f1e2d3c")),
+            ..EventFilter::default()
+        };
+
+        assert_eq!(matching(filter), 0);
+
+        let filter = EventFilter {
+            since: Some(u64::MAX),
+            ..EventFilter::default()
+        };
+
+        assert_eq!(matching(filter), 0);
+    }
+
+    #[test]
+    fn limits_keep_the_most_recent_events() {
+        let mut log = EventLog::default();
+
+        log.record(delivery("first"), &ping());
+        log.record(delivery("second"), &ping());
+        log.record(delivery("third"), &ping());
+
+        let filter = EventFilter {
+            limit: Some(2),
+            ..EventFilter::default()
+        };
+
+        let ids: Vec<_> = log
+            .query(&filter)
+            .events
+            .into_iter()
+            .filter_map(|event| event.delivery_id.as_deref())
+            .collect();
+
+        assert_eq!(ids, ["second", "third"]);
+    }
+
+    #[test]
+    fn offsets_page_through_older_events() {
+        let mut log = EventLog::default();
+
+        log.record(delivery("first"), &ping());
+        log.record(delivery("second"), &ping());
+        log.record(delivery("third"), &ping());
+
+        let filter = EventFilter {
+            offset: Some(2),
+            limit: Some(2),
+            ..EventFilter::default()
+        };
+
+        let page = log.query(&filter);
+
+        let ids: Vec<_> = page
+            .events
+            .iter()
+            .filter_map(|event| event.delivery_id.as_deref())
+            .collect();
+
+        assert_eq!(page.total, 3);
+        assert_eq!(page.offset, 2);
+        assert_eq!(ids, ["first"]);
+    }
+
+    #[test]
+    fn correlation_ids_start_with_the_delivery_id() {
+        assert_eq!(
+            correlation_id(Some("72d3162e-cc78-11e3-81ab-4c9367dc0958")),
+            "72d3162e"
+        );
+
+        assert_eq!(correlation_id(Some("short")).len(), 8);
+        assert_eq!(correlation_id(None).len(), 8);
+    }
+
+    #[tokio::test]
+    async fn stages_are_tagged_with_the_delivery_being_processed() {
+        let log = StageLog::default();
+
+        log.record("alexander-jackson/simple", Activity::Pull);
+
+        CORRELATION
+            .scope(String::from("72d3162e"), async {
+                log.record(
+                    "alexander-jackson/simple",
+                    Activity::Build(String::from("app")),
+                );
+            })
+            .await;
+
+        let filter = EventFilter {
+            correlation: Some(String::from("72d3162e")),
+            ..EventFilter::default()
+        };
+
+        let activities: Vec<_> = log
+            .query(&filter)
+            .into_iter()
+            .map(|stage| stage.activity)
+            .collect();
+
+        assert_eq!(activities, [Activity::Build(String::from("app"))]);
+        assert_eq!(log.query(&EventFilter::default()).len(), 2);
+
+        let filter = EventFilter {
+            kind: Some(WebhookVariant::Push),
+            ..EventFilter::default()
+        };
+
+        assert!(log.query(&filter).is_empty());
+    }
+}
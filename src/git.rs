@@ -1,26 +1,185 @@
 use std::path::Path;
 
-/// Fetches the changes for a set of branches from a remote.
-pub fn fetch<'a>(
+/// Splits a remote URL into its host and path, ignoring the scheme, user, port and `.git` suffix.
+///
+/// This allows SSH and HTTPS URLs for the same repository to be compared, such as
+/// `git@github.com:owner/repo.git` and `https://github.com/owner/repo`.
+fn normalize_url(url: &str) -> Option<(String, String)> {
+    let (rest, scp_like) = match url.split_once("://") {
+        Some((_, rest)) => (rest, false),
+        None => (url, true),
+    };
+
+    // Remove any user information before the host
+    let rest = rest.rsplit_once('@').map_or(rest, |(_, rest)| rest);
+
+    let (host, path) = if scp_like {
+        rest.split_once(':')?
+    } else {
+        rest.split_once('/')?
+    };
+
+    let host = host.split(':').next()?.to_lowercase();
+    let path = path.trim_matches('/').trim_end_matches(".git").to_owned();
+
+    Some((host, path))
+}
+
+/// Gets the host of a remote URL, such as `github.com`, if it has one.
+pub fn host(url: &str) -> Option<String> {
+    normalize_url(url).map(|(host, _)| host)
+}
+
+/// Finds the remote of a repository whose URL matches any of the given URLs.
+///
+/// Mirrored repositories may have several remotes, so this picks the one the webhook came from.
+/// If none of them match, `origin` will be used instead.
+pub fn find_matching_remote<'a>(
     repo: &'a git2::Repository,
-    refs: &[&str],
-    remote: &'a mut git2::Remote,
-    ssh_private_key_path: &'a Path,
-) -> Result<git2::AnnotatedCommit<'a>, git2::Error> {
+    urls: &[&str],
+) -> Result<git2::Remote<'a>, git2::Error> {
+    let targets: Vec<_> = urls.iter().filter_map(|url| normalize_url(url)).collect();
+
+    for name in repo.remotes()?.iter().flatten() {
+        let remote = repo.find_remote(name)?;
+
+        let matches = remote
+            .url()
+            .and_then(normalize_url)
+            .is_some_and(|url| targets.contains(&url));
+
+        if matches {
+            tracing::debug!(%name, "Found a remote matching the repository");
+            return Ok(remote);
+        }
+    }
+
+    tracing::warn!(
+        ?urls,
+        "No remote matched the repository, falling back to `origin`"
+    );
+
+    repo.find_remote("origin")
+}
+
+/// Creates the callbacks for connecting to a remote, authenticating with the given SSH key and its
+/// passphrase if there is one.
+///
+/// Keys held by `ssh-agent` are tried if there is no key file or it is not accepted, so the key
+/// file can be left unset on hosts where the agent holds the deploy identity.
+fn remote_callbacks<'a>(
+    ssh_private_key_path: Option<&'a Path>,
+    passphrase: Option<&'a str>,
+) -> git2::RemoteCallbacks<'a> {
     let mut cb = git2::RemoteCallbacks::new();
 
+    // The callback is called again after each rejected credential, so track what has been tried
+    let mut tried_key_file = ssh_private_key_path.is_none();
+    let mut tried_agent = false;
+
     // Use SSH credentials for authentication
-    cb.credentials(|_url, username_from_url, _allowed_types| {
-        git2::Cred::ssh_key(username_from_url.unwrap(), None, ssh_private_key_path, None)
+    cb.credentials(move |_url, username_from_url, _allowed_types| {
+        let username = username_from_url.unwrap_or("git");
+
+        if let (false, Some(path)) = (tried_key_file, ssh_private_key_path) {
+            tried_key_file = true;
+            return git2::Cred::ssh_key(username, None, path, passphrase);
+        }
+
+        if !tried_agent {
+            tried_agent = true;
+            tracing::debug!("Trying the keys held by `ssh-agent` for authentication");
+            return git2::Cred::ssh_key_from_agent(username);
+        }
+
+        Err(git2::Error::from_str(
+            "Neither the SSH key nor `ssh-agent` were accepted for authentication",
+        ))
     });
 
+    cb
+}
+
+/// Creates the options for connecting through the given proxy, or one detected from the git config
+/// and environment.
+fn proxy_options<'a>(proxy_url: Option<&str>) -> git2::ProxyOptions<'a> {
+    let mut po = git2::ProxyOptions::new();
+
+    match proxy_url {
+        Some(url) => po.url(url),
+        None => po.auto(),
+    };
+
+    po
+}
+
+/// Creates the options for fetching from a remote, authenticating with the given SSH key and its
+/// passphrase if there is one.
+///
+/// If a `depth` is given, only that many of the most recent commits are fetched.
+fn fetch_options<'a>(
+    depth: Option<u32>,
+    ssh_private_key_path: Option<&'a Path>,
+    passphrase: Option<&'a str>,
+    proxy_url: Option<&str>,
+) -> git2::FetchOptions<'a> {
     let mut fo = git2::FetchOptions::new();
-    fo.remote_callbacks(cb);
+    fo.remote_callbacks(remote_callbacks(ssh_private_key_path, passphrase));
+    fo.proxy_options(proxy_options(proxy_url));
     fo.download_tags(git2::AutotagOption::All);
 
+    if let Some(depth) = depth {
+        fo.depth(i32::try_from(depth).unwrap_or(i32::MAX));
+    }
+
+    fo
+}
+
+/// Clones a repository into the given path, which should not already exist.
+///
+/// If a `depth` is given, only that many of the most recent commits are cloned.
+pub fn clone(
+    url: &str,
+    path: &Path,
+    depth: Option<u32>,
+    ssh_private_key_path: Option<&Path>,
+    passphrase: Option<&str>,
+    proxy_url: Option<&str>,
+) -> Result<git2::Repository, git2::Error> {
+    tracing::info!(%url, ?path, ?depth, "Cloning the repository");
+
+    git2::build::RepoBuilder::new()
+        .fetch_options(fetch_options(
+            depth,
+            ssh_private_key_path,
+            passphrase,
+            proxy_url,
+        ))
+        .clone(url, path)
+}
+
+/// Fetches the changes for a set of branches from a remote.
+///
+/// If a `depth` is given, only that many of the most recent commits are fetched.
+pub fn fetch<'a>(
+    repo: &'a git2::Repository,
+    refs: &[&str],
+    remote: &'a mut git2::Remote,
+    depth: Option<u32>,
+    ssh_private_key_path: Option<&'a Path>,
+    passphrase: Option<&'a str>,
+    proxy_url: Option<&str>,
+) -> Result<git2::AnnotatedCommit<'a>, git2::Error> {
+    let mut fo = fetch_options(depth, ssh_private_key_path, passphrase, proxy_url);
+
     let remote_name = remote.name().unwrap();
 
-    tracing::debug!(?remote_name, ?refs, "Fetching data for the repository");
+    tracing::debug!(
+        ?remote_name,
+        ?refs,
+        ?depth,
+        "Fetching data for the repository"
+    );
 
     remote.fetch(refs, Some(&mut fo), None)?;
 
@@ -39,6 +198,28 @@ pub fn fetch<'a>(
     repo.reference_to_annotated_commit(&fetch_head)
 }
 
+/// Gets the commit the default branch of a remote points to, without fetching anything.
+pub fn remote_head(
+    remote: &mut git2::Remote,
+    ssh_private_key_path: Option<&Path>,
+    passphrase: Option<&str>,
+    proxy_url: Option<&str>,
+) -> Result<git2::Oid, git2::Error> {
+    let connection = remote.connect_auth(
+        git2::Direction::Fetch,
+        Some(remote_callbacks(ssh_private_key_path, passphrase)),
+        Some(proxy_options(proxy_url)),
+    )?;
+
+    let head = connection
+        .list()?
+        .iter()
+        .find(|head| head.name() == "HEAD")
+        .map(git2::RemoteHead::oid);
+
+    head.ok_or_else(|| git2::Error::from_str("The remote has no default branch"))
+}
+
 /// Performs a fast-forward merge on a repository.
 fn fast_forward(
     repo: &git2::Repository,
@@ -56,6 +237,9 @@ fn fast_forward(
 }
 
 /// Performs a normal merge on a repository.
+///
+/// Merges that conflict fail without changing the checkout, so that conflicted files are never
+/// deployed.
 fn normal_merge(
     repo: &git2::Repository,
     local: &git2::AnnotatedCommit,
@@ -72,8 +256,23 @@ fn normal_merge(
 
     if idx.has_conflicts() {
         tracing::warn!(local_id = ?local.id(), remote_id = ?remote.id(), "Encountered conflicts between the two versions");
-        repo.checkout_index(Some(&mut idx), None)?;
-        return Ok(());
+
+        let paths: Vec<_> = idx
+            .conflicts()?
+            .filter_map(Result::ok)
+            .filter_map(|conflict| conflict.our.or(conflict.their))
+            .map(|entry| String::from_utf8_lossy(&entry.path).into_owned())
+            .collect();
+
+        // Leave the checkout as it was before the merge rather than building conflicted files
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))?;
+
+        return Err(git2::Error::from_str(&format!(
+            "Merging {} into {} conflicts in {}, resolve the conflicts or force-push the branch",
+            remote.id(),
+            local.id(),
+            paths.join(", ")
+        )));
     }
 
     let result_tree = repo.find_tree(idx.write_tree_to(repo)?)?;
@@ -101,24 +300,19 @@ fn normal_merge(
 }
 
 /// Performs a merge on a repository, whether that be a fast-forward or normal.
+///
+/// The fetched commit is always merged into the local branch, which is checked out first if
+/// something else is, such as after a manual deploy of another branch or commit.
 pub fn merge<'a>(
     repo: &'a git2::Repository,
     remote_branch: &str,
     fetch_commit: &git2::AnnotatedCommit<'a>,
 ) -> Result<(), git2::Error> {
-    // 1. do a merge analysis
-    let analysis = repo.merge_analysis(&[fetch_commit])?;
-
-    // 2. Do the appopriate merge
-    if analysis.0.is_fast_forward() {
-        // do a fast forward
-        let refname = format!("refs/heads/{}", remote_branch);
-
-        tracing::debug!(%remote_branch, %refname, "Performing a fast-forward merge");
+    let refname = format!("refs/heads/{}", remote_branch);
 
-        if let Ok(mut r) = repo.find_reference(&refname) {
-            fast_forward(repo, &mut r, fetch_commit)?;
-        } else {
+    let mut local = match repo.find_reference(&refname) {
+        Ok(local) => local,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => {
             // The branch doesn't exist so just set the reference to the
             // commit directly. Usually this is because you are pulling
             // into an empty repository.
@@ -135,12 +329,347 @@ pub fn merge<'a>(
                     .conflict_style_merge(true)
                     .force(),
             ))?;
+
+            return Ok(());
         }
+        Err(e) => return Err(e),
+    };
+
+    // Return to the branch if a manual deploy left something else checked out
+    if !repo
+        .head()
+        .is_ok_and(|head| head.name() == Some(refname.as_str()))
+    {
+        tracing::info!(%refname, "Checking out the branch before merging into it");
+
+        repo.set_head(&refname)?;
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))?;
+    }
+
+    // 1. do a merge analysis against the branch rather than whatever was checked out
+    let analysis = repo.merge_analysis_for_ref(&local, &[fetch_commit])?;
+
+    // 2. Do the appopriate merge
+    if analysis.0.is_fast_forward() {
+        tracing::debug!(%remote_branch, %refname, "Performing a fast-forward merge");
+
+        fast_forward(repo, &mut local, fetch_commit)?;
     } else if analysis.0.is_normal() {
         // do a normal merge
-        let head_commit = repo.reference_to_annotated_commit(&repo.head()?)?;
+        let head_commit = repo.reference_to_annotated_commit(&local)?;
         normal_merge(repo, &head_commit, fetch_commit)?;
     }
 
     Ok(())
 }
+
+/// Points a branch at the fetched commit and checks it out, discarding any local history.
+///
+/// Unlike [`merge`], this exactly matches the remote even if its history has been rewritten.
+pub fn reset_hard<'a>(
+    repo: &'a git2::Repository,
+    remote_branch: &str,
+    fetch_commit: &git2::AnnotatedCommit<'a>,
+) -> Result<(), git2::Error> {
+    let refname = format!("refs/heads/{}", remote_branch);
+    let commit = repo.find_commit(fetch_commit.id())?;
+
+    tracing::debug!(%remote_branch, %refname, "Resetting the branch to the fetched commit");
+
+    repo.reference(
+        &refname,
+        commit.id(),
+        true,
+        &format!("Resetting {} to {}", remote_branch, commit.id()),
+    )?;
+    repo.set_head(&refname)?;
+    repo.reset(commit.as_object(), git2::ResetType::Hard, None)?;
+
+    Ok(())
+}
+
+/// Checks out the fetched commit without a branch, such as when deploying a specific commit.
+pub fn checkout_detached<'a>(
+    repo: &'a git2::Repository,
+    fetch_commit: &git2::AnnotatedCommit<'a>,
+) -> Result<(), git2::Error> {
+    let commit = repo.find_commit(fetch_commit.id())?;
+
+    tracing::debug!(id = %commit.id(), "Checking out the fetched commit");
+
+    repo.set_head_detached(commit.id())?;
+    repo.reset(commit.as_object(), git2::ResetType::Hard, None)?;
+
+    Ok(())
+}
+
+/// Checks whether a reference is the full hexadecimal identifier of a commit.
+pub fn is_commit_id(reference: &str) -> bool {
+    reference.len() == 40 && reference.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Checks whether a name can be used as a branch, rejecting anything git would refuse.
+pub fn is_valid_branch_name(name: &str) -> bool {
+    git2::Reference::is_valid_name(&format!("refs/heads/{}", name))
+}
+
+/// Reads a file from the tree of a commit, returning nothing if it does not exist.
+pub fn read_file(
+    repo: &git2::Repository,
+    commit: git2::Oid,
+    path: &Path,
+) -> Result<Option<String>, git2::Error> {
+    let tree = repo.find_commit(commit)?.tree()?;
+
+    let entry = match tree.get_path(path) {
+        Ok(entry) => entry,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let blob = entry.to_object(repo)?.peel_to_blob()?;
+
+    Ok(Some(String::from_utf8_lossy(blob.content()).into_owned()))
+}
+
+/// Restores a repository to a pristine copy of its `HEAD`.
+///
+/// This is equivalent to `git reset --hard` followed by `git clean -xfd`, discarding any local
+/// modifications as well as untracked and ignored files such as previous build artifacts.
+pub fn clean(repo: &git2::Repository) -> Result<(), git2::Error> {
+    let head = repo.head()?.peel_to_commit()?;
+    repo.reset(head.as_object(), git2::ResetType::Hard, None)?;
+
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| git2::Error::from_str("Cannot clean a bare repository"))?;
+
+    let mut options = git2::StatusOptions::new();
+    options
+        .include_untracked(true)
+        .include_ignored(true)
+        .recurse_untracked_dirs(false);
+
+    let statuses = repo.statuses(Some(&mut options))?;
+
+    for entry in statuses.iter() {
+        if !entry
+            .status()
+            .intersects(git2::Status::WT_NEW | git2::Status::IGNORED)
+        {
+            continue;
+        }
+
+        let path = match entry.path() {
+            Some(path) => workdir.join(path),
+            None => continue,
+        };
+
+        tracing::debug!(?path, "Removing an untracked path from the repository");
+
+        let result = if path.is_dir() {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        };
+
+        result
+            .map_err(|e| git2::Error::from_str(&format!("Failed to remove {:?}: {}", path, e)))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::git::{
+        checkout_detached, host, is_commit_id, is_valid_branch_name, merge, normalize_url,
+        read_file, remote_head,
+    };
+
+    fn normalized(host: &str, path: &str) -> Option<(String, String)> {
+        Some((host.to_owned(), path.to_owned()))
+    }
+
+    #[test]
+    fn ssh_and_https_urls_are_normalized_equally() {
+        let expected = normalized("github.com", "alexander-jackson/fisherman");
+
+        assert_eq!(
+            normalize_url("git@github.com:alexander-jackson/fisherman.git"),
+            expected
+        );
+        assert_eq!(
+            normalize_url("https://github.com/alexander-jackson/fisherman.git"),
+            expected
+        );
+        assert_eq!(
+            normalize_url("ssh://git@GitHub.com:22/alexander-jackson/fisherman/"),
+            expected
+        );
+    }
+
+    #[test]
+    fn urls_without_paths_are_not_normalized() {
+        assert_eq!(normalize_url("github.com"), None);
+    }
+
+    #[test]
+    fn hosts_are_taken_from_urls() {
+        assert_eq!(
+            host("git@GitHub.com:alexander-jackson/fisherman.git").as_deref(),
+            Some("github.com")
+        );
+        assert_eq!(host("github.com"), None);
+    }
+
+    #[test]
+    fn files_can_be_read_from_commits() {
+        let path = std::env::temp_dir().join(format!("fisherman-git-{}", std::process::id()));
+        let repo = git2::Repository::init(&path).unwrap();
+
+        let blob = repo.blob(b"production\n").unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        builder.insert(".deploy-branch", blob, 0o100644).unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+
+        let signature = git2::Signature::now("fisherman", "fisherman@example.com").unwrap();
+        let commit = repo
+            .commit(None, &signature, &signature, "Initial commit", &tree, &[])
+            .unwrap();
+
+        assert_eq!(
+            read_file(&repo, commit, Path::new(".deploy-branch")).unwrap(),
+            Some(String::from("production\n"))
+        );
+        assert_eq!(
+            read_file(&repo, commit, Path::new("missing")).unwrap(),
+            None
+        );
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn references_can_be_validated() {
+        assert!(is_commit_id("0123456789abcdef0123456789abcdef01234567"));
+        assert!(!is_commit_id("0123456789abcdef"));
+        assert!(!is_commit_id("hotfix/login-0123456789abcdef0123456789"));
+
+        assert!(is_valid_branch_name("hotfix/login"));
+        assert!(!is_valid_branch_name("../master"));
+        assert!(!is_valid_branch_name("bad name"));
+    }
+
+    #[test]
+    fn conflicting_merges_fail_without_changing_the_checkout() {
+        let path = std::env::temp_dir().join(format!("fisherman-merge-{}", std::process::id()));
+        let repo = git2::Repository::init(&path).unwrap();
+        let signature = git2::Signature::now("fisherman", "fisherman@example.com").unwrap();
+
+        let commit = |content: &[u8], parents: &[&git2::Commit]| {
+            let blob = repo.blob(content).unwrap();
+            let mut builder = repo.treebuilder(None).unwrap();
+            builder.insert("config.toml", blob, 0o100644).unwrap();
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+
+            let id = repo
+                .commit(None, &signature, &signature, "Commit", &tree, parents)
+                .unwrap();
+
+            repo.find_commit(id).unwrap()
+        };
+
+        let base = commit(b"port = 80\n", &[]);
+        let local = commit(b"port = 8080\n", &[&base]);
+        let remote = commit(b"port = 9090\n", &[&base]);
+
+        repo.branch("master", &local, true).unwrap();
+        repo.set_head("refs/heads/master").unwrap();
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))
+            .unwrap();
+
+        let fetch_commit = repo.find_annotated_commit(remote.id()).unwrap();
+        let error = merge(&repo, "master", &fetch_commit).unwrap_err();
+
+        assert!(error.message().contains("config.toml"));
+        assert_eq!(repo.head().unwrap().target(), Some(local.id()));
+        assert_eq!(
+            std::fs::read_to_string(path.join("config.toml")).unwrap(),
+            "port = 8080\n"
+        );
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn merges_return_to_the_branch_after_a_manual_deploy() {
+        let path = std::env::temp_dir().join(format!("fisherman-manual-{}", std::process::id()));
+        let repo = git2::Repository::init(&path).unwrap();
+        let signature = git2::Signature::now("fisherman", "fisherman@example.com").unwrap();
+
+        let commit = |content: &[u8], parents: &[&git2::Commit]| {
+            let blob = repo.blob(content).unwrap();
+            let mut builder = repo.treebuilder(None).unwrap();
+            builder.insert("config.toml", blob, 0o100644).unwrap();
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+
+            let id = repo
+                .commit(None, &signature, &signature, "Commit", &tree, parents)
+                .unwrap();
+
+            repo.find_commit(id).unwrap()
+        };
+
+        let base = commit(b"port = 80\n", &[]);
+        let hotfix = commit(b"port = 8080\n", &[&base]);
+        let pushed = commit(b"port = 9090\n", &[&base]);
+
+        repo.branch("master", &base, true).unwrap();
+        repo.set_head("refs/heads/master").unwrap();
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))
+            .unwrap();
+
+        // Deploy another commit manually, then push to the followed branch
+        let hotfix = repo.find_annotated_commit(hotfix.id()).unwrap();
+        checkout_detached(&repo, &hotfix).unwrap();
+
+        let fetch_commit = repo.find_annotated_commit(pushed.id()).unwrap();
+        merge(&repo, "master", &fetch_commit).unwrap();
+
+        let head = repo.head().unwrap();
+        assert_eq!(head.name(), Some("refs/heads/master"));
+        assert_eq!(head.target(), Some(pushed.id()));
+        assert_eq!(
+            std::fs::read_to_string(path.join("config.toml")).unwrap(),
+            "port = 9090\n"
+        );
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn remote_heads_are_found_without_fetching() {
+        let root = std::env::temp_dir().join(format!("fisherman-remote-{}", std::process::id()));
+        let origin = git2::Repository::init(root.join("origin")).unwrap();
+        let signature = git2::Signature::now("fisherman", "fisherman@example.com").unwrap();
+
+        let tree = origin
+            .find_tree(origin.treebuilder(None).unwrap().write().unwrap())
+            .unwrap();
+        let head = origin
+            .commit(Some("HEAD"), &signature, &signature, "Commit", &tree, &[])
+            .unwrap();
+
+        let repo = git2::Repository::init(root.join("clone")).unwrap();
+        let url = root.join("origin").to_string_lossy().into_owned();
+        let mut remote = repo.remote("origin", &url).unwrap();
+
+        assert_eq!(remote_head(&mut remote, None, None, None).unwrap(), head);
+        assert!(repo.find_commit(head).is_err());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
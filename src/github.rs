@@ -0,0 +1,339 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::config::GitHubConfig;
+
+/// The states a GitHub deployment can be in while `fisherman` processes it.
+#[derive(Copy, Clone, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeploymentState {
+    InProgress,
+    Success,
+    Failure,
+    Inactive,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateDeployment<'a> {
+    #[serde(rename = "ref")]
+    reference: &'a str,
+    environment: &'a str,
+    description: &'a str,
+    auto_merge: bool,
+    required_contexts: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Deployment {
+    id: u64,
+}
+
+impl DeploymentState {
+    /// Gets the equivalent state of a commit status, which has no separate in progress state.
+    fn commit_status(self) -> &'static str {
+        match self {
+            Self::InProgress => "pending",
+            Self::Success | Self::Inactive => "success",
+            Self::Failure => "failure",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct CreateDeploymentStatus {
+    state: DeploymentState,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateCommitStatus<'a> {
+    state: &'a str,
+    context: &'a str,
+    description: &'a str,
+}
+
+/// The latest status of a commit for a single context.
+#[derive(Debug, Deserialize)]
+pub struct CommitStatus {
+    pub context: String,
+    pub state: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CombinedStatus {
+    statuses: Vec<CommitStatus>,
+}
+
+/// The outcome of checking the required statuses of a commit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChecksOutcome {
+    /// Every required status succeeded
+    Passed,
+    /// Some required statuses have not completed yet, or have not been reported at all
+    Pending,
+    /// A required status failed, with the context that failed
+    Failed(String),
+}
+
+impl ChecksOutcome {
+    /// Checks the latest statuses of a commit against the contexts that are required to pass.
+    pub fn evaluate(required: &[String], statuses: &[CommitStatus]) -> Self {
+        let mut outcome = Self::Passed;
+
+        for context in required {
+            let status = statuses.iter().find(|s| &s.context == context);
+
+            match status.map(|s| s.state.as_str()) {
+                Some("success") => (),
+                Some("failure" | "error") => return Self::Failed(context.clone()),
+                _ => outcome = Self::Pending,
+            }
+        }
+
+        outcome
+    }
+}
+
+/// A minimal client for the parts of the GitHub API that `fisherman` uses.
+#[derive(Debug)]
+pub struct GitHubClient {
+    client: reqwest::Client,
+    api_url: String,
+    token: String,
+    status_context: String,
+}
+
+impl GitHubClient {
+    /// Creates a new client from the configuration, building on the given HTTP client builder.
+    pub fn new(config: &GitHubConfig, builder: reqwest::ClientBuilder) -> Result<Self> {
+        let client = builder
+            .user_agent(concat!("fisherman/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+
+        let api_url = config
+            .api_url
+            .as_deref()
+            .unwrap_or("https://api.github.com")
+            .trim_end_matches('/')
+            .to_owned();
+
+        Ok(Self {
+            client,
+            api_url,
+            token: config.token.as_str().to_owned(),
+            status_context: config
+                .status_context
+                .clone()
+                .unwrap_or_else(|| String::from("fisherman")),
+        })
+    }
+
+    /// Gets the base URL requests are sent to.
+    pub fn api_url(&self) -> &str {
+        &self.api_url
+    }
+
+    /// Creates a deployment of the given commit, returning its identifier.
+    pub async fn create_deployment(&self, repository: &str, commit: &str) -> Result<u64> {
+        let url = format!("{}/repos/{}/deployments", self.api_url, repository);
+
+        let body = CreateDeployment {
+            reference: commit,
+            environment: "production",
+            description: "Deployed by fisherman",
+            auto_merge: false,
+            required_contexts: Vec::new(),
+        };
+
+        let deployment: Deployment = self
+            .client
+            .post(url)
+            .bearer_auth(&self.token)
+            .header("Accept", "application/vnd.github+json")
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(deployment.id)
+    }
+
+    /// Updates the state of an existing deployment.
+    pub async fn update_deployment_status(
+        &self,
+        repository: &str,
+        deployment: u64,
+        state: DeploymentState,
+    ) -> Result<()> {
+        let url = format!(
+            "{}/repos/{}/deployments/{}/statuses",
+            self.api_url, repository, deployment
+        );
+
+        self.client
+            .post(url)
+            .bearer_auth(&self.token)
+            .header("Accept", "application/vnd.github+json")
+            .json(&CreateDeploymentStatus { state })
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    /// Sets the status of a commit under the configured context.
+    pub async fn create_commit_status(
+        &self,
+        repository: &str,
+        commit: &str,
+        state: DeploymentState,
+    ) -> Result<()> {
+        let url = format!("{}/repos/{}/statuses/{}", self.api_url, repository, commit);
+
+        let body = CreateCommitStatus {
+            state: state.commit_status(),
+            context: &self.status_context,
+            description: "Deployed by fisherman",
+        };
+
+        self.client
+            .post(url)
+            .bearer_auth(&self.token)
+            .header("Accept", "application/vnd.github+json")
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    /// Gets the latest status of a commit for each context it has been reported under.
+    pub async fn commit_statuses(
+        &self,
+        repository: &str,
+        commit: &str,
+    ) -> Result<Vec<CommitStatus>> {
+        let url = format!(
+            "{}/repos/{}/commits/{}/status?per_page=100",
+            self.api_url, repository, commit
+        );
+
+        let combined: CombinedStatus = self
+            .client
+            .get(url)
+            .bearer_auth(&self.token)
+            .header("Accept", "application/vnd.github+json")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(combined.statuses)
+    }
+
+    /// Polls the statuses of a commit until the required contexts have completed or the timeout
+    /// elapses, in which case the outcome is still pending.
+    pub async fn wait_for_checks(
+        &self,
+        repository: &str,
+        commit: &str,
+        required: &[String],
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<ChecksOutcome> {
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        loop {
+            let statuses = self.commit_statuses(repository, commit).await?;
+            let outcome = ChecksOutcome::evaluate(required, &statuses);
+
+            if outcome != ChecksOutcome::Pending || tokio::time::Instant::now() >= deadline {
+                return Ok(outcome);
+            }
+
+            tracing::debug!(%repository, %commit, "Waiting for the required checks to complete");
+            tokio::time::sleep(interval).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{GitHubConfig, Redacted};
+    use crate::github::{ChecksOutcome, CommitStatus, DeploymentState, GitHubClient};
+
+    fn client(status_context: Option<&str>) -> GitHubClient {
+        let config = GitHubConfig {
+            token: Redacted::from(String::from("<token>")),
+            api_url: None,
+            status_context: status_context.map(String::from),
+        };
+
+        GitHubClient::new(&config, reqwest::Client::builder()).unwrap()
+    }
+
+    #[test]
+    fn commit_statuses_use_the_configured_context() {
+        assert_eq!(client(None).status_context, "fisherman");
+        assert_eq!(
+            client(Some("fisherman/staging")).status_context,
+            "fisherman/staging"
+        );
+    }
+
+    #[test]
+    fn in_progress_deployments_are_pending_commit_statuses() {
+        assert_eq!(DeploymentState::InProgress.commit_status(), "pending");
+        assert_eq!(DeploymentState::Failure.commit_status(), "failure");
+    }
+
+    fn status(context: &str, state: &str) -> CommitStatus {
+        CommitStatus {
+            context: String::from(context),
+            state: String::from(state),
+        }
+    }
+
+    #[test]
+    fn required_checks_pass_once_every_context_succeeds() {
+        let required = [String::from("ci/build"), String::from("ci/test")];
+
+        let statuses = [status("ci/build", "success"), status("ci/test", "pending")];
+        assert_eq!(
+            ChecksOutcome::evaluate(&required, &statuses),
+            ChecksOutcome::Pending
+        );
+
+        let statuses = [status("ci/build", "success")];
+        assert_eq!(
+            ChecksOutcome::evaluate(&required, &statuses),
+            ChecksOutcome::Pending
+        );
+
+        let statuses = [
+            status("ci/build", "success"),
+            status("ci/test", "success"),
+            status("ci/lint", "failure"),
+        ];
+        assert_eq!(
+            ChecksOutcome::evaluate(&required, &statuses),
+            ChecksOutcome::Passed
+        );
+    }
+
+    #[test]
+    fn required_checks_fail_if_any_context_fails() {
+        let required = [String::from("ci/build"), String::from("ci/test")];
+        let statuses = [status("ci/build", "pending"), status("ci/test", "error")];
+
+        assert_eq!(
+            ChecksOutcome::evaluate(&required, &statuses),
+            ChecksOutcome::Failed(String::from("ci/test"))
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+/// Tracks the requests currently being handled, so that the process can wait for them to finish
+/// before replacing itself.
+#[derive(Debug, Default)]
+pub struct InFlight {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    requests: Mutex<Requests>,
+    idle: Notify,
+}
+
+#[derive(Debug, Default)]
+struct Requests {
+    count: usize,
+    closed: bool,
+}
+
+/// Marks a request as being handled until it is dropped.
+#[derive(Debug)]
+pub struct InFlightGuard {
+    inner: Arc<Inner>,
+}
+
+impl InFlight {
+    /// Starts handling a request, unless new requests are being refused.
+    pub fn enter(&self) -> Option<InFlightGuard> {
+        let mut requests = self.inner.lock();
+
+        if requests.closed {
+            return None;
+        }
+
+        requests.count += 1;
+
+        Some(InFlightGuard {
+            inner: Arc::clone(&self.inner),
+        })
+    }
+
+    /// Refuses any new requests until [`InFlight::open`] is called.
+    pub fn close(&self) {
+        self.inner.lock().closed = true;
+    }
+
+    /// Accepts new requests again.
+    pub fn open(&self) {
+        self.inner.lock().closed = false;
+    }
+
+    /// Waits until no requests are being handled.
+    pub async fn wait_until_idle(&self) {
+        loop {
+            let notified = self.inner.idle.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if self.inner.lock().count == 0 {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+}
+
+impl Inner {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Requests> {
+        self.requests.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut requests = self.inner.lock();
+        requests.count -= 1;
+
+        if requests.count == 0 {
+            self.inner.idle.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::inflight::InFlight;
+
+    #[tokio::test]
+    async fn waiting_finishes_once_every_request_has() {
+        let in_flight = InFlight::default();
+        let guard = in_flight.enter().unwrap();
+
+        let waiting = tokio::time::timeout(Duration::from_millis(50), in_flight.wait_until_idle());
+        assert!(waiting.await.is_err());
+
+        drop(guard);
+
+        let waiting = tokio::time::timeout(Duration::from_secs(1), in_flight.wait_until_idle());
+        assert!(waiting.await.is_ok());
+    }
+
+    #[test]
+    fn requests_are_refused_while_closed() {
+        let in_flight = InFlight::default();
+
+        in_flight.close();
+        assert!(in_flight.enter().is_none());
+
+        in_flight.open();
+        assert!(in_flight.enter().is_some());
+    }
+}
@@ -0,0 +1,33 @@
+#![allow(clippy::module_name_repetitions)]
+
+//! Continuous delivery for Rust projects, driven by GitHub webhooks.
+//!
+//! The binary wraps this library, but the pieces can also be embedded in another service: parse a
+//! [`Config`], spawn [`process_webhooks`] with the receiving half of a channel and route incoming
+//! requests to [`verify_incoming_webhooks`] with a [`State`] holding the sending half.
+
+#[macro_use]
+extern crate serde;
+
+pub mod args;
+pub mod auth;
+pub mod batch;
+pub mod config;
+pub mod error;
+pub mod events;
+pub mod git;
+pub mod github;
+pub mod inflight;
+pub mod lock;
+pub mod logging;
+pub mod logs;
+pub mod process;
+pub mod reconcile;
+pub mod retry;
+pub mod server;
+pub mod webhook;
+
+pub use crate::config::Config;
+pub use crate::error::ServerError;
+pub use crate::server::{process_webhooks, verify_incoming_webhooks, State};
+pub use crate::webhook::{ResponseFormat, Webhook, WebhookVariant};
@@ -0,0 +1,57 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+use fs2::FileExt;
+
+/// The name of the lock file created in the `repo_root`.
+const LOCK_FILE: &str = ".fisherman.lock";
+
+/// An advisory lock on a `repo_root`, held for as long as this value is alive.
+///
+/// This prevents several instances of `fisherman` from fighting over the same clones and builds.
+#[derive(Debug)]
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Acquires the lock for the given `repo_root`, failing if another instance already holds it.
+    pub fn acquire(repo_root: &Path) -> io::Result<Self> {
+        let path = repo_root.join(LOCK_FILE);
+
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)?;
+
+        file.try_lock_exclusive().map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("{} is locked by another instance: {}", path.display(), e),
+            )
+        })?;
+
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lock::InstanceLock;
+
+    #[test]
+    fn only_one_instance_can_hold_the_lock() {
+        let repo_root = std::env::temp_dir().join(format!("fisherman-lock-{}", std::process::id()));
+        std::fs::create_dir_all(&repo_root).unwrap();
+
+        let lock = InstanceLock::acquire(&repo_root).unwrap();
+        assert!(InstanceLock::acquire(&repo_root).is_err());
+
+        drop(lock);
+        assert!(InstanceLock::acquire(&repo_root).is_ok());
+
+        std::fs::remove_dir_all(&repo_root).unwrap();
+    }
+}
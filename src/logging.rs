@@ -1,11 +1,71 @@
+use anyhow::Result;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{self, Tracer};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::config::Config;
+
 /// Sets up the logging for the application.
-pub fn setup_logger() {
+///
+/// If `RUST_LOG` is unset, the `log_filter` will be used instead, falling back to a reasonable
+/// default for production if that is also missing. If an `otlp_endpoint` is configured, spans will
+/// also be exported to it through the configured proxy.
+pub fn setup_logger(config: &Config) {
     if std::env::var("RUST_LOG").is_err() {
-        // Set a reasonable default for logging in production
-        std::env::set_var("RUST_LOG", "info,fisherman=debug");
+        let default_filter = config.default.log_filter.as_deref();
+        std::env::set_var("RUST_LOG", default_filter.unwrap_or("info,fisherman=debug"));
     }
 
-    tracing_subscriber::fmt::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+    let tracer = config
+        .default
+        .otlp_endpoint
+        .as_deref()
+        .map(|endpoint| otlp_tracer(config, endpoint));
+
+    let (tracer, error) = match tracer {
+        Some(Ok(tracer)) => (Some(tracer), None),
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::from_default_env())
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
         .init();
+
+    if let Some(error) = error {
+        tracing::error!(
+            ?error,
+            "Failed to set up exporting spans, only logging locally"
+        );
+    }
+}
+
+/// Flushes any spans that have not been exported yet, such as before exiting.
+pub fn shutdown_logger() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Creates a tracer that exports spans in batches to `/v1/traces` on the endpoint over OTLP/HTTP.
+fn otlp_tracer(config: &Config, endpoint: &str) -> Result<Tracer> {
+    let client = config.http_client_builder()?.build()?;
+
+    let exporter = opentelemetry_otlp::new_exporter()
+        .http()
+        .with_endpoint(endpoint)
+        .with_http_client(client);
+
+    let resource = Resource::new([KeyValue::new("service.name", "fisherman")]);
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(trace::config().with_resource(resource))
+        .install_batch(runtime::Tokio)?;
+
+    Ok(tracer)
 }
@@ -0,0 +1,76 @@
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+
+/// The number of lines that can be buffered for slow watchers before they miss some.
+const CAPACITY: usize = 1024;
+
+/// A line of output from a command run while deploying a repository.
+#[derive(Clone, Debug)]
+struct LogLine {
+    repository: String,
+    line: String,
+}
+
+/// Broadcasts the output of deploys to anyone watching them live.
+#[derive(Clone, Debug)]
+pub struct LogStream {
+    sender: broadcast::Sender<LogLine>,
+}
+
+impl Default for LogStream {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+
+        Self { sender }
+    }
+}
+
+impl LogStream {
+    /// Checks whether anyone is watching, in which case output should be sent.
+    pub fn is_watched(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    /// Sends a line of output from a deploy of the given repository to any watchers.
+    pub fn send(&self, repository: &str, line: &str) {
+        let line = LogLine {
+            repository: repository.to_owned(),
+            line: line.to_owned(),
+        };
+
+        // Nobody may be watching, which is fine
+        let _ = self.sender.send(line);
+    }
+
+    /// Watches the output of deploys for a single repository.
+    ///
+    /// Lines that were missed due to watching too slowly are skipped.
+    pub fn watch(&self, repository: String) -> impl Stream<Item = String> {
+        BroadcastStream::new(self.sender.subscribe()).filter_map(move |line| match line {
+            Ok(line) if line.repository == repository => Some(line.line),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_stream::StreamExt;
+
+    use crate::logs::LogStream;
+
+    #[tokio::test]
+    async fn watchers_only_receive_their_repository() {
+        let logs = LogStream::default();
+        assert!(!logs.is_watched());
+
+        let mut stream = Box::pin(logs.watch(String::from("alexander-jackson/fisherman")));
+        assert!(logs.is_watched());
+
+        logs.send("alexander-jackson/locker", "Compiling locker");
+        logs.send("alexander-jackson/fisherman", "Compiling fisherman");
+
+        assert_eq!(stream.next().await.unwrap(), "Compiling fisherman");
+    }
+}
@@ -1,199 +1,162 @@
-#![allow(clippy::module_name_repetitions)]
-
-use std::convert::TryFrom;
-use std::net::{Ipv4Addr, SocketAddrV4};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
-use actix_web::http::header::HeaderValue;
 use actix_web::middleware::Logger;
-use actix_web::web::{self, Data};
-use actix_web::{App, HttpRequest, HttpResponse, HttpServer};
-use tokio::sync::{mpsc, Mutex};
-use tokio_stream::StreamExt;
-
-use crate::config::Config;
-use crate::error::ServerError;
-
-#[macro_use]
-extern crate serde;
-
-mod auth;
-mod config;
-mod error;
-mod git;
-mod logging;
-mod webhook;
-
-/// Defines the state that each request can access.
-#[derive(Clone, Debug)]
-struct State {
-    pub config: Arc<Config>,
-    pub sender: Arc<Mutex<mpsc::UnboundedSender<Webhook>>>,
-}
-
-#[derive(Copy, Clone, Debug)]
-enum WebhookVariant {
-    Push,
-    Ping,
-}
-
-impl TryFrom<&HttpRequest> for WebhookVariant {
-    type Error = ServerError;
-
-    fn try_from(request: &HttpRequest) -> Result<Self, Self::Error> {
-        // Decide the variant to parse based on the headers
-        let header = match request
-            .headers()
-            .get("X-GitHub-Event")
-            .and_then(|v| v.to_str().ok())
-        {
-            Some(variant) => variant,
-            None => return Err(ServerError::BadRequest),
-        };
-
-        tracing::debug!(%header, "Received an X-GitHub Event header");
+use actix_web::web::Data;
+use actix_web::{App, HttpServer};
+use tokio::sync::{mpsc, watch, Mutex};
 
-        match header {
-            "push" => Ok(Self::Push),
-            "ping" => Ok(Self::Ping),
-            _ => Err(ServerError::BadRequest),
-        }
-    }
-}
+use fisherman::args::{self, Args};
+use fisherman::events::EventLog;
+use fisherman::lock::InstanceLock;
+use fisherman::{logging, process_webhooks, reconcile, server, Config, State};
 
-#[derive(Debug)]
-enum Webhook {
-    Push(webhook::Push),
-    Ping(webhook::Ping),
-}
+#[actix_rt::main]
+async fn main() -> actix_web::Result<()> {
+    let args = Args::parse(std::env::args().skip(1))?;
 
-impl Webhook {
-    /// Gets the full name of the repository this hook refers to.
-    pub fn get_full_name(&self) -> &str {
-        match self {
-            Webhook::Ping(p) => p.get_full_name(),
-            Webhook::Push(p) => p.get_full_name(),
-        }
+    // Read the configuration file
+    let config_path = args.config_path(std::env::var_os(args::CONFIG_VARIABLE));
+
+    let content = std::fs::read_to_string(&config_path).map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!(
+                "Failed to read the config at {}: {}",
+                config_path.display(),
+                e
+            ),
+        )
+    })?;
+
+    let config = Config::from_str(&content).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "Failed to parse the config at {}: {}",
+                config_path.display(),
+                e
+            ),
+        )
+    })?;
+
+    let config = Arc::new(config);
+
+    logging::setup_logger(&config);
+
+    tracing::info!(path = ?config_path, "Read the config");
+
+    let mistakes = config.check_for_potential_mistakes();
+
+    if config.is_strict() && mistakes > 0 {
+        tracing::error!(%mistakes, "Refusing to start with potential mistakes in the config");
+        return Err(std::io::Error::other("Potential mistakes found in the config").into());
     }
 
-    /// Handles the payload of the request depending on its type.
-    pub async fn handle(&self, config: &Arc<Config>) -> HttpResponse {
-        match self {
-            Webhook::Ping(p) => p.handle(config).await,
-            Webhook::Push(p) => p.handle(config).await,
+    // Make sure no other instance is using the same repositories, holding the lock until exit
+    let _lock = if config.allows_multiple_instances() {
+        None
+    } else {
+        match InstanceLock::acquire(&config.default.repo_root) {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                tracing::error!(error = %e, "Refusing to start while another instance is running");
+                return Err(e.into());
+            }
         }
-    }
-
-    /// Deserializes JSON from bytes depending on which variant is expected.
-    pub fn from_slice(variant: WebhookVariant, bytes: &[u8]) -> serde_json::Result<Self> {
-        let webhook = match variant {
-            WebhookVariant::Push => Self::Push(serde_json::from_slice(bytes)?),
-            WebhookVariant::Ping => Self::Ping(serde_json::from_slice(bytes)?),
-        };
-
-        Ok(webhook)
-    }
-}
-
-/// Receives messages from GitHub's API and deserializes them before handling.
-///
-/// Reads the content of the payload as a stream of bytes before checking which variant is expected
-/// and deserializing the payload. It then verifies that the included hash is correct for the given
-/// repository before handling the request.
-async fn verify_incoming_webhooks(
-    state: web::Data<State>,
-    mut payload: web::Payload,
-    request: HttpRequest,
-) -> Result<HttpResponse, ServerError> {
-    let mut bytes = web::BytesMut::new();
-
-    while let Some(Ok(item)) = payload.next().await {
-        bytes.extend_from_slice(&item);
-    }
-
-    let variant = WebhookVariant::try_from(&request)?;
-
-    let webhook =
-        Webhook::from_slice(variant, &bytes).map_err(|_| ServerError::UnprocessableEntity)?;
-
-    // Validate the payload with the secret key
-    let secret = state
-        .config
-        .resolve_secret(webhook.get_full_name())
-        .map(str::as_bytes);
-
-    // Get the expected value as bytes
-    let expected = request
-        .headers()
-        .get("X-Hub-Signature-256")
-        .map(HeaderValue::to_str)
-        .and_then(Result::ok)
-        .map(str::as_bytes)
-        .map(|s| s.split_at(7).1);
+    };
 
-    auth::validate_webhook_body(&bytes, secret, expected)?;
+    // Deploy every repository to its current head and exit, rather than waiting for pushes
+    if args.reconcile {
+        let summary = reconcile::reconcile(&config).await;
 
-    tracing::debug!(?webhook, "Verified");
+        tracing::info!("Reconciled the configured repositories: {}", summary);
 
-    // Send the message to the other thread
-    let guard = state.sender.lock().await;
-    guard.send(webhook).unwrap();
-
-    // Return an `Accepted` status code
-    Ok(HttpResponse::Accepted().finish())
-}
-
-async fn process_webhooks(config: Arc<Config>, mut receiver: mpsc::UnboundedReceiver<Webhook>) {
-    loop {
-        // Read a webhook message from the channel
-        let webhook = receiver.recv().await.unwrap();
+        if !summary.is_success() {
+            return Err(std::io::Error::other("Some repositories failed to reconcile").into());
+        }
 
-        // Process its content
-        webhook.handle(&config).await;
+        return Ok(());
     }
-}
-
-#[actix_rt::main]
-async fn main() -> actix_web::Result<()> {
-    logging::setup_logger();
-
-    // Read the configuration file
-    let content = std::fs::read_to_string("fisherman.yml")?;
-    let config = Arc::new(Config::from_str(&content).expect("Failed to parse config"));
-
-    config.check_for_potential_mistakes();
 
     // Setup the socket to run on
-    let port = config.default.port.unwrap_or(5000);
-    let socket = SocketAddrV4::new(Ipv4Addr::LOCALHOST, port);
+    let socket = config.socket_address();
 
-    tracing::info!(%port, ?config, "Listening for incoming webhooks");
+    tracing::debug!(?config, "Setting up the server");
 
-    let (sender, receiver) = mpsc::unbounded_channel();
+    let (sender, receiver) = mpsc::channel(config.resolve_queue_capacity());
     let sender = Arc::new(Mutex::new(sender));
 
+    let (paused, paused_receiver) = watch::channel(false);
+    let paused = Arc::new(paused);
+
+    let events = Arc::new(Mutex::new(EventLog::default()));
+    let overflow = Arc::new(server::Overflow::default());
+    let ready = Arc::new(AtomicBool::new(false));
+    let ready_flag = Arc::clone(&ready);
+
+    let http_workers = config.default.http_workers;
+    let keep_alive = config.default.keep_alive_secs.map(Duration::from_secs);
+    let client_request_timeout = config
+        .default
+        .client_request_timeout_ms
+        .map(Duration::from_millis);
+    let max_connections = config.default.max_connections;
     let config_clone = Arc::clone(&config);
 
     tokio::spawn(async move {
-        process_webhooks(config_clone, receiver).await;
+        process_webhooks(config_clone, receiver, paused_receiver).await;
     });
 
-    let server = HttpServer::new(move || {
+    let mut server = HttpServer::new(move || {
         let state = State {
             config: Arc::clone(&config),
             sender: Arc::clone(&sender),
+            paused: Arc::clone(&paused),
+            events: Arc::clone(&events),
+            ready: Arc::clone(&ready),
+            overflow: Arc::clone(&overflow),
         };
 
         App::new()
             .wrap(Logger::new("%s @ %r"))
             .app_data(Data::new(state))
-            .route("/", web::post().to(verify_incoming_webhooks))
-    })
-    .bind(socket)?
-    .run();
+            .configure(server::configure)
+    });
+
+    if let Some(workers) = http_workers {
+        server = server.workers(workers);
+    }
+
+    // Tune connection handling for senders behind proxies, keeping the defaults otherwise
+    if let Some(keep_alive) = keep_alive {
+        server = server.keep_alive(keep_alive);
+    }
+
+    if let Some(timeout) = client_request_timeout {
+        server = server.client_request_timeout(timeout);
+    }
+
+    if let Some(max_connections) = max_connections {
+        server = server.max_connections(max_connections);
+    }
+
+    let server = server.bind(socket)?;
+
+    for address in server.addrs() {
+        tracing::info!(%address, "Listening for incoming webhooks");
+    }
+
+    let server = server.run();
+
+    // Everything has been setup, so webhooks can now be accepted
+    ready_flag.store(true, Ordering::Release);
 
     server.await?;
 
+    logging::shutdown_logger();
+
     Ok(())
 }
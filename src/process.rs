@@ -0,0 +1,268 @@
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{ExitStatus, Output, Stdio};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+
+/// The most output that is captured from each stream of a command, keeping the end of it.
+pub const MAX_CAPTURED_BYTES: usize = 1024 * 1024;
+
+/// Spawns a command and waits for it to exit.
+///
+/// The child is killed if it is still running once the timeout elapses, or if the returned future
+/// is dropped before it completes (such as when the task running it is cancelled), so that no
+/// processes are left orphaned.
+pub async fn run(command: &mut Command, timeout: Option<Duration>) -> Result<ExitStatus> {
+    let mut child = command.kill_on_drop(true).spawn()?;
+
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Ok(child.wait().await?),
+    };
+
+    match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(status) => Ok(status?),
+        Err(_) => {
+            child.kill().await?;
+            bail!("Command timed out after {:?}: {:?}", timeout, command);
+        }
+    }
+}
+
+/// Spawns a command and waits for it to exit, capturing anything it writes to stdout and stderr.
+///
+/// As with [`run`], the child is killed if the timeout elapses or the returned future is dropped.
+pub async fn output(command: &mut Command, timeout: Option<Duration>) -> Result<Output> {
+    let child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Ok(child.wait_with_output().await?),
+    };
+
+    // Dropping the child on timeout is enough to kill it
+    match tokio::time::timeout(timeout, child.wait_with_output()).await {
+        Ok(output) => Ok(output?),
+        Err(_) => bail!("Command timed out after {:?}: {:?}", timeout, command),
+    }
+}
+
+/// Spawns a command and waits for it to exit, capturing its output while also passing each line
+/// of it to `on_line` as it is written.
+///
+/// Lines that are not valid UTF-8 have any invalid sequences replaced. Only the last
+/// [`MAX_CAPTURED_BYTES`] of each stream are captured, so commands with a lot of output cannot
+/// exhaust memory. As with [`run`], the child is killed if the timeout elapses or the returned
+/// future is dropped.
+pub async fn run_streaming(
+    command: &mut Command,
+    timeout: Option<Duration>,
+    mut on_line: impl FnMut(&str),
+) -> Result<Output> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let mut stdout = CapturedStream::new(child.stdout.take().unwrap());
+    let mut stderr = CapturedStream::new(child.stderr.take().unwrap());
+
+    let completion = async move {
+        while !(stdout.done && stderr.done) {
+            let line = tokio::select! {
+                line = stdout.next_line(), if !stdout.done => line?,
+                line = stderr.next_line(), if !stderr.done => line?,
+            };
+
+            if let Some(line) = line {
+                on_line(&line);
+            }
+        }
+
+        let status = child.wait().await?;
+
+        Ok(Output {
+            status,
+            stdout: stdout.into_captured(),
+            stderr: stderr.into_captured(),
+        })
+    };
+
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return completion.await,
+    };
+
+    // Dropping the child on timeout is enough to kill it
+    match tokio::time::timeout(timeout, completion).await {
+        Ok(output) => output,
+        Err(_) => bail!("Command timed out after {:?}: {:?}", timeout, command),
+    }
+}
+
+/// The output of a child process being read line by line, keeping the end of it.
+struct CapturedStream<R> {
+    reader: BufReader<R>,
+    /// The line currently being read, which may be partially read if reading was cancelled
+    line: Vec<u8>,
+    /// The last [`MAX_CAPTURED_BYTES`] of the stream
+    captured: Vec<u8>,
+    done: bool,
+}
+
+impl<R: AsyncRead + Unpin> CapturedStream<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            line: Vec::new(),
+            captured: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Reads the next line of the stream, without its line ending, or nothing once it has ended.
+    ///
+    /// This can be cancelled without losing any output, as partially read lines are kept.
+    async fn next_line(&mut self) -> std::io::Result<Option<String>> {
+        if self.reader.read_until(b'\n', &mut self.line).await? == 0 {
+            self.done = true;
+
+            if self.line.is_empty() {
+                return Ok(None);
+            }
+        }
+
+        let line = std::mem::take(&mut self.line);
+        self.capture(&line);
+
+        let trimmed = line.strip_suffix(b"\n").unwrap_or(&line);
+        let trimmed = trimmed.strip_suffix(b"\r").unwrap_or(trimmed);
+
+        Ok(Some(String::from_utf8_lossy(trimmed).into_owned()))
+    }
+
+    /// Adds a line to the captured output, dropping the oldest output beyond the limit.
+    fn capture(&mut self, line: &[u8]) {
+        self.captured.extend_from_slice(line);
+
+        // Trim in larger steps to avoid shifting the buffer for every line
+        if self.captured.len() > 2 * MAX_CAPTURED_BYTES {
+            self.truncate();
+        }
+    }
+
+    /// Drops the oldest output beyond the limit.
+    fn truncate(&mut self) {
+        let excess = self.captured.len().saturating_sub(MAX_CAPTURED_BYTES);
+        self.captured.drain(..excess);
+    }
+
+    /// Takes the captured output once the stream has been read.
+    fn into_captured(mut self) -> Vec<u8> {
+        self.truncate();
+        self.captured
+    }
+}
+
+/// Replaces the current process with the given binary, passing along the same arguments.
+///
+/// This only returns if the process could not be replaced, in which case the current process will
+/// continue running as before.
+pub fn replace_current(binary: &Path) -> std::io::Error {
+    std::process::Command::new(binary)
+        .args(std::env::args_os().skip(1))
+        .exec()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::process::Command;
+
+    use crate::process::{output, run, run_streaming, MAX_CAPTURED_BYTES};
+
+    #[tokio::test]
+    async fn commands_are_run_to_completion() {
+        let status = run(&mut Command::new("true"), None).await.unwrap();
+
+        assert!(status.success());
+    }
+
+    #[tokio::test]
+    async fn commands_are_killed_after_the_timeout() {
+        let mut command = Command::new("sleep");
+        command.arg("10");
+
+        let result = run(&mut command, Some(Duration::from_millis(50))).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn output_is_captured() {
+        let mut command = Command::new("echo");
+        command.arg("hello");
+
+        let output = output(&mut command, None).await.unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"hello\n");
+    }
+
+    #[tokio::test]
+    async fn output_is_streamed_line_by_line() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo first; echo second >&2"]);
+
+        let mut lines = Vec::new();
+        let output = run_streaming(&mut command, None, |line| lines.push(line.to_owned()))
+            .await
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"first\n");
+        assert_eq!(output.stderr, b"second\n");
+
+        lines.sort();
+        assert_eq!(lines, ["first", "second"]);
+    }
+
+    #[tokio::test]
+    async fn invalid_utf8_output_is_streamed_lossily() {
+        let mut command = Command::new("printf");
+        command.arg("valid\\nin\\377valid\\nunterminated");
+
+        let mut lines = Vec::new();
+        let output = run_streaming(&mut command, None, |line| lines.push(line.to_owned()))
+            .await
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"valid\nin\xffvalid\nunterminated");
+        assert_eq!(lines, ["valid", "in\u{fffd}valid", "unterminated"]);
+    }
+
+    #[tokio::test]
+    async fn only_the_end_of_large_output_is_captured() {
+        let mut command = Command::new("sh");
+        command.args([
+            "-c",
+            "head -c 3000000 /dev/zero | tr '\\0' 'a'; echo; echo last",
+        ]);
+
+        let output = run_streaming(&mut command, None, |_| {}).await.unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout.len(), MAX_CAPTURED_BYTES);
+        assert!(output.stdout.ends_with(b"a\nlast\n"));
+    }
+}
@@ -0,0 +1,148 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::webhook::{self, Deployment, Repository};
+
+/// The outcome of reconciling every configured repository.
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// The repositories that were deployed
+    pub deployed: Vec<String>,
+    /// The repositories that were deployed without some of their binaries, along with why
+    pub partial: Vec<(String, String)>,
+    /// The repositories whose deploys were skipped, such as when missing their required file
+    pub skipped: Vec<String>,
+    /// The repositories that failed to deploy, along with why
+    pub failed: Vec<(String, String)>,
+}
+
+impl Summary {
+    /// Checks whether every repository was reconciled without errors.
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    /// Describes the outcome for a single notification, rather than one for each repository.
+    fn notification(&self) -> String {
+        format!("Reconciled the configured repositories: {}", self)
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} deployed, {} partially deployed, {} skipped, {} failed",
+            self.deployed.len(),
+            self.partial.len(),
+            self.skipped.len(),
+            self.failed.len()
+        )?;
+
+        for (repository, error) in self.partial.iter().chain(&self.failed) {
+            write!(f, "\n  {}: {}", repository, error)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Brings every configured repository up to date with the head of its followed branch.
+///
+/// Repositories are cloned if they are missing and deployed one at a time through the usual
+/// pipeline, without waiting for any webhooks. Failures are recorded rather than stopping the
+/// remaining repositories from being deployed.
+pub async fn reconcile(config: &Arc<Config>) -> Summary {
+    let mut repositories: Vec<_> = config
+        .specific
+        .iter()
+        .flat_map(|specific| specific.keys())
+        .map(String::as_str)
+        .collect();
+
+    repositories.sort_unstable();
+
+    let mut summary = Summary::default();
+
+    for full_name in repositories {
+        let repository = Repository::from_full_name(full_name);
+
+        let branch = match config.resolve_primary_branch(full_name) {
+            Ok(branch) => branch,
+            Err(e) => {
+                tracing::error!(repo = %full_name, error = %e, "Cannot reconcile the repository");
+                summary.failed.push((full_name.to_owned(), e.to_string()));
+                continue;
+            }
+        };
+
+        tracing::info!(repo = %full_name, %branch, "Reconciling the repository");
+
+        let result = match repository.ensure_cloned(config).await {
+            Ok(()) => repository.deploy(config, branch, None, false).await,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(Deployment::Complete) => summary.deployed.push(full_name.to_owned()),
+            Ok(Deployment::Skipped) => summary.skipped.push(full_name.to_owned()),
+            Ok(Deployment::Partial(e)) => {
+                tracing::warn!(repo = %full_name, error = ?e, "Reconciled the repository without some binaries");
+
+                if config.should_notify_reconcile_failures() {
+                    let message = webhook::partial_message(full_name, &e.to_string());
+                    webhook::send_discord_message_now(config, Some(branch), message).await;
+                }
+
+                summary.partial.push((full_name.to_owned(), e.to_string()));
+            }
+            Err(e) => {
+                tracing::error!(repo = %full_name, error = ?e, "Failed to reconcile the repository");
+
+                if config.should_notify_reconcile_failures() {
+                    let message = webhook::failure_message(full_name, &e.to_string());
+                    webhook::send_discord_message_now(config, Some(branch), message).await;
+                }
+
+                summary.failed.push((full_name.to_owned(), e.to_string()));
+            }
+        }
+    }
+
+    webhook::send_discord_message_now(config, None, summary.notification()).await;
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reconcile::Summary;
+
+    #[test]
+    fn summaries_list_failures() {
+        let summary = Summary {
+            deployed: vec![String::from("alexander-jackson/fisherman")],
+            partial: vec![(
+                String::from("alexander-jackson/cinnamon"),
+                String::from(
+                    "Failed to deploy some binaries: worker (Failed to restart program: worker)",
+                ),
+            )],
+            skipped: Vec::new(),
+            failed: vec![(
+                String::from("alexander-jackson/locker"),
+                String::from("Deploy timed out after 600s"),
+            )],
+        };
+
+        assert!(!summary.is_success());
+        assert!(summary
+            .notification()
+            .starts_with("Reconciled the configured repositories: 1 deployed"));
+        assert_eq!(
+            summary.to_string(),
+            "1 deployed, 1 partially deployed, 0 skipped, 1 failed\n  alexander-jackson/cinnamon: Failed to deploy some binaries: worker (Failed to restart program: worker)\n  alexander-jackson/locker: Deploy timed out after 600s"
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+/// Runs an operation, retrying it with exponential backoff if it fails transiently.
+///
+/// The operation will be attempted at most `retries + 1` times, with the delay between attempts
+/// starting at `initial_delay` and doubling after each failure. Errors that are not transient
+/// are returned immediately.
+pub async fn with_backoff<T, E, F, Fut>(
+    retries: u32,
+    initial_delay: Duration,
+    mut operation: F,
+    is_transient: impl Fn(&E) -> bool,
+) -> Result<T, E>
+where
+    E: Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut delay = initial_delay;
+    let mut attempt = 0;
+
+    loop {
+        match operation().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < retries && is_transient(&e) => {
+                tracing::warn!(%e, %attempt, ?delay, "Operation failed transiently, retrying");
+
+                tokio::time::sleep(delay).await;
+
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::time::Duration;
+
+    use crate::retry::with_backoff;
+
+    #[tokio::test]
+    async fn transient_failures_are_retried() {
+        let attempts = Cell::new(0);
+
+        let result = with_backoff(
+            3,
+            Duration::ZERO,
+            || {
+                attempts.set(attempts.get() + 1);
+                async {
+                    if attempts.get() < 3 {
+                        Err("transient")
+                    } else {
+                        Ok(())
+                    }
+                }
+            },
+            |_| true,
+        )
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn retries_are_bounded() {
+        let attempts = Cell::new(0);
+
+        let result: Result<(), _> = with_backoff(
+            2,
+            Duration::ZERO,
+            || {
+                attempts.set(attempts.get() + 1);
+                async { Err("transient") }
+            },
+            |_| true,
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn permanent_failures_are_not_retried() {
+        let attempts = Cell::new(0);
+
+        let result: Result<(), _> = with_backoff(
+            3,
+            Duration::ZERO,
+            || {
+                attempts.set(attempts.get() + 1);
+                async { Err("permanent") }
+            },
+            |_| false,
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+}
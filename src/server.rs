@@ -0,0 +1,883 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::{Infallible, TryFrom};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use actix_web::http::header::HeaderValue;
+use actix_web::web;
+use actix_web::{HttpRequest, HttpResponse};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot, watch, Mutex};
+use tokio::task::JoinHandle;
+use tokio_stream::StreamExt;
+use tracing::Instrument;
+
+use crate::auth::{self, SignatureAlgorithm};
+use crate::config::Config;
+use crate::error::ServerError;
+use crate::events::{self, Delivery, EventFilter, EventLog};
+use crate::git;
+use crate::inflight::InFlightGuard;
+use crate::process;
+use crate::webhook::{ManualDeploy, Outcome, ResponseFormat, Webhook, WebhookVariant};
+
+/// Defines the state that each request can access.
+#[derive(Clone, Debug)]
+pub struct State {
+    pub config: Arc<Config>,
+    pub sender: Arc<Mutex<mpsc::Sender<Delivery>>>,
+    /// Whether processing of webhooks is paused, in which case they will only be queued
+    pub paused: Arc<watch::Sender<bool>>,
+    /// The most recently received webhooks, which can be replayed
+    pub events: Arc<Mutex<EventLog>>,
+    /// Whether startup has finished and webhooks can be accepted
+    pub ready: Arc<AtomicBool>,
+    /// Webhooks accepted while the queue was full, waiting in the background for space
+    pub overflow: Arc<Overflow>,
+}
+
+/// Webhooks accepted while the queue was full, which are moved onto it in the order they arrived
+/// as space becomes available.
+///
+/// Each webhook holds an [`InFlightGuard`], so the process waits for them to be queued before
+/// replacing itself.
+#[derive(Debug, Default)]
+pub struct Overflow {
+    deliveries: std::sync::Mutex<VecDeque<(Delivery, InFlightGuard)>>,
+}
+
+impl Overflow {
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<(Delivery, InFlightGuard)>> {
+        self.deliveries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Moves the waiting webhooks onto the queue one at a time, finishing once none are left.
+    ///
+    /// Only one of these runs at a time, started by whichever webhook found the overflow empty.
+    async fn forward(self: Arc<Self>, sender: mpsc::Sender<Delivery>) {
+        loop {
+            let permit = sender.reserve().await;
+            let mut deliveries = self.lock();
+
+            let permit = match permit {
+                Ok(permit) => permit,
+                Err(_) => {
+                    for (delivery, _guard) in deliveries.drain(..) {
+                        tracing::error!(repository = %delivery.webhook.get_full_name(), correlation = %delivery.correlation, "Webhooks are no longer being processed, dropping the webhook");
+                    }
+
+                    return;
+                }
+            };
+
+            if let Some((delivery, _guard)) = deliveries.pop_front() {
+                permit.send(delivery);
+            }
+
+            if deliveries.is_empty() {
+                return;
+            }
+        }
+    }
+}
+
+impl State {
+    /// Checks that the request carries the configured administrative bearer token.
+    fn authorize_admin(&self, request: &HttpRequest) -> Result<(), ServerError> {
+        let provided = request
+            .headers()
+            .get("Authorization")
+            .map(HeaderValue::to_str)
+            .and_then(Result::ok)
+            .and_then(|s| s.strip_prefix("Bearer "));
+
+        auth::validate_admin_token(self.config.admin_token(), provided)
+    }
+
+    /// Marks a request as being handled, refusing it if the process is about to replace itself.
+    fn enter(&self) -> Result<InFlightGuard, ServerError> {
+        self.config.in_flight.enter().ok_or_else(|| {
+            tracing::warn!("The process is about to be replaced, refusing the request");
+            ServerError::ServiceUnavailable
+        })
+    }
+
+    /// Queues a webhook for processing, waiting at most the `queue_timeout_ms` for space.
+    ///
+    /// Webhooks are rejected if the queue is full, or if nothing is processing them anymore, so
+    /// that senders can retry them later rather than holding the request open. If a
+    /// `queue_timeout_ms` is configured, webhooks instead wait that long for space in a full queue,
+    /// after which they are queued in the background so that the request can still be
+    /// acknowledged quickly. At most `queue_capacity` webhooks wait in the background, and they
+    /// are queued in the order they arrived.
+    async fn enqueue(&self, delivery: Delivery) -> Result<(), ServerError> {
+        let sender = self.sender.lock().await.clone();
+
+        // Keep webhooks behind any that are already waiting for space
+        if !self.overflow.lock().is_empty() {
+            return self.enqueue_in_background(sender, delivery);
+        }
+
+        let timeout = match self.config.queue_timeout() {
+            Some(timeout) => timeout,
+            None => return Self::try_enqueue(&sender, delivery),
+        };
+
+        match tokio::time::timeout(timeout, sender.clone().reserve_owned()).await {
+            Ok(Ok(permit)) => {
+                permit.send(delivery);
+            }
+            Ok(Err(_)) => {
+                tracing::error!(repository = %delivery.webhook.get_full_name(), correlation = %delivery.correlation, "Webhooks are no longer being processed, rejecting the webhook");
+                return Err(ServerError::ServiceUnavailable);
+            }
+            Err(_) => {
+                tracing::warn!(repository = %delivery.webhook.get_full_name(), correlation = %delivery.correlation, ?timeout, "The webhook queue is still full, queueing the webhook in the background");
+
+                return self.enqueue_in_background(sender, delivery);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds a webhook to the overflow, rejecting it if `queue_capacity` webhooks are already
+    /// waiting there.
+    fn enqueue_in_background(
+        &self,
+        sender: mpsc::Sender<Delivery>,
+        delivery: Delivery,
+    ) -> Result<(), ServerError> {
+        let guard = self.enter()?;
+        let mut deliveries = self.overflow.lock();
+
+        if deliveries.len() >= self.config.resolve_queue_capacity() {
+            tracing::warn!(repository = %delivery.webhook.get_full_name(), correlation = %delivery.correlation, "Too many webhooks are waiting in the background, rejecting the webhook");
+            return Err(ServerError::ServiceUnavailable);
+        }
+
+        deliveries.push_back((delivery, guard));
+
+        // Start forwarding the overflow if nothing else is
+        if deliveries.len() == 1 {
+            actix_rt::spawn(Arc::clone(&self.overflow).forward(sender));
+        }
+
+        Ok(())
+    }
+
+    /// Queues a webhook if there is space for it, rejecting it otherwise.
+    fn try_enqueue(sender: &mpsc::Sender<Delivery>, delivery: Delivery) -> Result<(), ServerError> {
+        sender.try_send(delivery).map_err(|e| {
+            match e {
+                TrySendError::Full(delivery) => {
+                    tracing::warn!(repository = %delivery.webhook.get_full_name(), correlation = %delivery.correlation, "The webhook queue is full, rejecting the webhook");
+                }
+                TrySendError::Closed(delivery) => {
+                    tracing::error!(repository = %delivery.webhook.get_full_name(), correlation = %delivery.correlation, "Webhooks are no longer being processed, rejecting the webhook");
+                }
+            }
+
+            ServerError::ServiceUnavailable
+        })
+    }
+}
+
+/// Registers the routes served by `fisherman` on an application.
+///
+/// The [`State`] is expected to be provided separately through `App::app_data`, which allows the
+/// application to be built without binding to a port, such as in tests.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/", web::post().to(verify_incoming_webhooks))
+        .route("/healthz", web::get().to(healthz))
+        .route("/ready", web::get().to(ready))
+        .route("/status", web::get().to(status))
+        .route("/pause", web::post().to(pause))
+        .route("/resume", web::post().to(resume))
+        .route("/events", web::get().to(events))
+        .route("/logs/{owner}/{repo}", web::get().to(logs))
+        .route("/replay/{delivery_id}", web::post().to(replay))
+        .route("/validate", web::post().to(validate))
+        .route("/deploy/{owner}/{repo}", web::post().to(deploy))
+        .route(
+            "/reconcile/{owner}/{repo}",
+            web::post().to(reconcile_repository),
+        );
+}
+
+/// Reports that the server is alive, without touching any state so that it responds even while
+/// webhooks are being processed.
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+/// Reports whether startup has finished, responding with `503 Service Unavailable` until it has.
+pub async fn ready(state: web::Data<State>) -> HttpResponse {
+    if state.ready.load(Ordering::Acquire) {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::ServiceUnavailable().finish()
+    }
+}
+
+/// Reports the current state of processing.
+pub async fn status(state: web::Data<State>) -> HttpResponse {
+    let paused = *state.paused.borrow();
+
+    HttpResponse::Ok().json(serde_json::json!({ "paused": paused }))
+}
+
+/// Pauses the processing of webhooks, which will continue to be accepted and queued.
+pub async fn pause(
+    state: web::Data<State>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ServerError> {
+    state.authorize_admin(&request)?;
+    state.paused.send_replace(true);
+
+    tracing::info!("Paused the processing of webhooks");
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Resumes the processing of webhooks, starting with any that were queued while paused.
+pub async fn resume(
+    state: web::Data<State>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ServerError> {
+    state.authorize_admin(&request)?;
+    state.paused.send_replace(false);
+
+    tracing::info!("Resumed the processing of webhooks");
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Checks the current config for potential mistakes without changing anything, reporting each
+/// one along with the repository it was found in.
+pub async fn validate(
+    state: web::Data<State>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ServerError> {
+    state.authorize_admin(&request)?;
+
+    let mistakes = state.config.find_potential_mistakes();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "valid": mistakes.is_empty(),
+        "mistakes": mistakes,
+    })))
+}
+
+/// The query parameters of a manual deploy.
+#[derive(Debug, Deserialize)]
+pub struct DeployQuery {
+    /// The branch or full commit identifier to deploy, instead of the followed branch
+    #[serde(rename = "ref")]
+    reference: Option<String>,
+}
+
+/// Deploys a configured repository without waiting for a webhook.
+///
+/// The followed branch is deployed unless another branch or commit is given by `ref`, in which
+/// case the checkout is reset to it. Invalid and ignored branches are rejected. The deploy is
+/// queued behind any webhooks for the repository and held while processing is paused, responding
+/// with `202 Accepted` once it has been queued.
+pub async fn deploy(
+    state: web::Data<State>,
+    request: HttpRequest,
+    path: web::Path<(String, String)>,
+    query: web::Query<DeployQuery>,
+) -> Result<HttpResponse, ServerError> {
+    state.authorize_admin(&request)?;
+    let _guard = state.enter()?;
+
+    let (owner, repo) = path.into_inner();
+    let repository = format!("{}/{}", owner, repo);
+    let config = Arc::clone(&state.config);
+
+    if config.get_specific_config(&repository).is_none() {
+        return Err(ServerError::NotFound);
+    }
+
+    let (reference, forced) = match query.into_inner().reference {
+        Some(reference) if git::is_commit_id(&reference) => (reference, true),
+        Some(reference) => {
+            if !git::is_valid_branch_name(&reference)
+                || config.is_branch_ignored(&repository, &reference)
+            {
+                tracing::warn!(%repository, %reference, "Rejecting a manual deploy of a disallowed reference");
+                return Err(ServerError::BadRequest);
+            }
+
+            (reference, true)
+        }
+        None => match config.resolve_primary_branch(&repository) {
+            Ok(branch) => (branch.to_owned(), false),
+            Err(e) => {
+                tracing::warn!(%repository, error = %e, "Rejecting a manual deploy without a reference");
+                return Ok(HttpResponse::BadRequest().body(e.to_string()));
+            }
+        },
+    };
+
+    tracing::info!(%repository, %reference, "Queueing a manual deploy");
+
+    let deploy = ManualDeploy::new(&repository, reference, forced);
+    let delivery = Delivery::new(events::correlation_id(None), Webhook::Deploy(deploy));
+
+    state.enqueue(delivery).await?;
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Brings a single repository up to date with the head of its followed branch, responding with
+/// the result once it has finished.
+///
+/// The repository is cloned if it is missing and its checkout is reset to the remote, so it is
+/// deployed even if nothing has been pushed since the last deploy. The deploy is queued behind any
+/// webhooks for the repository, so it is rejected with `409 Conflict` while processing is paused
+/// rather than waiting for it to be resumed.
+pub async fn reconcile_repository(
+    state: web::Data<State>,
+    request: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ServerError> {
+    state.authorize_admin(&request)?;
+    let _guard = state.enter()?;
+
+    let (owner, repo) = path.into_inner();
+    let repository = format!("{}/{}", owner, repo);
+
+    if state.config.get_specific_config(&repository).is_none() {
+        return Err(ServerError::NotFound);
+    }
+
+    let branch = match state.config.resolve_primary_branch(&repository) {
+        Ok(branch) => branch,
+        Err(e) => {
+            tracing::warn!(%repository, error = %e, "Rejecting a reconcile of the repository");
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "repository": repository,
+                "error": e.to_string(),
+            })));
+        }
+    };
+
+    if *state.paused.borrow() {
+        tracing::warn!(%repository, "Rejecting a reconcile of the repository while processing is paused");
+        return Err(ServerError::Conflict);
+    }
+
+    tracing::info!(%repository, %branch, "Reconciling the repository on request");
+
+    let deploy = ManualDeploy::new(&repository, branch.to_owned(), true);
+    let mut delivery = Delivery::new(events::correlation_id(None), Webhook::Deploy(deploy));
+
+    let (reply, outcome) = oneshot::channel();
+    delivery.replies.push(reply);
+
+    state.enqueue(delivery).await?;
+
+    let response = match outcome.await {
+        Ok(Outcome::Failed(failure)) => {
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "repository": repository,
+                "branch": branch,
+                "error": failure.error(),
+            }))
+        }
+        Ok(outcome) => {
+            let error = match &outcome {
+                Outcome::PartiallyDeployed(failure) => Some(failure.error()),
+                _ => None,
+            };
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "repository": repository,
+                "branch": branch,
+                "deployed": outcome.is_deployed(),
+                "error": error,
+            }))
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    };
+
+    Ok(response)
+}
+
+/// Lists the most recently received webhooks and the stages of the deploys they caused, optionally
+/// filtered by the query parameters.
+///
+/// Events are returned in pages of at most `max_events_per_page`, along with the total number of
+/// matching events. Clients that accept `text/plain` get a line for each instead of JSON.
+pub async fn events(
+    state: web::Data<State>,
+    request: HttpRequest,
+    filter: web::Query<EventFilter>,
+) -> Result<HttpResponse, ServerError> {
+    let mut filter = filter.into_inner();
+    let max = state.config.max_events_per_page();
+    filter.limit = Some(filter.limit.map_or(max, |limit| limit.min(max)));
+
+    let events = state.events.lock().await;
+
+    let mut page = events.query(&filter);
+    page.stages = state.config.stages.query(&filter);
+
+    let accepts_text = request
+        .headers()
+        .get("Accept")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/plain"));
+
+    if accepts_text {
+        return Ok(HttpResponse::Ok()
+            .content_type("text/plain")
+            .body(page.to_string()));
+    }
+
+    Ok(HttpResponse::Ok().json(page))
+}
+
+/// Streams the output of deploys of a repository as they happen, as server-sent events.
+pub async fn logs(
+    state: web::Data<State>,
+    request: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ServerError> {
+    state.authorize_admin(&request)?;
+
+    let (owner, repo) = path.into_inner();
+    let repository = format!("{}/{}", owner, repo);
+
+    tracing::info!(%repository, "Streaming the output of deploys");
+
+    let stream = state
+        .config
+        .logs
+        .watch(repository)
+        .map(|line| Ok::<_, Infallible>(web::Bytes::from(format!("data: {}\n\n", line))));
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .streaming(stream))
+}
+
+/// Queues a previously received webhook to be processed again, such as after fixing the cause of a
+/// failed deploy.
+pub async fn replay(
+    state: web::Data<State>,
+    request: HttpRequest,
+    delivery_id: web::Path<String>,
+) -> Result<HttpResponse, ServerError> {
+    state.authorize_admin(&request)?;
+    let _guard = state.enter()?;
+
+    let delivery = state
+        .events
+        .lock()
+        .await
+        .find(&delivery_id)
+        .map(|event| Delivery::new(event.correlation.clone(), event.webhook.clone()))
+        .ok_or(ServerError::NotFound)?;
+
+    tracing::info!(%delivery_id, repository = %delivery.webhook.get_full_name(), correlation = %delivery.correlation, "Replaying a webhook");
+
+    state.enqueue(delivery).await?;
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Receives messages from GitHub's API and deserializes them before handling.
+///
+/// Reads the content of the payload as a stream of bytes before checking which variant is expected
+/// and deserializing the payload. It then verifies that the included hash is correct for the given
+/// repository before handling the request.
+pub async fn verify_incoming_webhooks(
+    state: web::Data<State>,
+    mut payload: web::Payload,
+    request: HttpRequest,
+) -> Result<HttpResponse, ServerError> {
+    let _guard = state.enter()?;
+    let mut bytes = web::BytesMut::new();
+
+    while let Some(Ok(item)) = payload.next().await {
+        bytes.extend_from_slice(&item);
+    }
+
+    let variant = WebhookVariant::try_from(&request)?;
+
+    let webhook =
+        Webhook::from_slice(variant, &bytes).map_err(|_| ServerError::UnprocessableEntity)?;
+
+    // Check the payload will be validated with the secret for the repository it claims to be from
+    let repository = webhook.get_full_name();
+
+    if !state.config.has_specific_secret(repository) {
+        if state.config.requires_specific_secrets() {
+            tracing::warn!(%repository, "Rejecting a webhook for a repository without its own secret");
+            return Err(ServerError::Unauthorized);
+        }
+
+        if state.config.falls_back_to_global_secret(repository) {
+            tracing::warn!(%repository, "Validating a webhook with the global secret, as the repository does not have its own");
+        }
+    }
+
+    // Validate the payload with the secret key
+    let secret = state
+        .config
+        .resolve_secret(webhook.get_full_name())
+        .map(str::as_bytes);
+
+    // GitLab only ever sends the secret itself, rather than a signature
+    let algorithm = match variant {
+        WebhookVariant::GitLabPush => SignatureAlgorithm::Token,
+        _ => state
+            .config
+            .resolve_signature_algorithm(webhook.get_full_name()),
+    };
+
+    // Use the legacy SHA-1 signature if there is no SHA-256 one, preferring SHA-256 if both exist
+    let headers = request.headers();
+
+    let algorithm = match algorithm.fallback() {
+        Some(fallback)
+            if !headers.contains_key(algorithm.header())
+                && headers.contains_key(fallback.header()) =>
+        {
+            tracing::debug!(
+                ?fallback,
+                "Verifying the payload with the fallback algorithm"
+            );
+            fallback
+        }
+        _ => algorithm,
+    };
+
+    // Get the expected value as bytes
+    let expected = headers
+        .get(algorithm.header())
+        .map(HeaderValue::to_str)
+        .and_then(Result::ok)
+        .map(|s| algorithm.signature(s))
+        .map(str::as_bytes);
+
+    auth::validate_webhook_body(&bytes, secret, expected, algorithm)?;
+
+    tracing::debug!(?webhook, "Verified");
+
+    // Skip kinds of webhook the repository should not be deployed by
+    let variant = webhook.variant();
+
+    if !state
+        .config
+        .is_event_allowed(webhook.get_full_name(), variant)
+    {
+        tracing::info!(repository = %webhook.get_full_name(), ?variant, "Skipping a webhook kind that is not allowed for the repository");
+        return Ok(HttpResponse::Ok().finish());
+    }
+
+    let delivery_id = request
+        .headers()
+        .get("X-GitHub-Delivery")
+        .map(HeaderValue::to_str)
+        .and_then(Result::ok)
+        .map(String::from);
+
+    let mut delivery = Delivery::new(events::correlation_id(delivery_id.as_deref()), webhook);
+
+    state.events.lock().await.record(delivery_id, &delivery);
+
+    // Process the webhook before responding if the repository asks for it, waiting for it to be
+    // processed in turn with any others for the repository
+    if state
+        .config
+        .is_synchronous(delivery.webhook.get_full_name())
+    {
+        let timeout = state
+            .config
+            .resolve_synchronous_timeout(delivery.webhook.get_full_name());
+
+        let format = ResponseFormat::from(&request);
+        let (reply, outcome) = oneshot::channel();
+        delivery.replies.push(reply);
+
+        state.enqueue(delivery).await?;
+
+        return match tokio::time::timeout(timeout, outcome).await {
+            Ok(Ok(outcome)) => Ok(outcome.into_response(format)),
+            Ok(Err(_)) => Ok(HttpResponse::InternalServerError().finish()),
+            Err(_) => {
+                tracing::warn!(
+                    ?timeout,
+                    "Synchronous processing timed out, continuing in the background"
+                );
+                Ok(HttpResponse::Accepted().finish())
+            }
+        };
+    }
+
+    // Send the message to the other thread
+    state.enqueue(delivery).await?;
+
+    // Return an `Accepted` status code
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Processes webhooks from the channel until the sender is dropped.
+///
+/// Webhooks for different repositories are processed concurrently, while those for the same
+/// repository are queued for a task of its own and processed one at a time. Webhooks are held in
+/// these queues while processing is paused. After a successful deploy of `fisherman`'s own
+/// repository, new requests are refused and the process is replaced with the new binary once all
+/// queued webhooks have been processed and every request being handled has finished.
+pub async fn process_webhooks(
+    config: Arc<Config>,
+    mut receiver: mpsc::Receiver<Delivery>,
+    paused: watch::Receiver<bool>,
+) {
+    let (updates, mut pending_updates) = mpsc::unbounded_channel();
+    let mut workers = Workers::new(Arc::clone(&config), paused, updates);
+
+    loop {
+        tokio::select! {
+            delivery = receiver.recv() => match delivery {
+                Some(delivery) => workers.dispatch(delivery).await,
+                None => break,
+            },
+            Some(binary) = pending_updates.recv() => {
+                // Refuse any new requests, then finish everything queued or still being handled
+                config.in_flight.close();
+                drain(&config, &mut workers, &mut receiver).await;
+
+                tracing::info!(?binary, "Replacing the running process with the new binary");
+
+                let error = process::replace_current(&binary);
+                tracing::error!(%error, ?binary, "Failed to replace the running process");
+
+                config.in_flight.open();
+            }
+        }
+    }
+
+    workers.finish().await;
+}
+
+/// Processes every queued webhook, along with any queued by requests that are still being handled.
+///
+/// Requests such as synchronous webhooks may be waiting for their webhook to be processed, so
+/// webhooks continue to be processed until every request has finished and nothing else is queued.
+async fn drain(config: &Config, workers: &mut Workers, receiver: &mut mpsc::Receiver<Delivery>) {
+    loop {
+        workers.finish().await;
+
+        tokio::select! {
+            Some(delivery) = receiver.recv() => workers.dispatch(delivery).await,
+            () = config.in_flight.wait_until_idle() => {
+                let mut received = false;
+
+                while let Ok(delivery) = receiver.try_recv() {
+                    received = true;
+                    workers.dispatch(delivery).await;
+                }
+
+                if !received {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// The tasks processing webhooks, one for each repository that has received any.
+struct Workers {
+    config: Arc<Config>,
+    paused: watch::Receiver<bool>,
+    updates: mpsc::UnboundedSender<PathBuf>,
+    workers: HashMap<String, Worker>,
+}
+
+/// A task processing the webhooks of a single repository in the order they were received.
+struct Worker {
+    sender: mpsc::Sender<Delivery>,
+    handle: JoinHandle<()>,
+}
+
+impl Workers {
+    fn new(
+        config: Arc<Config>,
+        paused: watch::Receiver<bool>,
+        updates: mpsc::UnboundedSender<PathBuf>,
+    ) -> Self {
+        Self {
+            config,
+            paused,
+            updates,
+            workers: HashMap::new(),
+        }
+    }
+
+    /// Queues a webhook for the task of the repository it deploys, starting one if it isn't
+    /// running.
+    ///
+    /// Repositories are identified after following any alias or `Deploy-To` target, so webhooks
+    /// deploying the same repository are never processed at the same time. This waits while the
+    /// queue of the repository is full, which in turn fills the queue of incoming webhooks and
+    /// causes further ones to be rejected.
+    async fn dispatch(&mut self, delivery: Delivery) {
+        let repository = delivery
+            .webhook
+            .deployed_repository(&self.config)
+            .to_owned();
+
+        let delivery = match self.workers.get(&repository) {
+            Some(worker) => match worker.sender.send(delivery).await {
+                Ok(()) => return,
+                Err(mpsc::error::SendError(delivery)) => {
+                    tracing::warn!(%repository, "The task processing webhooks stopped, starting another");
+                    delivery
+                }
+            },
+            None => delivery,
+        };
+
+        let worker = self.spawn();
+
+        if worker.sender.send(delivery).await.is_err() {
+            tracing::error!(%repository, "Failed to queue a webhook for processing");
+        }
+
+        self.workers.insert(repository, worker);
+    }
+
+    /// Starts a task processing the webhooks of a single repository.
+    fn spawn(&self) -> Worker {
+        let (sender, receiver) = mpsc::channel(self.config.resolve_queue_capacity());
+
+        let handle = tokio::spawn(process_repository_webhooks(
+            Arc::clone(&self.config),
+            receiver,
+            self.paused.clone(),
+            self.updates.clone(),
+        ));
+
+        Worker { sender, handle }
+    }
+
+    /// Waits for every task to process the webhooks already queued for it.
+    async fn finish(&mut self) {
+        for (repository, worker) in self.workers.drain() {
+            drop(worker.sender);
+
+            if let Err(error) = worker.handle.await {
+                tracing::error!(%repository, %error, "The task processing webhooks failed");
+            }
+        }
+    }
+}
+
+/// Processes the webhooks of a single repository one at a time until the sender is dropped.
+///
+/// The binary to replace the running process with is sent to `updates` after a successful deploy
+/// of `fisherman`'s own repository.
+async fn process_repository_webhooks(
+    config: Arc<Config>,
+    mut receiver: mpsc::Receiver<Delivery>,
+    mut paused: watch::Receiver<bool>,
+    updates: mpsc::UnboundedSender<PathBuf>,
+) {
+    let mut backlog = VecDeque::new();
+
+    loop {
+        // Read a webhook message from the backlog or channel
+        let delivery = match backlog.pop_front() {
+            Some(delivery) => delivery,
+            None => match receiver.recv().await {
+                Some(delivery) => delivery,
+                None => return,
+            },
+        };
+
+        // Wait for processing to be resumed if it is paused
+        while *paused.borrow_and_update() {
+            tracing::info!(repository = %delivery.webhook.get_full_name(), "Processing is paused, waiting to be resumed");
+
+            if paused.changed().await.is_err() {
+                break;
+            }
+        }
+
+        // Wait for any further pushes that can be deployed together with this one
+        let Delivery {
+            correlation,
+            webhook,
+            replies,
+        } = coalesce(&config, delivery, &mut receiver, &mut backlog).await;
+
+        // Process its content, tying together everything it logs
+        let span = tracing::info_span!("webhook", %correlation);
+        let outcome = events::CORRELATION
+            .scope(correlation, webhook.handle(&config).instrument(span))
+            .await;
+
+        if outcome.is_deployed() {
+            if let Some(binary) = config.resolve_self_update_binary(webhook.get_full_name()) {
+                let _ = updates.send(binary);
+            }
+        }
+
+        // Anything waiting may have given up already, which is fine
+        for reply in replies {
+            let _ = reply.send(outcome.clone());
+        }
+    }
+}
+
+/// Waits for the coalescing window of a webhook's repository, returning the latest webhook it can
+/// be coalesced with.
+///
+/// Any webhooks for the repository received while waiting are added to the backlog, except for
+/// those that were coalesced, so that everything else is still processed in order. The latest
+/// webhook is still treated as forced if any it was coalesced with were, and anything waiting for
+/// the outcome of a coalesced webhook receives the outcome of the latest one instead.
+async fn coalesce(
+    config: &Config,
+    delivery: Delivery,
+    receiver: &mut mpsc::Receiver<Delivery>,
+    backlog: &mut VecDeque<Delivery>,
+) -> Delivery {
+    let window = config.resolve_coalesce_window(delivery.webhook.get_full_name());
+
+    if window.is_zero() || !matches!(delivery.webhook, Webhook::Push(_)) {
+        return delivery;
+    }
+
+    tracing::info!(repository = %delivery.webhook.get_full_name(), ?window, "Waiting for further pushes before deploying");
+
+    let deadline = tokio::time::Instant::now() + window;
+
+    while let Ok(Some(next)) = tokio::time::timeout_at(deadline, receiver.recv()).await {
+        backlog.push_back(next);
+    }
+
+    let mut latest = delivery;
+    let mut remaining = VecDeque::with_capacity(backlog.len());
+
+    for next in backlog.drain(..) {
+        if !latest.webhook.coalesces_with(&next.webhook) {
+            remaining.push_back(next);
+            continue;
+        }
+
+        let earlier = std::mem::replace(&mut latest, next);
+        latest.webhook.absorb(&earlier.webhook);
+        latest.replies.extend(earlier.replies);
+    }
+
+    *backlog = remaining;
+
+    latest
+}
@@ -1,297 +1,2855 @@
+use std::convert::TryFrom;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use actix_web::HttpResponse;
-use anyhow::{bail, Result};
+use actix_web::{HttpRequest, HttpResponse};
+use anyhow::{anyhow, bail, Context, Result};
+use regex::Regex;
+use serenity::http::{Http, HttpError, StatusCode};
+use serenity::model::id::ChannelId;
 use tokio::process::Command;
+use tokio::sync::OwnedSemaphorePermit;
+use tracing::Instrument;
 
-use crate::config::Config;
-use crate::git;
+use crate::config::{
+    CommandContext, Config, MatchMode, Stage, Strategy, SuccessCriteria, VerifyRestart,
+};
+use crate::error::{DeployError, ServerError};
+use crate::events::Activity;
+use crate::github::{ChecksOutcome, DeploymentState, GitHubClient};
+use crate::inflight::InFlight;
+use crate::{batch, git, process, retry};
 
-#[derive(Debug, Deserialize)]
-pub struct User {
-    name: String,
+/// Represents the kinds of webhook that can be received, based on the event header.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookVariant {
+    Push,
+    Ping,
+    RepositoryDispatch,
+    /// A push from Bitbucket, which is handled as a regular push once it has been parsed
+    #[serde(skip)]
+    BitbucketPush,
+    /// A push from GitLab, which is handled as a regular push once it has been parsed
+    #[serde(skip)]
+    GitLabPush,
+    /// A deploy requested through the API, which is never received as a webhook
+    Deploy,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Commit {
-    id: String,
-    message: String,
-    author: User,
+impl TryFrom<&HttpRequest> for WebhookVariant {
+    type Error = ServerError;
+
+    fn try_from(request: &HttpRequest) -> Result<Self, Self::Error> {
+        let headers = request.headers();
+
+        // Bitbucket sends the kind of event in its own header
+        if let Some(key) = headers.get("X-Event-Key").and_then(|v| v.to_str().ok()) {
+            tracing::debug!(%key, "Received an X-Event-Key header");
+
+            return match key {
+                "repo:push" => Ok(Self::BitbucketPush),
+                _ => Err(ServerError::BadRequest),
+            };
+        }
+
+        // GitLab does the same, naming its events differently
+        if let Some(event) = headers.get("X-Gitlab-Event").and_then(|v| v.to_str().ok()) {
+            tracing::debug!(%event, "Received an X-Gitlab-Event header");
+
+            return match event {
+                "Push Hook" => Ok(Self::GitLabPush),
+                _ => Err(ServerError::BadRequest),
+            };
+        }
+
+        // Decide the variant to parse based on the headers
+        let header = match headers.get("X-GitHub-Event").and_then(|v| v.to_str().ok()) {
+            Some(variant) => variant,
+            None => return Err(ServerError::BadRequest),
+        };
+
+        tracing::debug!(%header, "Received an X-GitHub Event header");
+
+        match header {
+            "push" => Ok(Self::Push),
+            "ping" => Ok(Self::Ping),
+            "repository_dispatch" => Ok(Self::RepositoryDispatch),
+            _ => Err(ServerError::BadRequest),
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Push {
-    #[serde(rename = "ref")]
-    refname: String,
-    repository: Repository,
-    head_commit: Commit,
+/// The format of the body returned when processing a webhook fails.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResponseFormat {
+    Text,
+    Json,
 }
 
-impl Push {
-    /// Checks whether the push request is to the followed branch of a repository.
-    fn changes_follow_branch(&self, follow: &str) -> bool {
-        let formatted = format!("refs/heads/{}", follow);
+impl From<&HttpRequest> for ResponseFormat {
+    fn from(request: &HttpRequest) -> Self {
+        let accepts_json = request
+            .headers()
+            .get("Accept")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|accept| accept.contains("application/json"));
 
-        formatted == self.refname
+        if accepts_json {
+            Self::Json
+        } else {
+            Self::Text
+        }
     }
+}
 
-    /// Triggers a `git pull` for the repository associated with the webhook.
-    ///
-    /// This will open the repository, which is assumed to be at `repo_root` and fetch the contents
-    /// of its default branch (which can be `master`, `main` or whatever the default is set to). It
-    /// will then merge the contents of the fetch.
-    fn trigger_pull(&self, config: &Arc<Config>) -> Result<()> {
-        let path = config.default.repo_root.join(&self.repository.name);
-        let repo = git2::Repository::open(&path)?;
-        let branch = config.resolve_follow_branch(&self.repository.full_name);
+/// Describes why processing a webhook failed, for programmatic consumers.
+#[derive(Clone, Debug, Serialize)]
+pub struct Failure {
+    /// The full name of the repository that failed to deploy
+    repository: String,
+    /// The stage of the deploy that failed, if the failure happened during one
+    stage: Option<Stage>,
+    /// The error that caused the failure
+    error: String,
+}
 
-        tracing::info!(?path, %branch, "Fetching changes for the project");
+impl Failure {
+    /// Gets the error that caused the failure.
+    pub fn error(&self) -> &str {
+        &self.error
+    }
+    /// Creates a response describing the failure in the given format.
+    fn into_response(self, format: ResponseFormat) -> HttpResponse {
+        match format {
+            ResponseFormat::Text => HttpResponse::InternalServerError().body(self.error),
+            ResponseFormat::Json => HttpResponse::InternalServerError().json(self),
+        }
+    }
+}
 
-        let mut remote = repo.find_remote("origin")?;
+/// The outcome of handling a webhook, which can be sent to any request waiting for it before being
+/// turned into a response.
+#[derive(Clone, Debug)]
+pub enum Outcome {
+    /// The webhook was handled, along with a message to respond with if there is one
+    Handled(Option<String>),
+    /// The webhook was handled by deploying the repository
+    Deployed,
+    /// The webhook was handled by deploying the repository without some of its binaries
+    PartiallyDeployed(Failure),
+    /// Handling the webhook failed
+    Failed(Failure),
+}
 
-        let fetch_commit = git::fetch(
-            &repo,
-            &[branch],
-            &mut remote,
-            &config.default.ssh_private_key,
-        )?;
+impl Outcome {
+    /// Checks whether the webhook was handled without failing.
+    pub fn is_success(&self) -> bool {
+        matches!(
+            self,
+            Self::Handled(_) | Self::Deployed | Self::PartiallyDeployed(_)
+        )
+    }
 
-        Ok(git::merge(&repo, branch, &fetch_commit)?)
+    /// Checks whether the webhook deployed the repository.
+    pub fn is_deployed(&self) -> bool {
+        matches!(self, Self::Deployed | Self::PartiallyDeployed(_))
     }
 
-    /// Runs any precommands specified in the config.
-    ///
-    /// Commands will be run in the `code_root` directory and will simply be executed by the shell.
-    async fn run_precommands(&self, config: &Arc<Config>) -> Result<()> {
-        if let Some(commands) = config.resolve_precommands(&self.repository.full_name) {
-            let repo_path = config.default.repo_root.join(&self.repository.name);
-            commands.execute(&repo_path).await?;
+    /// Creates a response describing the outcome, using the given format for failures.
+    pub fn into_response(self, format: ResponseFormat) -> HttpResponse {
+        match self {
+            Self::Handled(Some(message)) => HttpResponse::Ok().body(message),
+            Self::Handled(None) | Self::Deployed => HttpResponse::Ok().finish(),
+            Self::PartiallyDeployed(failure) => match format {
+                ResponseFormat::Text => HttpResponse::Ok().body(failure.error),
+                ResponseFormat::Json => HttpResponse::Ok().json(failure),
+            },
+            Self::Failed(failure) => failure.into_response(format),
         }
-
-        Ok(())
     }
+}
 
-    /// Triggers the recompilation of a repository associated with the webhook.
-    ///
-    /// This should be run after pulling the new changes to update the repository. After being
-    /// rebuilt, it can be restarted in `supervisor` and the new changes will go live.
-    async fn trigger_build(&self, config: &Arc<Config>) -> Result<()> {
-        if !config.should_build_binaries(&self.repository.full_name) {
-            tracing::info!(
-                repo = %self.repository.full_name,
-                "Not building any binaries for the repository as set in the configuration"
-            );
+/// How a deploy that did not fail finished.
+#[derive(Debug)]
+pub enum Deployment {
+    /// The deploy was skipped, such as when the repository is missing its required file
+    Skipped,
+    /// Every binary was deployed
+    Complete,
+    /// The deploy finished without some binaries, which failed for the given reason
+    Partial(anyhow::Error),
+}
 
-            return Ok(());
+impl Deployment {
+    /// Checks whether the deploy went ahead, even if some binaries were left out.
+    pub fn went_ahead(&self) -> bool {
+        !matches!(self, Self::Skipped)
+    }
+
+    /// Gets why some binaries were left out of the deploy, if any were.
+    pub fn partial_error(&self) -> Option<&anyhow::Error> {
+        match self {
+            Self::Partial(error) => Some(error),
+            Self::Skipped | Self::Complete => None,
         }
+    }
+}
 
-        let code_root = config.resolve_code_root(&self.repository.full_name);
-        let binaries = config.resolve_binaries(&self.repository.full_name);
+/// The outcome of a deploy, written to the `result_file` of a repository for other tooling.
+#[derive(Debug, Serialize)]
+struct DeployResult<'a> {
+    repository: &'a str,
+    branch: &'a str,
+    commit: &'a str,
+    success: bool,
+    /// How long the deploy took, in milliseconds
+    duration_ms: u128,
+    /// When the deploy finished, in seconds since the Unix epoch
+    finished_at: u64,
+    error: Option<String>,
+}
 
-        let path = &config
-            .default
-            .repo_root
-            .join(&self.repository.name)
-            .join(&code_root);
+impl DeployResult<'_> {
+    /// Writes the result to a file, replacing it atomically so readers never see a partial result.
+    fn write_to(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
 
-        tracing::info!(?path, "Rebuilding binaries");
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&temporary, path)?;
 
-        for binary in binaries {
-            tracing::info!(%binary, "Building a specific binary");
+        Ok(())
+    }
+}
 
-            let status = Command::new(config.default.cargo_path.clone())
-                .args(["build", "--release", "--bin", &binary])
-                .current_dir(path)
-                .spawn()?
-                .wait()
-                .await?;
+/// Gets the stage of a deploy that caused an error, if any.
+fn failed_stage(error: &anyhow::Error) -> Option<Stage> {
+    error.downcast_ref::<DeployError>().map(|e| e.stage)
+}
 
-            if !status.success() {
-                bail!("Failed to build binary: {}", binary);
-            }
+/// Represents any of the webhooks that can be received.
+#[derive(Clone, Debug)]
+pub enum Webhook {
+    Push(Push),
+    Ping(Ping),
+    RepositoryDispatch(RepositoryDispatch),
+    /// A deploy requested through the API, queued in the same way so that it waits its turn
+    Deploy(ManualDeploy),
+}
+
+impl Webhook {
+    /// Gets the full name of the repository this hook refers to.
+    pub fn get_full_name(&self) -> &str {
+        match self {
+            Webhook::Ping(p) => p.get_full_name(),
+            Webhook::Push(p) => p.get_full_name(),
+            Webhook::RepositoryDispatch(d) => d.get_full_name(),
+            Webhook::Deploy(d) => d.get_full_name(),
         }
+    }
 
-        Ok(())
+    /// Gets the kind of webhook this is.
+    pub fn variant(&self) -> WebhookVariant {
+        match self {
+            Webhook::Ping(_) => WebhookVariant::Ping,
+            Webhook::Push(_) => WebhookVariant::Push,
+            Webhook::RepositoryDispatch(_) => WebhookVariant::RepositoryDispatch,
+            Webhook::Deploy(_) => WebhookVariant::Deploy,
+        }
     }
 
-    /// Triggers a process restart by `supervisor`.
+    /// Handles the payload of the request depending on its type.
     ///
-    /// Restarts the process within `supervisor`, allowing a new version to supersede the existing
-    /// version.
-    async fn trigger_restart(&self, config: &Arc<Config>) -> Result<()> {
-        if !config.should_build_binaries(&self.repository.full_name) {
-            tracing::info!(
-                repo = %self.repository.full_name,
-                "Not restarting any processes for this webhook"
-            );
-
-            return Ok(());
+    /// If processing fails, the outcome will describe why.
+    pub async fn handle(&self, config: &Arc<Config>) -> Outcome {
+        match self {
+            Webhook::Ping(p) => p.handle(config).await,
+            Webhook::Push(p) => p.handle(config).await,
+            Webhook::RepositoryDispatch(d) => d.handle(config).await,
+            Webhook::Deploy(d) => d.handle(config).await,
         }
+    }
 
-        let binaries = config.resolve_binaries(&self.repository.full_name);
-
-        for binary in binaries {
-            tracing::info!(%binary, "Allowing `supervisor` to restart");
+    /// Gets the configured repository this webhook deploys, following any alias and the
+    /// `Deploy-To` target of a push.
+    ///
+    /// Webhooks for the same repository must be processed one at a time, while those for
+    /// different ones can be processed concurrently.
+    pub fn deployed_repository<'a>(&'a self, config: &'a Config) -> &'a str {
+        let repository = match self {
+            Webhook::Push(p) => p.target_key(config).unwrap_or(p.get_full_name()),
+            _ => self.get_full_name(),
+        };
 
-            let status = Command::new("supervisorctl")
-                .args(["restart", &binary])
-                .spawn()?
-                .wait()
-                .await?;
+        config.resolve_alias(repository)
+    }
 
-            if !status.success() {
-                bail!("Failed to restart binary: {}", binary);
+    /// Checks whether this webhook can be coalesced with a later one, such that only the later one
+    /// needs to be processed.
+    ///
+    /// This is only the case for pushes to the same branch of the same repository, as deploying
+    /// the later push will also deploy the changes of the earlier one.
+    pub fn coalesces_with(&self, later: &Webhook) -> bool {
+        match (self, later) {
+            (Webhook::Push(earlier), Webhook::Push(later)) => {
+                earlier.get_full_name() == later.get_full_name() && earlier.refname == later.refname
             }
+            _ => false,
         }
-
-        Ok(())
     }
 
-    /// Runs any additional commands specified in the config.
+    /// Takes over anything from an earlier webhook that was coalesced into this one which still
+    /// needs to be handled.
     ///
-    /// Commands will be run in the `code_root` directory and will simply be executed by the shell.
-    async fn run_additional_commands(&self, config: &Arc<Config>) -> Result<()> {
-        if let Some(commands) = config.resolve_commands(&self.repository.full_name) {
-            let repo_path = config.default.repo_root.join(&self.repository.name);
-            commands.execute(&repo_path).await?;
+    /// A forced push rewrote the history of the branch, so later pushes on top of it must replace
+    /// the checkout as well rather than being merged into it.
+    pub fn absorb(&mut self, earlier: &Webhook) {
+        if let (Webhook::Push(later), Webhook::Push(earlier)) = (self, earlier) {
+            later.forced |= earlier.forced;
         }
-
-        Ok(())
     }
 
-    /// Notifies a Discord channel of the changes if a configuration exists.
-    async fn notify_discord_channel(&self, config: &Arc<Config>) {
-        let (client, channel_id) = match config.get_client_and_channel_id() {
-            Some((client, channel_id)) => (client, channel_id),
-            None => return,
+    /// Deserializes JSON from bytes depending on which variant is expected.
+    pub fn from_slice(variant: WebhookVariant, bytes: &[u8]) -> serde_json::Result<Self> {
+        let webhook = match variant {
+            WebhookVariant::Push => Self::Push(serde_json::from_slice(bytes)?),
+            WebhookVariant::Ping => Self::Ping(serde_json::from_slice(bytes)?),
+            WebhookVariant::RepositoryDispatch => {
+                Self::RepositoryDispatch(serde_json::from_slice(bytes)?)
+            }
+            WebhookVariant::BitbucketPush => {
+                let push: BitbucketPush = serde_json::from_slice(bytes)?;
+                Self::Push(Push::try_from(push).map_err(serde::de::Error::custom)?)
+            }
+            WebhookVariant::GitLabPush => {
+                let push: GitLabPush = serde_json::from_slice(bytes)?;
+                Self::Push(Push::try_from(push).map_err(serde::de::Error::custom)?)
+            }
+            WebhookVariant::Deploy => {
+                return Err(serde::de::Error::custom(
+                    "deploys are requested through the API rather than received",
+                ))
+            }
         };
 
-        // Generate the message to send
-        let brief = self.head_commit.message.lines().next().unwrap_or_default();
-
-        let repository = &self.repository.full_name;
-        let author = &self.head_commit.author.name;
-        let commit_id = &self.head_commit.id[..8];
+        Ok(webhook)
+    }
+}
 
-        let message = format!(
-            "Production instance of `{}` has been successfully updated to `commit_id={}` (`{}`), authored by {}",
-            repository, commit_id, brief, author
-        );
+#[derive(Clone, Debug, Deserialize)]
+pub struct User {
+    name: String,
+    email: Option<String>,
+    username: Option<String>,
+}
 
-        channel_id
-            .send_message(&client, |m| m.content(message))
-            .await
-            .expect("Failed to send the message to the channel");
+impl User {
+    /// Checks whether this user is the given identity, by name, email or username.
+    fn is(&self, identity: &str) -> bool {
+        self.name == identity
+            || self.email.as_deref() == Some(identity)
+            || self.username.as_deref() == Some(identity)
     }
+}
 
-    /// Notifies a Discord channel of a failure in the handling of a webhook.
-    async fn notify_of_failure(&self, config: &Arc<Config>, error: &str) {
-        let (client, channel_id) = match config.get_client_and_channel_id() {
-            Some((client, channel_id)) => (client, channel_id),
-            None => return,
+#[derive(Clone, Debug, Deserialize)]
+pub struct Commit {
+    id: String,
+    message: String,
+    author: User,
+}
+
+/// The parts of a Bitbucket `repo:push` payload that are needed to handle it as a push.
+#[derive(Debug, Deserialize)]
+struct BitbucketPush {
+    push: BitbucketChanges,
+    repository: BitbucketRepository,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketChanges {
+    changes: Vec<BitbucketChange>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketChange {
+    new: Option<BitbucketReference>,
+    old: Option<BitbucketReference>,
+    #[serde(default)]
+    forced: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketReference {
+    #[serde(rename = "type")]
+    kind: String,
+    name: String,
+    target: BitbucketCommit,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketCommit {
+    hash: String,
+    #[serde(default)]
+    message: String,
+    author: BitbucketAuthor,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketAuthor {
+    /// The author as written in the commit, such as `Name <email>`
+    raw: String,
+    user: Option<BitbucketUser>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketUser {
+    display_name: Option<String>,
+    nickname: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketRepository {
+    full_name: String,
+    links: Option<BitbucketLinks>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketLinks {
+    html: Option<BitbucketLink>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketLink {
+    href: String,
+}
+
+impl From<BitbucketAuthor> for User {
+    fn from(author: BitbucketAuthor) -> Self {
+        let (name, email) = match author.raw.split_once('<') {
+            Some((name, email)) => (name.trim(), Some(email.trim_end_matches('>').trim())),
+            None => (author.raw.trim(), None),
         };
 
-        let message = format!(
-            "Production instance of `{}` failed to be updated, error: {}",
-            self.repository.full_name, error
-        );
+        let user = author.user;
 
-        channel_id
-            .send_message(&client, |m| m.content(message))
-            .await
-            .expect("Failed to send the message to the channel");
+        Self {
+            name: user
+                .as_ref()
+                .and_then(|u| u.display_name.clone())
+                .unwrap_or_else(|| name.to_owned()),
+            email: email.map(String::from),
+            username: user.and_then(|u| u.nickname),
+        }
     }
+}
 
-    /// Handles the webhook message for push messages.
-    ///
-    /// Checks whether the message updates the followed branch before pulling the changes,
-    /// rebuilding all binaries, restarting them and running any additional commands provided in
-    /// the configuration. If this all succeeds, informs the Discord channel if this is specified
-    /// in the configuration as well.
-    async fn handle_inner(
-        &self,
-        config: &Arc<Config>,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
-        // Get the branch that this repository follows
-        let follow_branch = config.resolve_follow_branch(self.get_full_name());
+impl TryFrom<BitbucketPush> for Push {
+    type Error = &'static str;
 
-        if self.changes_follow_branch(follow_branch) {
-            tracing::info!(%follow_branch, "Commits were pushed to the followed branch in this event");
+    /// Maps the first change to a branch onto a push, as Bitbucket may batch several changes.
+    fn try_from(payload: BitbucketPush) -> Result<Self, Self::Error> {
+        let (new, old, forced) = payload
+            .push
+            .changes
+            .into_iter()
+            .filter_map(|c| Some((c.new?, c.old, c.forced)))
+            .find(|(new, _, _)| new.kind == "branch")
+            .ok_or("the push did not update any branches")?;
 
-            // Pull the new changes
-            self.trigger_pull(config)?;
+        let full_name = payload.repository.full_name;
 
-            // Run any precommands that have been setup
-            self.run_precommands(config).await?;
+        let repository = Repository {
+            ssh_url: Some(format!("git@bitbucket.org:{}.git", full_name)),
+            clone_url: Some(format!("https://bitbucket.org/{}.git", full_name)),
+            html_url: payload
+                .repository
+                .links
+                .and_then(|l| l.html)
+                .map(|l| l.href),
+            full_name,
+        };
 
-            // Build the updated binary
-            self.trigger_build(config).await?;
+        Ok(Self {
+            refname: format!("refs/heads/{}", new.name),
+            repository,
+            head_commit: Commit {
+                id: new.target.hash.clone(),
+                message: new.target.message,
+                author: User::from(new.target.author),
+            },
+            forced,
+            before: old.map(|old| old.target.hash),
+            after: Some(new.target.hash),
+        })
+    }
+}
 
-            // Restart in `supervisor`
-            self.trigger_restart(config).await?;
+/// The parts of a GitLab `Push Hook` payload that are needed to handle it as a push.
+#[derive(Debug, Deserialize)]
+struct GitLabPush {
+    #[serde(rename = "ref")]
+    refname: String,
+    before: Option<String>,
+    after: Option<String>,
+    /// The commit at the head of the branch after the push, or nothing if it was deleted
+    checkout_sha: Option<String>,
+    project: GitLabProject,
+    commits: Vec<GitLabCommit>,
+}
 
-            // Run any additional commands
-            self.run_additional_commands(config).await?;
+#[derive(Debug, Deserialize)]
+struct GitLabProject {
+    path_with_namespace: String,
+    git_ssh_url: Option<String>,
+    git_http_url: Option<String>,
+    web_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabCommit {
+    id: String,
+    message: String,
+    author: GitLabAuthor,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabAuthor {
+    name: String,
+    email: Option<String>,
+}
+
+impl TryFrom<GitLabPush> for Push {
+    type Error = &'static str;
+
+    /// Maps the commit at the head of the branch onto the head commit of a push.
+    fn try_from(payload: GitLabPush) -> Result<Self, Self::Error> {
+        let head = payload
+            .checkout_sha
+            .as_deref()
+            .ok_or("the push deleted the branch")?;
+
+        let commit = payload
+            .commits
+            .into_iter()
+            .find(|c| c.id == head)
+            .ok_or("the push did not include its head commit")?;
+
+        let project = payload.project;
+
+        Ok(Self {
+            refname: payload.refname,
+            repository: Repository {
+                full_name: project.path_with_namespace,
+                ssh_url: project.git_ssh_url,
+                clone_url: project.git_http_url,
+                html_url: project.web_url,
+            },
+            head_commit: Commit {
+                id: commit.id,
+                message: commit.message,
+                author: User {
+                    name: commit.author.name,
+                    email: commit.author.email,
+                    username: None,
+                },
+            },
+            forced: false,
+            before: payload.before,
+            after: payload.after,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Push {
+    #[serde(rename = "ref")]
+    refname: String,
+    repository: Repository,
+    head_commit: Commit,
+    /// Whether the push rewrote the history of the branch
+    #[serde(default)]
+    forced: bool,
+    /// The commit the branch pointed to before the push
+    before: Option<String>,
+    /// The commit the branch points to after the push
+    after: Option<String>,
+}
 
-            // Everything worked, so update the Discord channel if there is one
-            self.notify_discord_channel(config).await;
+impl Push {
+    /// Gets the branch this push should deploy, if any.
+    ///
+    /// Pushes to branches matching the `ignore_branches` patterns are never deployed, even if the
+    /// branch is also followed.
+    fn deployed_branch(&self, config: &Config) -> Option<&str> {
+        self.deployed_branch_in(
+            config,
+            &config.resolve_follow_branches(self.get_full_name()),
+        )
+    }
+
+    /// Gets the branch this push should deploy out of the given followed branches, if any.
+    fn deployed_branch_in(&self, config: &Config, follow: &[&str]) -> Option<&str> {
+        let repository = self.get_full_name();
+        let branch = self.refname.strip_prefix("refs/heads/")?;
+
+        if config.is_branch_ignored(repository, branch) {
+            tracing::info!(%branch, %repository, "Ignoring a push to an ignored branch");
+            return None;
         }
 
-        Ok(())
+        self.changes_follow_branch(follow, config.resolve_match_mode(repository))
     }
 
-    /// Wraps the [`handle_inner`] method by propagating errors correctly.
-    pub async fn handle(&self, config: &Arc<Config>) -> HttpResponse {
-        match self.handle_inner(config).await {
-            Ok(()) => HttpResponse::Ok().finish(),
+    /// Checks whether the push is to a branch that could be deployed, whichever branches are
+    /// followed, as tags and ignored branches never are.
+    fn could_be_deployed(&self, config: &Config) -> bool {
+        self.refname
+            .strip_prefix("refs/heads/")
+            .is_some_and(|branch| !config.is_branch_ignored(self.get_full_name(), branch))
+    }
+
+    /// Reads the branch to follow from the `follow_file` of the repository, if it has one.
+    ///
+    /// The file is read from the default branch of the remote, so the repository controls which
+    /// branch is deployed. If it cannot be read, the configured branches are followed instead.
+    async fn read_follow_file(&self, config: &Arc<Config>) -> Option<String> {
+        let file = config.resolve_follow_file(self.get_full_name())?;
+
+        match self.repository.read_remote_file(config, file).await {
+            Ok(Some(branch)) => Some(branch),
+            Ok(None) => {
+                tracing::warn!(
+                    ?file,
+                    "The follow file does not exist, using the configured branches"
+                );
+                None
+            }
             Err(e) => {
-                let error = e.to_string();
-                self.notify_of_failure(config, &error).await;
-                HttpResponse::InternalServerError().body(error)
+                tracing::warn!(?file, error = ?e, "Failed to read the follow file, using the configured branches");
+                None
             }
         }
     }
 
-    /// Retrieves the full name of the repository this webhook relates to.
-    pub fn get_full_name(&self) -> &str {
-        &self.repository.full_name
+    /// Describes the push to any commands that are run for it.
+    fn command_context(&self) -> CommandContext<'_> {
+        CommandContext {
+            branch: self.refname.strip_prefix("refs/heads/"),
+            commit: Some(&self.head_commit.id),
+            author: Some(&self.head_commit.author.name),
+            ..CommandContext::new(self.get_full_name())
+        }
     }
-}
 
-#[derive(Debug, Deserialize)]
-pub struct Ping {
-    hook: Hook,
-    repository: Repository,
-}
+    /// Checks whether the push was made by the `ignore_author` of the repository, such as a bot
+    /// pushing from a deploy command, which would otherwise trigger another deploy.
+    fn is_from_ignored_author(&self, config: &Config) -> bool {
+        config
+            .resolve_ignored_author(self.get_full_name())
+            .is_some_and(|author| self.head_commit.author.is(author))
+    }
 
-impl Ping {
-    pub fn get_full_name(&self) -> &str {
-        &self.repository.full_name
+    /// Checks whether the push left the branch pointing at the same commit, such as when a push is
+    /// replayed, in which case there is nothing to deploy.
+    fn is_no_op(&self) -> bool {
+        matches!((&self.before, &self.after), (Some(before), Some(after)) if before == after)
     }
 
-    pub async fn handle(&self, _config: &Arc<Config>) -> HttpResponse {
-        let body = format!(
-            "Setup tracking of `{}` at url: {}",
-            self.repository.full_name, self.hook.config.url
-        );
+    /// Gets the branch the push is to if it matches any of the followed branches of a repository,
+    /// which are interpreted according to the match mode.
+    fn changes_follow_branch(&self, follow: &[&str], mode: MatchMode) -> Option<&str> {
+        let branch = self.refname.strip_prefix("refs/heads/")?;
 
-        HttpResponse::Ok().body(body)
+        follow
+            .iter()
+            .any(|pattern| mode.matches(pattern, branch))
+            .then_some(branch)
     }
-}
 
-#[derive(Debug, Deserialize)]
-pub struct Repository {
-    name: String,
-    full_name: String,
-}
+    /// Notifies a Discord channel of the changes if a configuration exists, mentioning any
+    /// binaries that were left out of the deploy.
+    async fn notify_discord_channel(
+        &self,
+        config: &Arc<Config>,
+        branch: &str,
+        deployment: &Deployment,
+    ) {
+        // Generate the message to send
+        let repository = &self.repository.full_name;
+        let author = &self.head_commit.author.name;
+        let commit_id = &self.head_commit.id[..8];
 
-#[derive(Debug, Deserialize)]
-pub struct Hook {
-    #[serde(rename = "type")]
-    config: HookConfig,
-}
+        let mut message = if config.should_include_commit_message(repository) {
+            let brief = self.head_commit.message.lines().next().unwrap_or_default();
 
-#[derive(Debug, Deserialize)]
+            format!(
+                "Production instance of `{}` has been successfully updated to `commit_id={}` (`{}`), authored by {}",
+                repository, commit_id, brief, author
+            )
+        } else {
+            format!(
+                "Production instance of `{}` has been successfully updated to `commit_id={}`, authored by {}",
+                repository, commit_id, author
+            )
+        };
+
+        if self.forced {
+            message.push_str(" after a force-push");
+        }
+
+        // Link the pull request the commit was merged from, if there was one
+        if let Some(url) = self.pull_request_url() {
+            message.push_str(&format!(", merged from {}", url));
+        }
+
+        if let Some(error) = deployment.partial_error() {
+            message.push_str(&partial_suffix(error));
+        }
+
+        send_discord_message(config, Some(branch), message).await;
+    }
+
+    /// Gets the repository to deploy, based on any `Deploy-To` trailer in the head commit message.
+    ///
+    /// Targets are mapped to other keys in the configuration, whose settings and clone will be used
+    /// for the deploy instead. If there is no trailer, or the target is unknown, the repository
+    /// itself will be deployed.
+    fn target_repository(&self, config: &Config) -> Repository {
+        let repository = self.get_full_name();
+
+        let target = match deploy_target(&self.head_commit.message) {
+            Some(target) => target,
+            None => return self.repository.clone(),
+        };
+
+        match self.target_key(config) {
+            Some(key) => {
+                tracing::info!(%repository, %target, %key, "Deploying to the target selected by the commit");
+                self.repository.with_full_name(key)
+            }
+            None => {
+                tracing::warn!(%repository, %target, "Ignoring an unknown deploy target");
+                self.repository.clone()
+            }
+        }
+    }
+
+    /// Gets the configured repository selected by the `Deploy-To` trailer of the head commit, if it
+    /// names a known target.
+    fn target_key<'a>(&self, config: &'a Config) -> Option<&'a str> {
+        let target = deploy_target(&self.head_commit.message)?;
+        config.resolve_target(self.get_full_name(), target)
+    }
+
+    /// Gets the URL of the pull request the head commit was merged from, if there was one.
+    ///
+    /// GitHub includes the number of the pull request at the end of the first line of squash and
+    /// merge commits, such as `Add a feature (#123)`.
+    fn pull_request_url(&self) -> Option<String> {
+        let html_url = self.repository.html_url.as_deref()?;
+        let brief = self.head_commit.message.lines().next()?;
+        let number = pull_request_number(brief)?;
+
+        Some(format!("{}/pull/{}", html_url, number))
+    }
+
+    /// Waits for the required checks of the head commit to pass, if the repository has any.
+    ///
+    /// Returns whether the push should be deployed, notifying the Discord channel if it will not be
+    /// because a check failed or did not complete in time.
+    async fn wait_for_required_checks(&self, config: &Arc<Config>, branch: &str) -> Result<bool> {
+        let repository = self.get_full_name();
+
+        let checks = match config.resolve_required_checks(repository) {
+            Some(checks) => checks,
+            None => return Ok(true),
+        };
+
+        let client = config
+            .get_github_client()
+            .context("Required checks are configured, but the GitHub API is not")?;
+
+        let commit = &self.head_commit.id;
+        tracing::info!(%repository, %commit, contexts = ?checks.contexts, "Waiting for the required checks to pass");
+
+        let outcome = client
+            .wait_for_checks(
+                repository,
+                commit,
+                &checks.contexts,
+                checks.timeout(),
+                checks.interval(),
+            )
+            .await?;
+
+        let reason = match outcome {
+            ChecksOutcome::Passed => return Ok(true),
+            ChecksOutcome::Pending => String::from("the required checks did not complete in time"),
+            ChecksOutcome::Failed(context) => format!("the required check `{}` failed", context),
+        };
+
+        tracing::warn!(%repository, %commit, %reason, "Not deploying a push");
+
+        let message = format!(
+            "Production instance of `{}` was not updated to `commit_id={}` as {}",
+            repository,
+            &commit[..8],
+            reason
+        );
+        send_discord_message(config, Some(branch), message).await;
+
+        Ok(false)
+    }
+
+    /// Creates a GitHub deployment for the head commit if the repository reports deploys.
+    async fn start_github_deployment(&self, config: &Arc<Config>) -> Option<(GitHubClient, u64)> {
+        let repository = &self.repository.full_name;
+
+        if !config.should_create_github_deployments(repository) {
+            return None;
+        }
+
+        let client = config.get_github_client()?;
+
+        let id = match client
+            .create_deployment(repository, &self.head_commit.id)
+            .await
+        {
+            Ok(id) => id,
+            Err(e) => {
+                tracing::error!(%e, %repository, "Failed to create a GitHub deployment");
+                return None;
+            }
+        };
+
+        self.update_github_deployment(&client, id, DeploymentState::InProgress)
+            .await;
+
+        Some((client, id))
+    }
+
+    /// Updates the state of a GitHub deployment and the status of its commit, logging any failures.
+    async fn update_github_deployment(
+        &self,
+        client: &GitHubClient,
+        id: u64,
+        state: DeploymentState,
+    ) {
+        let repository = &self.repository.full_name;
+
+        if let Err(e) = client.update_deployment_status(repository, id, state).await {
+            tracing::error!(%e, %repository, ?state, "Failed to update a GitHub deployment");
+        }
+
+        if let Err(e) = client
+            .create_commit_status(repository, &self.head_commit.id, state)
+            .await
+        {
+            tracing::error!(%e, %repository, ?state, "Failed to update the commit status");
+        }
+    }
+
+    /// Writes the result of a deploy to the `result_file` of the repository, if it has one.
+    fn write_result_file(
+        &self,
+        config: &Config,
+        branch: &str,
+        result: &Result<Deployment>,
+        duration: Duration,
+    ) {
+        let repository = self.get_full_name();
+
+        let path = match config.resolve_result_file(repository) {
+            Some(path) => path,
+            None => return,
+        };
+
+        let finished_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+
+        let deploy_result = DeployResult {
+            repository,
+            branch,
+            commit: &self.head_commit.id,
+            success: result.is_ok(),
+            duration_ms: duration.as_millis(),
+            finished_at,
+            error: match result {
+                Ok(deployment) => deployment.partial_error().map(ToString::to_string),
+                Err(e) => Some(e.to_string()),
+            },
+        };
+
+        if let Err(e) = deploy_result.write_to(&path) {
+            tracing::error!(%repository, ?path, error = ?e, "Failed to write the result file");
+        }
+    }
+
+    /// Handles the webhook message for push messages.
+    ///
+    /// Runs any commands that apply to every push, then checks whether the message updates the
+    /// followed branch before pulling the changes, rebuilding all binaries, restarting them and
+    /// running any additional commands provided in the configuration. If this all succeeds,
+    /// informs the Discord channel if this is specified in the configuration as well. Returns
+    /// how the repository was deployed.
+    async fn handle_inner(&self, config: &Arc<Config>) -> Result<Deployment> {
+        // Ignore pushes that did not change anything
+        if self.is_no_op() {
+            tracing::info!(after = ?self.after, "Ignoring a push that did not change the branch");
+            return Ok(Deployment::Skipped);
+        }
+
+        // Ignore pushes made by deploys themselves to avoid deploying in a loop
+        if self.is_from_ignored_author(config) {
+            let author = &self.head_commit.author.name;
+            tracing::info!(%author, "Ignoring a push from the ignored author");
+            return Ok(Deployment::Skipped);
+        }
+
+        // Run any commands that apply to every push
+        self.repository
+            .run_always_commands(config, &self.command_context())
+            .await?;
+
+        // Check whether this push is to a branch that should be deployed, which the repository may
+        // decide for itself, although reading that needs the remote so is skipped where possible
+        let follow_file_branch = if self.could_be_deployed(config) {
+            self.read_follow_file(config).await
+        } else {
+            None
+        };
+
+        let follow_branch = match follow_file_branch.as_deref() {
+            Some(branch) => self.deployed_branch_in(config, &[branch]),
+            None => self.deployed_branch(config),
+        };
+
+        if let Some(follow_branch) = follow_branch {
+            tracing::info!(%follow_branch, "Commits were pushed to the followed branch in this event");
+
+            // Only deploy commits that passed the checks the repository requires
+            if !self.wait_for_required_checks(config, follow_branch).await? {
+                return Ok(Deployment::Skipped);
+            }
+
+            // Let GitHub know that a deploy is starting if that is configured
+            let deployment = self.start_github_deployment(config).await;
+
+            // Wait for any conflicting deploys to finish first
+            let template = config.resolve_deploy_key_template(self.get_full_name());
+            let key = deploy_key(
+                template,
+                self.get_full_name(),
+                follow_branch,
+                Some(&self.head_commit.message),
+            );
+            let _guard = config.lock_deploy(&key).await;
+
+            // Pull, build and restart the new changes for the target selected by the commit
+            let started = Instant::now();
+            let result = self
+                .target_repository(config)
+                .deploy(config, follow_branch, Some(&self.head_commit), self.forced)
+                .await;
+
+            self.write_result_file(config, follow_branch, &result, started.elapsed());
+
+            if let Some((client, id)) = deployment {
+                let state = match result {
+                    Ok(Deployment::Complete | Deployment::Partial(_)) => DeploymentState::Success,
+                    Ok(Deployment::Skipped) => DeploymentState::Inactive,
+                    Err(_) => DeploymentState::Failure,
+                };
+
+                self.update_github_deployment(&client, id, state).await;
+            }
+
+            let deployment = result?;
+
+            // The deploy went ahead, so update the Discord channel if there is one
+            if deployment.went_ahead()
+                && config.should_run_stage(self.get_full_name(), Stage::Notify)
+            {
+                self.notify_discord_channel(config, follow_branch, &deployment)
+                    .await;
+            }
+
+            return Ok(deployment);
+        }
+
+        Ok(Deployment::Skipped)
+    }
+
+    /// Wraps the [`handle_inner`] method by propagating errors correctly.
+    pub async fn handle(&self, config: &Arc<Config>) -> Outcome {
+        match self.handle_inner(config).await {
+            Ok(deployment) => deployment_outcome(self.get_full_name(), deployment),
+            Err(e) => {
+                let branch = self.refname.strip_prefix("refs/heads/");
+                self.repository.handle_failure(config, branch, &e).await;
+
+                Outcome::Failed(Failure {
+                    repository: self.get_full_name().to_owned(),
+                    stage: failed_stage(&e),
+                    error: e.to_string(),
+                })
+            }
+        }
+    }
+
+    /// Retrieves the full name of the repository this webhook relates to.
+    pub fn get_full_name(&self) -> &str {
+        &self.repository.full_name
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Ping {
+    hook: Hook,
+    repository: Repository,
+}
+
+impl Ping {
+    pub fn get_full_name(&self) -> &str {
+        &self.repository.full_name
+    }
+
+    pub async fn handle(&self, _config: &Arc<Config>) -> Outcome {
+        let body = format!(
+            "Setup tracking of `{}` at url: {}",
+            self.repository.full_name, self.hook.config.url
+        );
+
+        Outcome::Handled(Some(body))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RepositoryDispatch {
+    action: String,
+    client_payload: Option<serde_json::Value>,
+    repository: Repository,
+}
+
+impl RepositoryDispatch {
+    /// Handles the webhook message for repository dispatch messages.
+    ///
+    /// Checks whether the dispatched action is configured for the repository and, if so, deploys
+    /// the branch it maps to in the same way as a push to the followed branch would. Returns
+    /// how the repository was deployed.
+    async fn handle_inner(&self, config: &Arc<Config>) -> Result<Deployment> {
+        let branch = match config.resolve_dispatch_branch(self.get_full_name(), &self.action) {
+            Some(branch) => branch,
+            None => {
+                tracing::info!(action = %self.action, "Ignoring a dispatch with an unconfigured action");
+                return Ok(Deployment::Skipped);
+            }
+        };
+
+        tracing::info!(action = %self.action, client_payload = ?self.client_payload, %branch, "Deploying in response to a repository dispatch");
+
+        // Wait for any conflicting deploys to finish first
+        let template = config.resolve_deploy_key_template(self.get_full_name());
+        let key = deploy_key(template, self.get_full_name(), branch, None);
+        let _guard = config.lock_deploy(&key).await;
+
+        let deployment = self.repository.deploy(config, branch, None, false).await?;
+
+        if !deployment.went_ahead() {
+            return Ok(deployment);
+        }
+
+        let mut message = format!(
+            "Production instance of `{}` has been successfully updated to the head of `{}` by the `{}` dispatch",
+            self.repository.full_name, branch, self.action
+        );
+
+        if let Some(error) = deployment.partial_error() {
+            message.push_str(&partial_suffix(error));
+        }
+
+        if config.should_run_stage(self.get_full_name(), Stage::Notify) {
+            send_discord_message(config, Some(branch), message).await;
+        }
+
+        Ok(deployment)
+    }
+
+    /// Wraps the [`handle_inner`] method by propagating errors correctly.
+    pub async fn handle(&self, config: &Arc<Config>) -> Outcome {
+        match self.handle_inner(config).await {
+            Ok(deployment) => deployment_outcome(self.get_full_name(), deployment),
+            Err(e) => {
+                let branch = config.resolve_dispatch_branch(self.get_full_name(), &self.action);
+                self.repository.handle_failure(config, branch, &e).await;
+
+                Outcome::Failed(Failure {
+                    repository: self.get_full_name().to_owned(),
+                    stage: failed_stage(&e),
+                    error: e.to_string(),
+                })
+            }
+        }
+    }
+
+    /// Retrieves the full name of the repository this webhook relates to.
+    pub fn get_full_name(&self) -> &str {
+        &self.repository.full_name
+    }
+}
+
+/// A deploy of a branch or commit requested through the API rather than by a webhook.
+#[derive(Clone, Debug)]
+pub struct ManualDeploy {
+    repository: Repository,
+    reference: String,
+    forced: bool,
+}
+
+impl ManualDeploy {
+    /// Creates a deploy of a reference of a configured repository, which is reset to the
+    /// reference if `forced`.
+    pub fn new(full_name: &str, reference: String, forced: bool) -> Self {
+        Self {
+            repository: Repository::from_full_name(full_name),
+            reference,
+            forced,
+        }
+    }
+
+    /// Deploys the reference, cloning the repository first if needed.
+    pub async fn handle(&self, config: &Arc<Config>) -> Outcome {
+        let result = self
+            .repository
+            .deploy_manually(config, &self.reference, self.forced)
+            .await;
+
+        match result {
+            Ok(deployment) => deployment_outcome(self.get_full_name(), deployment),
+            Err(e) => Outcome::Failed(Failure {
+                repository: self.get_full_name().to_owned(),
+                stage: failed_stage(&e),
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    /// Retrieves the full name of the repository being deployed.
+    pub fn get_full_name(&self) -> &str {
+        &self.repository.full_name
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Repository {
+    full_name: String,
+    ssh_url: Option<String>,
+    clone_url: Option<String>,
+    html_url: Option<String>,
+}
+
+impl Repository {
+    /// Creates a repository hosted on GitHub from its full name, such as when reconciling every
+    /// configured repository without a webhook.
+    pub fn from_full_name(full_name: &str) -> Self {
+        Self {
+            full_name: full_name.to_owned(),
+            ssh_url: Some(format!("git@github.com:{}.git", full_name)),
+            clone_url: Some(format!("https://github.com/{}.git", full_name)),
+            html_url: Some(format!("https://github.com/{}", full_name)),
+        }
+    }
+
+    /// Clones the repository into `repo_root` if it has not been cloned yet.
+    pub async fn ensure_cloned(&self, config: &Arc<Config>) -> Result<()> {
+        let path = config.resolve_repository_path(&self.full_name);
+
+        if path.exists() {
+            return Ok(());
+        }
+
+        let url = self
+            .ssh_url
+            .as_deref()
+            .or(self.clone_url.as_deref())
+            .with_context(|| format!("No URL to clone {} from", self.full_name))?;
+
+        let _permit = config.acquire_fetch_permit().await;
+        let _host_permit = self.acquire_host_permit(config).await;
+
+        let config = Arc::clone(config);
+        let depth = config.resolve_clone_depth(&self.full_name);
+        let url = url.to_owned();
+
+        run_blocking(move || {
+            let key = config.default.ssh_private_key.as_deref();
+            let passphrase = config.ssh_private_key_passphrase();
+            let proxy_url = config.default.proxy_url.as_deref();
+
+            git::clone(&url, &path, depth, key, passphrase, proxy_url)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Deploys a branch or commit of the repository on request rather than in response to a push,
+    /// cloning it first if needed.
+    ///
+    /// The checkout is reset to the reference if `forced`, so branches other than the followed one
+    /// can be deployed. Failures are reported to the Discord channel as they would be for a push,
+    /// while deploys that left out some binaries are reported without running `on_failure`.
+    pub async fn deploy_manually(
+        &self,
+        config: &Arc<Config>,
+        reference: &str,
+        forced: bool,
+    ) -> Result<Deployment> {
+        let template = config.resolve_deploy_key_template(&self.full_name);
+        let key = deploy_key(template, &self.full_name, reference, None);
+        let _guard = config.lock_deploy(&key).await;
+
+        tracing::info!(repo = %self.full_name, %reference, "Deploying the repository manually");
+
+        let result = match self.ensure_cloned(config).await {
+            Ok(()) => self.deploy(config, reference, None, forced).await,
+            Err(e) => Err(e),
+        };
+
+        match &result {
+            Ok(deployment) => {
+                if let Some(error) = deployment.partial_error() {
+                    let message = partial_message(&self.full_name, &error.to_string());
+                    send_discord_message(config, Some(reference), message).await;
+                }
+            }
+            Err(e) => self.handle_failure(config, Some(reference), e).await,
+        }
+
+        result
+    }
+
+    /// Creates a copy of the repository that is configured and stored under a different name.
+    fn with_full_name(&self, full_name: &str) -> Self {
+        Self {
+            full_name: full_name.to_owned(),
+            ..self.clone()
+        }
+    }
+
+    /// Triggers a `git pull` for the repository associated with the webhook.
+    ///
+    /// This will open the repository, which is assumed to be at `repo_root` and fetch the contents
+    /// of the given branch (which is usually the followed branch of the repository). It
+    /// will then merge the contents of the fetch, or reset to them if the branch was force-pushed
+    /// and can no longer be merged. Only `max_concurrent_fetches` repositories will be fetched at
+    /// once, and only `max_connections_per_host` from the same host.
+    ///
+    /// The pull carries on if the deploy is cancelled, so it is tracked by `blocking` until it has
+    /// finished with the checkout.
+    async fn trigger_pull(
+        &self,
+        config: &Arc<Config>,
+        branch: &str,
+        forced: bool,
+        blocking: &InFlight,
+    ) -> Result<()> {
+        let _permit = config.acquire_fetch_permit().await;
+        let _host_permit = self.acquire_host_permit(config).await;
+
+        let repository = self.clone();
+        let config = Arc::clone(config);
+        let branch = branch.to_owned();
+        let guard = blocking.enter();
+
+        run_blocking(move || {
+            let _guard = guard;
+            repository.pull(&config, &branch, forced)
+        })
+        .await
+    }
+
+    /// Fetches and applies the changes to the given branch, blocking until they have been.
+    fn pull(&self, config: &Config, branch: &str, forced: bool) -> Result<()> {
+        let path = config.resolve_repository_path(&self.full_name);
+        let repo = git2::Repository::open(&path)?;
+
+        tracing::info!(?path, %branch, "Fetching changes for the project");
+
+        let urls: Vec<_> = [&self.ssh_url, &self.clone_url]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+
+        let mut remote = git::find_matching_remote(&repo, &urls)?;
+
+        let key = config.default.ssh_private_key.as_deref();
+        let passphrase = config.ssh_private_key_passphrase();
+        let proxy_url = config.default.proxy_url.as_deref();
+
+        let depth = config.resolve_clone_depth(&self.full_name);
+        let fetch_commit = git::fetch(
+            &repo,
+            &[branch],
+            &mut remote,
+            depth,
+            key,
+            passphrase,
+            proxy_url,
+        )?;
+
+        if git::is_commit_id(branch) {
+            tracing::info!(?path, commit = %branch, "Checking out a specific commit");
+            git::checkout_detached(&repo, &fetch_commit)?;
+        } else if forced {
+            tracing::info!(?path, %branch, "Resetting to the remote after a force-push");
+            git::reset_hard(&repo, branch, &fetch_commit)?;
+        } else if config.resolve_strategy(&self.full_name) == Strategy::Reset {
+            tracing::info!(?path, %branch, "Resetting to the remote");
+            git::reset_hard(&repo, branch, &fetch_commit)?;
+        } else {
+            git::merge(&repo, branch, &fetch_commit)?;
+        }
+
+        if config.should_clean_build(&self.full_name) {
+            tracing::info!(?path, "Cleaning the repository before building");
+            git::clean(&repo)?;
+        }
+
+        Ok(())
+    }
+
+    /// Waits until the repository can be fetched from its host, if `max_connections_per_host` is
+    /// set.
+    async fn acquire_host_permit(&self, config: &Config) -> Option<OwnedSemaphorePermit> {
+        let host = [&self.ssh_url, &self.clone_url]
+            .into_iter()
+            .flatten()
+            .find_map(|url| git::host(url))?;
+
+        config.acquire_host_permit(&host).await
+    }
+
+    /// Reads a file from the default branch of the remote, after fetching it.
+    ///
+    /// Its contents are trimmed, returning nothing if the file does not exist.
+    async fn read_remote_file(&self, config: &Arc<Config>, file: &Path) -> Result<Option<String>> {
+        let _permit = config.acquire_fetch_permit().await;
+        let _host_permit = self.acquire_host_permit(config).await;
+
+        let repository = self.clone();
+        let config = Arc::clone(config);
+        let file = file.to_owned();
+
+        run_blocking(move || repository.read_file_from_remote(&config, &file)).await
+    }
+
+    /// Reads a file from the default branch of the remote, blocking until it has.
+    ///
+    /// The default branch is only fetched if it has moved to a commit that has not been fetched
+    /// before, as otherwise the file can be read from the existing copy of the commit.
+    fn read_file_from_remote(&self, config: &Config, file: &Path) -> Result<Option<String>> {
+        let path = config.resolve_repository_path(&self.full_name);
+        let repo = git2::Repository::open(&path)?;
+
+        let urls: Vec<_> = [&self.ssh_url, &self.clone_url]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+
+        let mut remote = git::find_matching_remote(&repo, &urls)?;
+
+        let key = config.default.ssh_private_key.as_deref();
+        let passphrase = config.ssh_private_key_passphrase();
+        let proxy_url = config.default.proxy_url.as_deref();
+
+        let head = git::remote_head(&mut remote, key, passphrase, proxy_url)?;
+
+        let commit = match repo.find_commit(head) {
+            Ok(commit) => commit.id(),
+            Err(_) => {
+                let depth = config.resolve_clone_depth(&self.full_name);
+                git::fetch(
+                    &repo,
+                    &["HEAD"],
+                    &mut remote,
+                    depth,
+                    key,
+                    passphrase,
+                    proxy_url,
+                )?
+                .id()
+            }
+        };
+
+        let content = git::read_file(&repo, commit, file)?;
+
+        Ok(content.map(|content| content.trim().to_owned()))
+    }
+
+    /// Checks whether the repository contains the file it requires to be deployed, if any.
+    fn has_required_file(&self, config: &Arc<Config>) -> bool {
+        let file = match config.resolve_required_file(&self.full_name) {
+            Some(file) => file,
+            None => return true,
+        };
+
+        let path = config.resolve_repository_path(&self.full_name).join(file);
+
+        if !path.is_file() {
+            tracing::info!(repo = %self.full_name, ?path, "Skipping the deploy as the required file is missing");
+            return false;
+        }
+
+        true
+    }
+
+    /// Runs any commands specified in the config that should run before pulling.
+    ///
+    /// Commands will be run in the repository directory before any git operations, such as to stop
+    /// a service that holds locks on files in the repository.
+    async fn run_pre_pull_commands(
+        &self,
+        config: &Arc<Config>,
+        context: &CommandContext<'_>,
+    ) -> Result<()> {
+        if let Some(commands) = config.resolve_pre_pull_commands(&self.full_name) {
+            let repo_path = config.resolve_repository_path(&self.full_name);
+            commands.execute(config, context, &repo_path).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs any precommands specified in the config.
+    ///
+    /// Commands will be run in the `code_root` directory and will simply be executed by the shell.
+    async fn run_precommands(
+        &self,
+        config: &Arc<Config>,
+        context: &CommandContext<'_>,
+    ) -> Result<()> {
+        if let Some(commands) = config.resolve_precommands(&self.full_name) {
+            let repo_path = config.resolve_repository_path(&self.full_name);
+            commands.execute(config, context, &repo_path).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Triggers the recompilation of a repository associated with the webhook.
+    ///
+    /// This should be run after pulling the new changes to update the repository. After being
+    /// rebuilt, it can be restarted in `supervisor` and the new changes will go live. Any
+    /// `build_commands` are run in place of `cargo`.
+    async fn trigger_build(
+        &self,
+        config: &Arc<Config>,
+        context: &CommandContext<'_>,
+        failures: &mut BinaryFailures,
+    ) -> Result<()> {
+        let code_root = config.resolve_code_root(&self.full_name);
+        let binaries = config.resolve_binaries(&self.full_name);
+
+        let path = &config
+            .resolve_repository_path(&self.full_name)
+            .join(&code_root);
+
+        if let Some(min_free_disk_mb) = config.default.min_free_disk_mb {
+            let available_mb = fs2::available_space(path)? / (1024 * 1024);
+
+            if available_mb < min_free_disk_mb {
+                bail!(
+                    "Insufficient disk space to build: {}MB available, {}MB required",
+                    available_mb,
+                    min_free_disk_mb
+                );
+            }
+        }
+
+        if let Some(commands) = config.resolve_build_commands(&self.full_name) {
+            tracing::info!(?path, "Building with the configured commands");
+            return commands.execute(config, context, path).await;
+        }
+
+        tracing::info!(?path, "Rebuilding binaries");
+
+        for binary in &binaries {
+            tracing::info!(%binary, "Building a specific binary");
+
+            if let Err(e) = self.build_binary(config, path, binary).await {
+                failures.record(config, &self.full_name, Stage::Build, binary, e)?;
+            }
+        }
+
+        // There is nothing left to deploy if every binary failed
+        if !binaries.is_empty() && failures.len() == binaries.len() {
+            return Err(failures.take_error());
+        }
+
+        Ok(())
+    }
+
+    /// Builds a single binary of the repository with `cargo`, checking it was replaced if required
+    /// by the success criteria.
+    async fn build_binary(&self, config: &Arc<Config>, path: &Path, binary: &str) -> Result<()> {
+        let mut command = config.create_command(&self.full_name, &config.default.cargo_path);
+        command.arg("build");
+
+        match config.resolve_cargo_profile(&self.full_name) {
+            Some(profile) => command.args(["--profile", profile]),
+            None => command.arg("--release"),
+        };
+
+        let features = config.resolve_cargo_features(&self.full_name);
+
+        if !features.is_empty() {
+            command.args(["--features", &features.join(",")]);
+        }
+
+        command.args(["--bin", binary]).current_dir(path);
+
+        if config.is_cargo_offline(&self.full_name) {
+            command.env("CARGO_NET_OFFLINE", "true");
+        }
+
+        if let Some(cargo_home) = config.resolve_cargo_home(&self.full_name) {
+            command.env("CARGO_HOME", cargo_home);
+        }
+
+        let started = SystemTime::now();
+
+        if !config.run_command(&self.full_name, &mut command).await? {
+            bail!("Failed to build binary: {}", binary);
+        }
+
+        // Check the build replaced the binary that will be restarted
+        if let Some(criteria) = config.resolve_success_criteria(&self.full_name) {
+            let binary_path = config.resolve_binary_path(&self.full_name, binary);
+            criteria.check_binary(&binary_path, started)?;
+        }
+
+        Ok(())
+    }
+
+    /// Triggers a process restart by `supervisor`.
+    ///
+    /// Restarts the process within `supervisor`, allowing a new version to supersede the existing
+    /// version. Binaries that failed to build are left running their existing version.
+    async fn trigger_restart(
+        &self,
+        config: &Arc<Config>,
+        failures: &mut BinaryFailures,
+    ) -> Result<()> {
+        if config.is_self_repository(&self.full_name) {
+            tracing::info!(
+                repo = %self.full_name,
+                "Not restarting `fisherman` in `supervisor`, it will replace itself instead"
+            );
+
+            return Ok(());
+        }
+
+        let programs: Vec<_> = config
+            .resolve_binaries(&self.full_name)
+            .iter()
+            .filter(|binary| !failures.contains(binary))
+            .flat_map(|binary| config.resolve_supervisor_programs(&self.full_name, binary))
+            .collect();
+
+        let mut restarted = Vec::with_capacity(programs.len());
+
+        for program in programs {
+            match self.restart_program(config, &program).await {
+                Ok(()) => restarted.push(program),
+                Err(e) => failures.record(config, &self.full_name, Stage::Restart, &program, e)?,
+            }
+        }
+
+        let mut programs = restarted;
+
+        if let Some(verify) = config.resolve_verify_restart(&self.full_name) {
+            programs = self
+                .verify_restart(config, verify, programs, failures)
+                .await?;
+        }
+
+        if let Some(criteria) = config.resolve_success_criteria(&self.full_name) {
+            self.check_supervisor_status(config, criteria, programs, failures)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Restarts a single program with the configured `restart_command`.
+    async fn restart_program(&self, config: &Arc<Config>, program: &str) -> Result<()> {
+        let (executable, args) = config.resolve_restart_command(&self.full_name, program);
+
+        tracing::info!(%program, %executable, "Restarting the program");
+
+        let mut command = Command::new(&executable);
+        command.args(&args);
+
+        let output = process::output(&mut command, config.command_timeout()).await?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        let supervised = config.has_supervisor_restarts(&self.full_name);
+
+        if supervised && (is_unknown_program(&stdout) || is_unknown_program(&stderr)) {
+            bail!(
+                "`supervisor` has no program named `{}`, add it to the `supervisor` config or set `supervisor_name_template`",
+                program
+            );
+        }
+
+        if !output.status.success() {
+            bail!("Failed to restart program: {}", program);
+        }
+
+        Ok(())
+    }
+
+    /// Polls the status of restarted programs until each has stayed `RUNNING` for the settle
+    /// period, failing if any of them take longer than the timeout, such as when crash-looping.
+    ///
+    /// Returns the programs that are stable, as those that are not are recorded as failures when
+    /// the repository continues on binary failures.
+    async fn verify_restart(
+        &self,
+        config: &Arc<Config>,
+        verify: &VerifyRestart,
+        programs: Vec<String>,
+        failures: &mut BinaryFailures,
+    ) -> Result<Vec<String>> {
+        let mut stable = Vec::with_capacity(programs.len());
+
+        for program in programs {
+            match self.verify_program(config, verify, &program).await {
+                Ok(()) => stable.push(program),
+                Err(e) => failures.record(config, &self.full_name, Stage::Restart, &program, e)?,
+            }
+        }
+
+        Ok(stable)
+    }
+
+    /// Polls the status of a single restarted program until it has stayed `RUNNING` for the
+    /// settle period, failing if it takes longer than the timeout.
+    async fn verify_program(
+        &self,
+        config: &Arc<Config>,
+        verify: &VerifyRestart,
+        program: &str,
+    ) -> Result<()> {
+        let deadline = Instant::now() + verify.timeout();
+        let mut running_since = None;
+
+        loop {
+            let (succeeded, status) = self.program_status(config, program).await?;
+            let now = Instant::now();
+
+            // Only `supervisorctl` needs its output checked, as it succeeds for stopped programs
+            let running = succeeded
+                && (!config.has_supervisor_status(&self.full_name)
+                    || VerifyRestart::is_running(&status));
+
+            if running {
+                let since = *running_since.get_or_insert(now);
+
+                if now.duration_since(since) >= verify.settle() {
+                    tracing::info!(%program, "Restarted program is stable");
+                    return Ok(());
+                }
+            } else {
+                running_since = None;
+            }
+
+            if now >= deadline {
+                bail!(
+                    "`{}` did not stay running for {:?} within {:?} of restarting: {}",
+                    program,
+                    verify.settle(),
+                    verify.timeout(),
+                    status.trim()
+                );
+            }
+
+            tokio::time::sleep(verify.interval()).await;
+        }
+    }
+
+    /// Runs the `status_command` for a program, returning whether it succeeded and its output.
+    async fn program_status(&self, config: &Arc<Config>, program: &str) -> Result<(bool, String)> {
+        let (executable, args) = config.resolve_status_command(&self.full_name, program);
+
+        let mut command = Command::new(&executable);
+        command.args(&args);
+
+        let output = process::output(&mut command, config.command_timeout()).await?;
+        let status = String::from_utf8_lossy(&output.stdout).into_owned();
+
+        Ok((output.status.success(), status))
+    }
+
+    /// Checks that restarted programs reach the status required by the success criteria.
+    ///
+    /// Waits for the configured delay first, as `supervisor` will report a process as running
+    /// immediately after starting it, even if it exits shortly afterwards.
+    async fn check_supervisor_status(
+        &self,
+        config: &Arc<Config>,
+        criteria: &SuccessCriteria,
+        programs: Vec<String>,
+        failures: &mut BinaryFailures,
+    ) -> Result<()> {
+        if criteria.supervisor_status.is_none() {
+            return Ok(());
+        }
+
+        let delay = Duration::from_secs(criteria.supervisor_delay_secs.unwrap_or(5));
+        tokio::time::sleep(delay).await;
+
+        for program in programs {
+            let (_, output) = self.program_status(config, &program).await?;
+
+            let status = criteria
+                .check_supervisor_status(&output)
+                .with_context(|| format!("Failed to restart program: {}", program));
+
+            if let Err(e) = status {
+                failures.record(config, &self.full_name, Stage::Restart, &program, e)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs any additional commands specified in the config.
+    ///
+    /// Commands will be run in the `code_root` directory and will simply be executed by the shell.
+    async fn run_additional_commands(
+        &self,
+        config: &Arc<Config>,
+        context: &CommandContext<'_>,
+    ) -> Result<()> {
+        if let Some(commands) = config.resolve_commands(&self.full_name) {
+            let repo_path = config.resolve_repository_path(&self.full_name);
+            commands.execute(config, context, &repo_path).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs any commands specified in the config that should run for every push.
+    ///
+    /// Commands will be run in the repository directory, even if the push is not to a followed
+    /// branch.
+    async fn run_always_commands(
+        &self,
+        config: &Arc<Config>,
+        context: &CommandContext<'_>,
+    ) -> Result<()> {
+        if let Some(commands) = config.resolve_always_commands(&self.full_name) {
+            let repo_path = config.resolve_repository_path(&self.full_name);
+            commands.execute(config, context, &repo_path).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Pulls the given branch before rebuilding all binaries, restarting them and running any
+    /// additional commands provided in the configuration.
+    ///
+    /// If the repository has a deploy timeout and it elapses, the deploy is cancelled (killing any
+    /// commands it was running) and fails. Returns how the deploy finished, as it is skipped if the
+    /// repository is missing its required file after pulling, and partial if it continued without
+    /// some binaries that failed. Force-pushed branches replace
+    /// the local checkout rather than being merged into it. The `commit` that was pushed, if there
+    /// was one, is described to any commands that are run.
+    pub async fn deploy(
+        &self,
+        config: &Arc<Config>,
+        branch: &str,
+        commit: Option<&Commit>,
+        forced: bool,
+    ) -> Result<Deployment> {
+        let span = tracing::info_span!("deploy", repo = %self.full_name, %branch, %forced);
+        let blocking = InFlight::default();
+
+        let deploy = self
+            .deploy_inner(config, branch, commit, forced, &blocking)
+            .instrument(span);
+
+        match config.resolve_deploy_timeout(&self.full_name) {
+            Some(timeout) => run_with_timeout(&self.full_name, timeout, &blocking, deploy).await,
+            None => deploy.await,
+        }
+    }
+
+    /// Performs each stage of a deploy in order.
+    ///
+    /// Stages that are disabled for the repository are skipped.
+    async fn deploy_inner(
+        &self,
+        config: &Arc<Config>,
+        branch: &str,
+        commit: Option<&Commit>,
+        forced: bool,
+        blocking: &InFlight,
+    ) -> Result<Deployment> {
+        // Manual deploys of a commit know what is being deployed without a push
+        let context = CommandContext {
+            branch: Some(branch),
+            commit: commit
+                .map(|commit| commit.id.as_str())
+                .or_else(|| git::is_commit_id(branch).then_some(branch)),
+            author: commit.map(|commit| commit.author.name.as_str()),
+            ..CommandContext::new(&self.full_name)
+        };
+
+        let stages = [
+            Stage::PrePull,
+            Stage::Pull,
+            Stage::Precommands,
+            Stage::Build,
+            Stage::Restart,
+            Stage::Commands,
+        ];
+
+        let mut failures = BinaryFailures::default();
+
+        for stage in stages {
+            if !config.should_run_stage(&self.full_name, stage) {
+                tracing::info!(repo = %self.full_name, ?stage, "Skipping a disabled stage of the deploy");
+                continue;
+            }
+
+            let span = tracing::info_span!("stage", repo = %self.full_name, ?stage);
+
+            let proceed = self
+                .run_stage(config, &context, forced, stage, &mut failures, blocking)
+                .instrument(span)
+                .await
+                .map_err(|source| DeployError { stage, source })?;
+
+            self.record_stage(config, stage, &failures);
+
+            if !proceed {
+                return Ok(Deployment::Skipped);
+            }
+        }
+
+        // Report the binaries that were skipped once the rest have been deployed
+        if let Some(stage) = failures.stage() {
+            let error = DeployError {
+                stage,
+                source: failures.take_error(),
+            };
+
+            return Ok(Deployment::Partial(error.into()));
+        }
+
+        Ok(Deployment::Complete)
+    }
+
+    /// Records a finished stage of a deploy in the stage log, with an entry for each binary that was
+    /// built or restarted.
+    fn record_stage(&self, config: &Config, stage: Stage, failures: &BinaryFailures) {
+        let binaries = config.resolve_binaries(&self.full_name);
+
+        // A binary only counts as deployed if it and all of its programs succeeded
+        let deployed = binaries.iter().filter(|binary| {
+            !failures.contains(binary)
+                && config
+                    .resolve_supervisor_programs(&self.full_name, binary)
+                    .iter()
+                    .all(|program| !failures.contains(program))
+        });
+
+        let activities: Vec<_> = match stage {
+            Stage::Pull => vec![Activity::Pull],
+            Stage::Build => deployed.cloned().map(Activity::Build).collect(),
+            Stage::Restart if !config.is_self_repository(&self.full_name) => {
+                deployed.cloned().map(Activity::Restart).collect()
+            }
+            _ => Vec::new(),
+        };
+
+        for activity in activities {
+            config.stages.record(&self.full_name, activity);
+        }
+    }
+
+    /// Performs a single stage of a deploy, returning whether the deploy should continue.
+    async fn run_stage(
+        &self,
+        config: &Arc<Config>,
+        context: &CommandContext<'_>,
+        forced: bool,
+        stage: Stage,
+        failures: &mut BinaryFailures,
+        blocking: &InFlight,
+    ) -> Result<bool> {
+        let branch = context.branch.unwrap_or_default();
+
+        match stage {
+            // Run any commands that must happen before touching the repository
+            Stage::PrePull => self.run_pre_pull_commands(config, context).await?,
+            // Pull the new changes, checking they should still be deployed
+            Stage::Pull => {
+                self.trigger_pull(config, branch, forced, blocking).await?;
+                return Ok(self.has_required_file(config));
+            }
+            // Run any precommands that have been setup
+            Stage::Precommands => self.run_precommands(config, context).await?,
+            // Build the updated binary
+            Stage::Build => self.trigger_build(config, context, failures).await?,
+            // Restart in `supervisor`
+            Stage::Restart => self.trigger_restart(config, failures).await?,
+            // Run any additional commands
+            Stage::Commands => self.run_additional_commands(config, context).await?,
+            Stage::Notify => {}
+        }
+
+        Ok(true)
+    }
+
+    /// Responds to a failure in the handling of a webhook by notifying the Discord channel and
+    /// running any `on_failure` commands.
+    async fn handle_failure(
+        &self,
+        config: &Arc<Config>,
+        branch: Option<&str>,
+        error: &anyhow::Error,
+    ) {
+        let message = error.to_string();
+
+        self.notify_of_failure(config, branch, &message).await;
+
+        if let Err(e) = self.run_failure_commands(config, branch, error).await {
+            tracing::error!(repo = %self.full_name, error = ?e, "Failed to run the `on_failure` commands");
+        }
+    }
+
+    /// Runs any commands specified in the config that should run when handling a webhook fails.
+    ///
+    /// Along with the usual variables describing the deploy, the failed stage and error are passed
+    /// to the commands in the `FISHERMAN_STAGE` and `FISHERMAN_ERROR` environment variables,
+    /// leaving out any that are unknown. The repository is also kept in `FISHERMAN_REPOSITORY`.
+    async fn run_failure_commands(
+        &self,
+        config: &Arc<Config>,
+        branch: Option<&str>,
+        error: &anyhow::Error,
+    ) -> Result<()> {
+        let commands = match config.resolve_on_failure_commands(&self.full_name) {
+            Some(commands) => commands,
+            None => return Ok(()),
+        };
+
+        // Commands still run if the repository failed to clone, just from the root instead
+        let repo_path = config.resolve_repository_path(&self.full_name);
+        let working_dir = if repo_path.is_dir() {
+            repo_path
+        } else {
+            config.default.repo_root.clone()
+        };
+
+        let message = error.to_string();
+        let stage = failed_stage(error).map(|stage| stage.to_string());
+
+        let context = CommandContext {
+            branch,
+            ..CommandContext::new(&self.full_name)
+        };
+
+        let mut env = vec![
+            ("FISHERMAN_REPOSITORY", self.full_name.as_str()),
+            ("FISHERMAN_ERROR", message.as_str()),
+        ];
+
+        env.extend(stage.as_deref().map(|stage| ("FISHERMAN_STAGE", stage)));
+
+        commands
+            .execute_with_env(config, &context, &working_dir, &env)
+            .await
+    }
+
+    /// Notifies a Discord channel of a failure in the handling of a webhook.
+    async fn notify_of_failure(&self, config: &Arc<Config>, branch: Option<&str>, error: &str) {
+        send_discord_message(config, branch, failure_message(&self.full_name, error)).await;
+    }
+}
+
+/// Parses the value of a trailer such as `Deploy-To: staging` in a commit message, if there is one.
+///
+/// Trailer names are matched case-insensitively, and later trailers take precedence.
+fn commit_trailer<'a>(message: &'a str, name: &str) -> Option<&'a str> {
+    message.lines().rev().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim())
+            .filter(|value| !value.is_empty())
+    })
+}
+
+/// Parses the deploy target from a `Deploy-To` trailer in a commit message, if there is one.
+fn deploy_target(message: &str) -> Option<&str> {
+    commit_trailer(message, "Deploy-To")
+}
+
+/// Renders the key identifying deploys that must not run at the same time from a template.
+///
+/// `{repo}` and `{branch}` are replaced with the repository and branch being deployed, while
+/// `{trailer:<name>}` is replaced with the value of that trailer in the commit message, or nothing
+/// if it is missing.
+fn deploy_key(template: &str, repository: &str, branch: &str, message: Option<&str>) -> String {
+    let trailers = Regex::new(r"\{trailer:([^}]+)\}").expect("Trailer pattern was invalid");
+
+    let key = template
+        .replace("{repo}", repository)
+        .replace("{branch}", branch);
+
+    trailers
+        .replace_all(&key, |captures: &regex::Captures| {
+            message
+                .and_then(|message| commit_trailer(message, &captures[1]))
+                .unwrap_or_default()
+                .to_owned()
+        })
+        .into_owned()
+}
+
+/// The binaries that failed to build or restart during a deploy that continued without them.
+#[derive(Debug, Default)]
+struct BinaryFailures(Vec<(Stage, String, anyhow::Error)>);
+
+impl BinaryFailures {
+    /// Records a binary or program that failed, unless the repository should fail the deploy
+    /// straight away, in which case the error is returned instead.
+    fn record(
+        &mut self,
+        config: &Config,
+        repository: &str,
+        stage: Stage,
+        name: &str,
+        error: anyhow::Error,
+    ) -> Result<()> {
+        if !config.should_continue_on_binary_failure(repository) {
+            return Err(error);
+        }
+
+        tracing::warn!(%repository, ?stage, %name, ?error, "Continuing the deploy without a failed binary");
+        self.0.push((stage, name.to_owned(), error));
+
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.0.iter().any(|(_, failed, _)| failed == name)
+    }
+
+    /// Gets the stage of the first failure, if there were any.
+    fn stage(&self) -> Option<Stage> {
+        self.0.first().map(|(stage, _, _)| *stage)
+    }
+
+    /// Combines the failures into a single error, leaving none behind.
+    fn take_error(&mut self) -> anyhow::Error {
+        let failures: Vec<_> = self
+            .0
+            .drain(..)
+            .map(|(_, name, error)| format!("{name} ({error})"))
+            .collect();
+
+        anyhow!("Failed to deploy some binaries: {}", failures.join(", "))
+    }
+}
+
+/// Checks whether the output of `supervisorctl` says the program it was given does not exist.
+fn is_unknown_program(output: &str) -> bool {
+    output.contains("ERROR (no such process)")
+}
+
+/// Parses the pull request number from the end of a commit title such as `Add a feature (#123)`.
+fn pull_request_number(title: &str) -> Option<u64> {
+    title
+        .trim_end()
+        .strip_suffix(')')?
+        .rsplit_once("(#")?
+        .1
+        .parse()
+        .ok()
+}
+
+/// Describes a deploy that left out some binaries for a notification.
+pub(crate) fn partial_message(repository: &str, error: &str) -> String {
+    format!(
+        "Production instance of `{}` was only partially updated, error: {}",
+        repository, error
+    )
+}
+
+/// Describes the binaries left out of a deploy at the end of a notification of its success.
+fn partial_suffix(error: &anyhow::Error) -> String {
+    format!(", but not every binary was deployed, error: {}", error)
+}
+
+/// Gets the outcome of handling a webhook from how it deployed the repository.
+fn deployment_outcome(repository: &str, deployment: Deployment) -> Outcome {
+    match deployment {
+        Deployment::Skipped => Outcome::Handled(None),
+        Deployment::Complete => Outcome::Deployed,
+        Deployment::Partial(e) => Outcome::PartiallyDeployed(Failure {
+            repository: repository.to_owned(),
+            stage: failed_stage(&e),
+            error: e.to_string(),
+        }),
+    }
+}
+
+/// Describes a failure to deploy a repository for a notification.
+pub(crate) fn failure_message(repository: &str, error: &str) -> String {
+    format!(
+        "Production instance of `{}` failed to be updated, error: {}",
+        repository, error
+    )
+}
+
+/// Sends a message to the configured Discord channel straight away, without batching it with
+/// others, such as when the process is about to exit.
+pub(crate) async fn send_discord_message_now(
+    config: &Config,
+    branch: Option<&str>,
+    message: String,
+) {
+    if let Some((client, channel_id)) = config.get_client_and_channel_id(branch) {
+        send_to_channel(config, &client, channel_id, message).await;
+    }
+}
+
+/// Sends a message about a deploy of a branch to the configured Discord channel, if there is one.
+///
+/// If messages are batched, the message will instead be sent along with any others to the same
+/// channel once the batching window has passed.
+async fn send_discord_message(config: &Arc<Config>, branch: Option<&str>, message: String) {
+    let (client, channel_id) = match config.get_client_and_channel_id(branch) {
+        Some((client, channel_id)) => (client, channel_id),
+        None => return,
+    };
+
+    let delay = match config
+        .default
+        .discord
+        .as_ref()
+        .and_then(|d| d.batch_delay())
+    {
+        Some(delay) => delay,
+        None => return send_to_channel(config, &client, channel_id, message).await,
+    };
+
+    // Another message already started the batch, so it will send this one too
+    if !config.notifications.push(channel_id.0, message) {
+        return;
+    }
+
+    let config = Arc::clone(config);
+
+    tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+
+        let messages = config.notifications.take(channel_id.0);
+
+        for summary in batch::summarize(messages) {
+            send_to_channel(&config, &client, channel_id, summary).await;
+        }
+    });
+}
+
+/// Sends a message to a Discord channel, retrying transient failures.
+async fn send_to_channel(config: &Config, client: &Http, channel_id: ChannelId, message: String) {
+    let retries = config
+        .default
+        .discord
+        .as_ref()
+        .and_then(|d| d.retries)
+        .unwrap_or(3);
+
+    let result = retry::with_backoff(
+        retries,
+        Duration::from_millis(500),
+        || channel_id.send_message(client, |m| m.content(&message)),
+        is_transient_discord_error,
+    )
+    .await;
+
+    if let Err(e) = result {
+        tracing::error!(%e, %message, "Failed to send the message to the channel");
+    }
+}
+
+/// Runs a deploy of a repository, cancelling it if it takes longer than the timeout.
+///
+/// Blocking work such as `git2` operations cannot be cancelled, so a cancelled deploy still waits
+/// for any it tracked in `blocking` to finish before failing. Whoever holds the deploy lock keeps
+/// it until then, so the next deploy cannot use the checkout while it is still being changed.
+async fn run_with_timeout<T>(
+    repository: &str,
+    timeout: Duration,
+    blocking: &InFlight,
+    deploy: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    match tokio::time::timeout(timeout, deploy).await {
+        Ok(result) => result,
+        Err(_) => {
+            tracing::warn!(repo = %repository, ?timeout, "Deploy timed out, cancelling it");
+            blocking.wait_until_idle().await;
+
+            bail!("Deploy timed out after {:?}", timeout);
+        }
+    }
+}
+
+/// Runs blocking work, such as `git2` operations, on a thread of its own so that it cannot hold up
+/// other tasks, keeping the current span for anything it logs.
+async fn run_blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    let span = tracing::Span::current();
+
+    tokio::task::spawn_blocking(move || span.in_scope(work)).await?
+}
+
+/// Checks whether an error from Discord is likely to succeed if the request is retried.
+fn is_transient_discord_error(error: &serenity::Error) -> bool {
+    match error {
+        serenity::Error::Http(inner) => match inner.as_ref() {
+            HttpError::Request(_) => true,
+            HttpError::UnsuccessfulRequest(response) => {
+                response.status_code.is_server_error()
+                    || response.status_code == StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Hook {
+    config: HookConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
 pub struct HookConfig {
     url: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::config::{Config, MatchMode, Stage};
+    use crate::error::DeployError;
+    use crate::inflight::InFlight;
+    use crate::webhook::{
+        deploy_key, deploy_target, deployment_outcome, failed_stage, is_unknown_program,
+        pull_request_number, run_blocking, run_with_timeout, BinaryFailures, DeployResult,
+        Deployment, Failure, Push, ResponseFormat, Webhook, WebhookVariant,
+    };
+
+    static CONFIG: &str = r#"
+default:
+    ssh_private_key: "/root/.ssh/id_rsa"
+    repo_root: "/root"
+    cargo_path: "/root/.cargo/bin/cargo"
+
+specific:
+    alexander-jackson/fisherman:
+        follow: ["master", "dependabot/cargo/serde"]
+        ignore_branches: ["dependabot/*"]
+        targets:
+            staging: "alexander-jackson/fisherman-staging"
+        ignore_author: "fisherman@example.com"
+"#;
+
+    fn push_to(refname: &str) -> Push {
+        let payload = serde_json::json!({
+            "ref": refname,
+            "repository": {
+                "name": "fisherman",
+                "full_name": "alexander-jackson/fisherman",
+            },
+            "head_commit": {
+                "id": "0123456789abcdef",
+                "message": "Update the README",
+                "author": { "name": "Alexander Jackson" },
+            },
+        });
+
+        serde_json::from_value(payload).unwrap()
+    }
+
+    #[test]
+    fn pushes_to_other_branches_are_not_followed() {
+        let push = push_to("refs/heads/develop");
+
+        assert_eq!(
+            push.changes_follow_branch(&["master"], MatchMode::Exact),
+            None
+        );
+    }
+
+    #[test]
+    fn the_matching_branch_in_a_priority_list_is_followed() {
+        let push = push_to("refs/heads/master");
+
+        assert_eq!(
+            push.changes_follow_branch(&["main", "master"], MatchMode::Exact),
+            Some("master")
+        );
+    }
+
+    #[test]
+    fn followed_branches_are_deployed() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let push = push_to("refs/heads/master");
+
+        assert_eq!(push.deployed_branch(&config), Some("master"));
+    }
+
+    #[test]
+    fn ignored_branches_take_precedence_over_followed_ones() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let push = push_to("refs/heads/dependabot/cargo/serde");
+
+        assert_eq!(push.deployed_branch(&config), None);
+    }
+
+    #[test]
+    fn pushes_to_tags_are_not_deployed() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let push = push_to("refs/tags/master");
+
+        assert_eq!(push.deployed_branch(&config), None);
+    }
+
+    #[test]
+    fn pushes_to_the_same_branch_coalesce() {
+        let earlier = Webhook::Push(push_to("refs/heads/master"));
+
+        assert!(earlier.coalesces_with(&Webhook::Push(push_to("refs/heads/master"))));
+        assert!(!earlier.coalesces_with(&Webhook::Push(push_to("refs/heads/develop"))));
+    }
+
+    #[test]
+    fn coalescing_keeps_earlier_forced_pushes() {
+        let mut forced = push_to("refs/heads/master");
+        forced.forced = true;
+
+        let mut latest = Webhook::Push(push_to("refs/heads/master"));
+        latest.absorb(&Webhook::Push(forced));
+        latest.absorb(&Webhook::Push(push_to("refs/heads/master")));
+
+        assert!(matches!(latest, Webhook::Push(push) if push.forced));
+    }
+
+    #[test]
+    fn pull_request_numbers_are_parsed_from_squash_commits() {
+        assert_eq!(pull_request_number("Add a feature (#123)"), Some(123));
+        assert_eq!(pull_request_number("Fix (#1) and (#2) "), Some(2));
+
+        assert_eq!(pull_request_number("Add a feature"), None);
+        assert_eq!(pull_request_number("Add a feature (#abc)"), None);
+    }
+
+    #[test]
+    fn pull_requests_are_linked_from_the_repository() {
+        let mut push = push_to("refs/heads/master");
+        assert_eq!(push.pull_request_url(), None);
+
+        push.repository.html_url = Some(String::from(
+            "https://github.com/alexander-jackson/fisherman",
+        ));
+        push.head_commit.message = String::from("Update the README (#42)\n\nMore detail");
+
+        assert_eq!(
+            push.pull_request_url().as_deref(),
+            Some("https://github.com/alexander-jackson/fisherman/pull/42")
+        );
+    }
+
+    #[test]
+    fn deploy_targets_are_parsed_from_trailers() {
+        let message = "Update the README\n\nDeploy-To: staging\nSigned-off-by: Someone";
+        assert_eq!(deploy_target(message), Some("staging"));
+
+        assert_eq!(
+            deploy_target("Update the README\n\ndeploy-to:production"),
+            Some("production")
+        );
+        assert_eq!(deploy_target("Update the README"), None);
+        assert_eq!(deploy_target("Update the README\n\nDeploy-To: "), None);
+    }
+
+    #[test]
+    fn deploy_targets_select_other_configurations() {
+        let mut push = push_to("refs/heads/master");
+        push.head_commit.message = String::from("Update the README\n\nDeploy-To: staging");
+
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert_eq!(
+            push.target_repository(&config).full_name,
+            "alexander-jackson/fisherman-staging"
+        );
+
+        push.head_commit.message = String::from("Update the README\n\nDeploy-To: unknown");
+
+        assert_eq!(
+            push.target_repository(&config).full_name,
+            "alexander-jackson/fisherman"
+        );
+    }
+
+    #[test]
+    fn webhooks_are_processed_for_the_repository_they_deploy() {
+        let mut push = push_to("refs/heads/master");
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert_eq!(
+            Webhook::Push(push.clone()).deployed_repository(&config),
+            "alexander-jackson/fisherman"
+        );
+
+        push.head_commit.message = String::from("Update the README\n\nDeploy-To: staging");
+
+        assert_eq!(
+            Webhook::Push(push).deployed_repository(&config),
+            "alexander-jackson/fisherman-staging"
+        );
+    }
+
+    #[test]
+    fn forced_pushes_are_parsed() {
+        assert!(!push_to("refs/heads/master").forced);
+
+        let payload = serde_json::json!({
+            "ref": "refs/heads/master",
+            "forced": true,
+            "repository": {
+                "full_name": "alexander-jackson/fisherman",
+            },
+            "head_commit": {
+                "id": "0123456789abcdef",
+                "message": "Rewrite the history",
+                "author": { "name": "Alexander Jackson" },
+            },
+        });
+
+        let push: Push = serde_json::from_value(payload).unwrap();
+        assert!(push.forced);
+    }
+
+    #[test]
+    fn failures_include_the_stage_that_failed() {
+        let error = anyhow::Error::from(DeployError {
+            stage: Stage::Build,
+            source: anyhow::anyhow!("Failed to build `fisherman`"),
+        });
+
+        assert_eq!(failed_stage(&error), Some(Stage::Build));
+        assert_eq!(failed_stage(&anyhow::anyhow!("Deploy timed out")), None);
+    }
+
+    #[test]
+    fn failures_can_be_described_as_json() {
+        let failure = Failure {
+            repository: String::from("alexander-jackson/fisherman"),
+            stage: Some(Stage::Build),
+            error: String::from("Failed to build `fisherman`"),
+        };
+
+        assert_eq!(
+            serde_json::to_value(&failure).unwrap(),
+            serde_json::json!({
+                "repository": "alexander-jackson/fisherman",
+                "stage": "build",
+                "error": "Failed to build `fisherman`",
+            })
+        );
+
+        let response = failure.into_response(ResponseFormat::Json);
+        assert_eq!(response.status(), 500);
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            "application/json"
+        );
+    }
+
+    #[test]
+    fn pushes_from_the_ignored_author_are_ignored() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let mut push = push_to("refs/heads/master");
+
+        assert!(!push.is_from_ignored_author(&config));
+
+        push.head_commit.author.email = Some(String::from("fisherman@example.com"));
+        assert!(push.is_from_ignored_author(&config));
+    }
+
+    #[test]
+    fn pushes_that_change_nothing_are_no_ops() {
+        let mut push = push_to("refs/heads/master");
+        assert!(!push.is_no_op());
+
+        push.before = Some(String::from("0123456789abcdef"));
+        push.after = Some(String::from("fedcba9876543210"));
+        assert!(!push.is_no_op());
+
+        push.before = Some(String::from("fedcba9876543210"));
+        assert!(push.is_no_op());
+    }
+
+    #[test]
+    fn unknown_supervisor_programs_are_detected() {
+        assert!(is_unknown_program("prod-api: ERROR (no such process)\n"));
+        assert!(!is_unknown_program(
+            "prod-api: stopped\nprod-api: ERROR (spawn error)\n"
+        ));
+    }
+
+    #[test]
+    fn deploy_keys_are_rendered_from_templates() {
+        let message = Some("Update the README\n\nTenant: acme");
+
+        assert_eq!(
+            deploy_key("{repo}", "alexander-jackson/fisherman", "master", message),
+            "alexander-jackson/fisherman"
+        );
+        assert_eq!(
+            deploy_key(
+                "{repo}@{branch}/{trailer:tenant}",
+                "alexander-jackson/fisherman",
+                "master",
+                message
+            ),
+            "alexander-jackson/fisherman@master/acme"
+        );
+        assert_eq!(
+            deploy_key(
+                "tenant-{trailer:Tenant}",
+                "alexander-jackson/fisherman",
+                "master",
+                None
+            ),
+            "tenant-"
+        );
+    }
+
+    #[test]
+    fn follow_files_replace_the_configured_branches() {
+        let config = Config::from_str(CONFIG).unwrap();
+        let push = push_to("refs/heads/production");
+
+        assert_eq!(push.deployed_branch(&config), None);
+        assert_eq!(
+            push.deployed_branch_in(&config, &["production"]),
+            Some("production")
+        );
+    }
+
+    #[test]
+    fn bitbucket_pushes_are_handled_as_pushes() {
+        let payload = serde_json::json!({
+            "push": {
+                "changes": [{
+                    "old": {
+                        "type": "branch",
+                        "name": "master",
+                        "target": {
+                            "hash": "fedcba9876543210",
+                            "author": { "raw": "Alexander Jackson <alex@example.com>" },
+                        },
+                    },
+                    "new": {
+                        "type": "branch",
+                        "name": "master",
+                        "target": {
+                            "hash": "0123456789abcdef",
+                            "message": "Update the README",
+                            "author": {
+                                "raw": "Alexander Jackson <alex@example.com>",
+                                "user": { "nickname": "alexander-jackson" },
+                            },
+                        },
+                    },
+                    "forced": false,
+                }],
+            },
+            "repository": {
+                "full_name": "alexander-jackson/fisherman",
+                "links": { "html": { "href": "https://bitbucket.org/alexander-jackson/fisherman" } },
+            },
+        });
+
+        let bytes = serde_json::to_vec(&payload).unwrap();
+        let webhook = Webhook::from_slice(WebhookVariant::BitbucketPush, &bytes).unwrap();
+
+        let push = match webhook {
+            Webhook::Push(push) => push,
+            _ => panic!("Expected a push"),
+        };
+
+        assert_eq!(push.refname, "refs/heads/master");
+        assert_eq!(push.get_full_name(), "alexander-jackson/fisherman");
+        assert_eq!(push.head_commit.id, "0123456789abcdef");
+        assert!(push.head_commit.author.is("alex@example.com"));
+        assert!(push.head_commit.author.is("alexander-jackson"));
+        assert_eq!(push.before.as_deref(), Some("fedcba9876543210"));
+        assert_eq!(
+            push.repository.ssh_url.as_deref(),
+            Some("git@bitbucket.org:alexander-jackson/fisherman.git")
+        );
+    }
+
+    #[test]
+    fn bitbucket_pushes_without_branches_are_rejected() {
+        let payload = serde_json::json!({
+            "push": { "changes": [{ "new": null, "old": null }] },
+            "repository": { "full_name": "alexander-jackson/fisherman" },
+        });
+
+        let bytes = serde_json::to_vec(&payload).unwrap();
+        assert!(Webhook::from_slice(WebhookVariant::BitbucketPush, &bytes).is_err());
+    }
+
+    #[test]
+    fn deploy_results_can_be_written_to_files() {
+        let directory =
+            std::env::temp_dir().join(format!("fisherman-result-{}", std::process::id()));
+        let path = directory.join("alexander-jackson/fisherman.json");
+
+        let result = DeployResult {
+            repository: "alexander-jackson/fisherman",
+            branch: "master",
+            commit: "0123456789abcdef",
+            success: false,
+            duration_ms: 1500,
+            finished_at: 1700000000,
+            error: Some(String::from("Failed to build")),
+        };
+
+        result.write_to(&path).unwrap();
+
+        let written: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(written["commit"], "0123456789abcdef");
+        assert_eq!(written["success"], false);
+        assert_eq!(written["duration_ms"], 1500);
+        assert_eq!(written["error"], "Failed to build");
+    }
+
+    #[test]
+    fn gitlab_pushes_are_handled_as_pushes() {
+        let payload = serde_json::json!({
+            "object_kind": "push",
+            "ref": "refs/heads/master",
+            "before": "fedcba9876543210",
+            "after": "0123456789abcdef",
+            "checkout_sha": "0123456789abcdef",
+            "project": {
+                "path_with_namespace": "alexander-jackson/fisherman",
+                "git_ssh_url": "git@gitlab.example.com:alexander-jackson/fisherman.git",
+                "web_url": "https://gitlab.example.com/alexander-jackson/fisherman",
+            },
+            "commits": [
+                {
+                    "id": "00112233445566ff",
+                    "message": "Fix a typo",
+                    "author": { "name": "Alexander Jackson" },
+                },
+                {
+                    "id": "0123456789abcdef",
+                    "message": "Update the README",
+                    "author": { "name": "Alexander Jackson", "email": "alex@example.com" },
+                },
+            ],
+        });
+
+        let bytes = serde_json::to_vec(&payload).unwrap();
+
+        let push = match Webhook::from_slice(WebhookVariant::GitLabPush, &bytes).unwrap() {
+            Webhook::Push(push) => push,
+            _ => panic!("Expected a push"),
+        };
+
+        assert_eq!(push.refname, "refs/heads/master");
+        assert_eq!(push.get_full_name(), "alexander-jackson/fisherman");
+        assert_eq!(push.head_commit.message, "Update the README");
+        assert!(push.head_commit.author.is("alex@example.com"));
+        assert_eq!(push.after.as_deref(), Some("0123456789abcdef"));
+    }
+
+    #[test]
+    fn gitlab_pushes_deleting_branches_are_rejected() {
+        let payload = serde_json::json!({
+            "ref": "refs/heads/feature",
+            "checkout_sha": null,
+            "project": { "path_with_namespace": "alexander-jackson/fisherman" },
+            "commits": [],
+        });
+
+        let bytes = serde_json::to_vec(&payload).unwrap();
+        assert!(Webhook::from_slice(WebhookVariant::GitLabPush, &bytes).is_err());
+    }
+
+    #[test]
+    fn pushed_branches_are_deployed_when_matching_patterns() {
+        let push = push_to("refs/heads/release/1.2");
+
+        assert_eq!(
+            push.changes_follow_branch(&["release/*"], MatchMode::Glob),
+            Some("release/1.2")
+        );
+        assert_eq!(
+            push.changes_follow_branch(&["release/*"], MatchMode::Exact),
+            None
+        );
+    }
+
+    #[test]
+    fn binary_failures_are_collected_when_continuing() {
+        let repository = "alexander-jackson/fisherman";
+        let config = Config::from_str(CONFIG).unwrap();
+        let mut failures = BinaryFailures::default();
+
+        let error = anyhow::anyhow!("Failed to build binary: server");
+        assert!(failures
+            .record(&config, repository, Stage::Build, "server", error)
+            .is_err());
+        assert_eq!(failures.stage(), None);
+
+        let config = Config::from_str(&CONFIG.replace(
+            "ignore_author: \"fisherman@example.com\"",
+            "continue_on_binary_failure: true",
+        ))
+        .unwrap();
+
+        let error = anyhow::anyhow!("Failed to build binary: server");
+        failures
+            .record(&config, repository, Stage::Build, "server", error)
+            .unwrap();
+        let error = anyhow::anyhow!("Failed to restart program: worker");
+        failures
+            .record(&config, repository, Stage::Restart, "worker", error)
+            .unwrap();
+
+        assert!(failures.contains("server"));
+        assert!(!failures.contains("fisherman"));
+        assert_eq!(failures.stage(), Some(Stage::Build));
+        assert_eq!(
+            failures.take_error().to_string(),
+            "Failed to deploy some binaries: server (Failed to build binary: server), worker (Failed to restart program: worker)"
+        );
+        assert_eq!(failures.len(), 0);
+    }
+
+    #[test]
+    fn partial_deploys_are_successful_deploys() {
+        let repository = "alexander-jackson/fisherman";
+        let error = DeployError {
+            stage: Stage::Restart,
+            source: anyhow::anyhow!("Failed to deploy some binaries: worker"),
+        };
+
+        let outcome = deployment_outcome(repository, Deployment::Partial(error.into()));
+
+        assert!(outcome.is_success());
+        assert!(outcome.is_deployed());
+        assert_eq!(
+            outcome.into_response(ResponseFormat::Text).status(),
+            actix_web::http::StatusCode::OK
+        );
+
+        let outcome = deployment_outcome(repository, Deployment::Skipped);
+        assert!(outcome.is_success());
+        assert!(!outcome.is_deployed());
+    }
+
+    #[tokio::test]
+    async fn timed_out_deploys_hold_the_lock_until_blocking_work_finishes() {
+        let config = Arc::new(Config::from_str(CONFIG).unwrap());
+        let repository = "alexander-jackson/fisherman";
+        let finished = Arc::new(AtomicBool::new(false));
+
+        let first = {
+            let config = Arc::clone(&config);
+            let finished = Arc::clone(&finished);
+
+            tokio::spawn(async move {
+                let _guard = config.lock_deploy(repository).await;
+                let blocking = InFlight::default();
+                let guard = blocking.enter();
+
+                // Stands in for a fetch that takes longer than the deploy is allowed to
+                let pull = run_blocking(move || {
+                    let _guard = guard;
+                    std::thread::sleep(Duration::from_millis(200));
+                    finished.store(true, Ordering::SeqCst);
+                    Ok(())
+                });
+
+                let timeout = Duration::from_millis(20);
+                run_with_timeout(repository, timeout, &blocking, pull).await
+            })
+        };
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // The next deploy cannot start until the pull has stopped changing the checkout
+        let _guard = config.lock_deploy(repository).await;
+        assert!(finished.load(Ordering::SeqCst));
+
+        let error = first.await.unwrap().unwrap_err();
+        assert!(error.to_string().starts_with("Deploy timed out"));
+    }
+
+    #[test]
+    fn tags_could_never_be_deployed() {
+        let config = Config::from_str(CONFIG).unwrap();
+
+        assert!(push_to("refs/heads/develop").could_be_deployed(&config));
+        assert!(!push_to("refs/tags/v1.0.0").could_be_deployed(&config));
+    }
+
+    #[tokio::test]
+    async fn pushes_to_unfollowed_branches_are_not_deploys() {
+        let config = Arc::new(Config::from_str(CONFIG).unwrap());
+        let push = push_to("refs/heads/develop");
+
+        assert!(!push.handle(&config).await.is_deployed());
+    }
+}
@@ -0,0 +1,1104 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use actix_web::http::StatusCode;
+use actix_web::test::{self, TestRequest};
+use actix_web::web::Data;
+use actix_web::App;
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha1::Sha1;
+use sha2::Sha256;
+use tokio::sync::{mpsc, watch, Mutex};
+
+use fisherman::events::{Delivery, EventLog};
+use fisherman::webhook::Outcome;
+use fisherman::{server, Config, State, Webhook, WebhookVariant};
+
+static SAMPLE_PAYLOAD: &[u8] = include_bytes!("../sample_payload.json");
+static SECRET: &str = "ac9045a77c15bd105cfa09a64635f9b006b3f845";
+
+static CONFIG: &str = r#"
+default:
+    ssh_private_key: "/root/.ssh/id_rsa"
+    repo_root: "/root"
+    cargo_path: "/root/.cargo/bin/cargo"
+    admin_token: "admin"
+
+specific:
+    alexander-jackson/simple:
+        secret: "ac9045a77c15bd105cfa09a64635f9b006b3f845"
+"#;
+
+fn state() -> (State, mpsc::Receiver<Delivery>) {
+    state_with(Config::from_str(CONFIG).unwrap())
+}
+
+fn state_with(config: Config) -> (State, mpsc::Receiver<Delivery>) {
+    let config = Arc::new(config);
+    let (sender, receiver) = mpsc::channel(config.resolve_queue_capacity());
+
+    let state = State {
+        config,
+        sender: Arc::new(Mutex::new(sender)),
+        paused: Arc::new(watch::channel(false).0),
+        events: Arc::new(Mutex::new(EventLog::default())),
+        ready: Arc::new(AtomicBool::new(true)),
+        overflow: Arc::default(),
+    };
+
+    (state, receiver)
+}
+
+fn sign_sha1(bytes: &[u8], secret: &str) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(bytes);
+
+    format!("sha1={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+fn sign(bytes: &[u8], secret: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(bytes);
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+async fn post(request: TestRequest) -> (StatusCode, mpsc::Receiver<Delivery>) {
+    let (state, receiver) = state();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let response = test::call_service(&app, request.uri("/").to_request()).await;
+
+    (response.status(), receiver)
+}
+
+#[actix_web::test]
+async fn signed_payloads_are_accepted_and_queued() {
+    let request = TestRequest::post()
+        .insert_header(("X-GitHub-Event", "ping"))
+        .insert_header(("X-Hub-Signature-256", sign(SAMPLE_PAYLOAD, SECRET)))
+        .set_payload(SAMPLE_PAYLOAD);
+
+    let (status, mut receiver) = post(request).await;
+
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let delivery = receiver.try_recv().unwrap();
+    assert_eq!(delivery.webhook.get_full_name(), "alexander-jackson/simple");
+}
+
+#[actix_web::test]
+async fn unsigned_payloads_are_unauthorized() {
+    let request = TestRequest::post()
+        .insert_header(("X-GitHub-Event", "ping"))
+        .set_payload(SAMPLE_PAYLOAD);
+
+    let (status, mut receiver) = post(request).await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(receiver.try_recv().is_err());
+}
+
+#[actix_web::test]
+async fn incorrectly_signed_payloads_are_unauthorized() {
+    let request = TestRequest::post()
+        .insert_header(("X-GitHub-Event", "ping"))
+        .insert_header(("X-Hub-Signature-256", sign(SAMPLE_PAYLOAD, "wrong")))
+        .set_payload(SAMPLE_PAYLOAD);
+
+    let (status, _) = post(request).await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn missing_event_headers_are_bad_requests() {
+    let request = TestRequest::post()
+        .insert_header(("X-Hub-Signature-256", sign(SAMPLE_PAYLOAD, SECRET)))
+        .set_payload(SAMPLE_PAYLOAD);
+
+    let (status, _) = post(request).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn unknown_events_are_bad_requests() {
+    let request = TestRequest::post()
+        .insert_header(("X-GitHub-Event", "gollum"))
+        .set_payload(SAMPLE_PAYLOAD);
+
+    let (status, _) = post(request).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn malformed_payloads_are_unprocessable() {
+    let payload = b"{\"zen\": \"Keep it logically awesome.\"}";
+
+    let request = TestRequest::post()
+        .insert_header(("X-GitHub-Event", "ping"))
+        .insert_header(("X-Hub-Signature-256", sign(payload, SECRET)))
+        .set_payload(&payload[..]);
+
+    let (status, _) = post(request).await;
+
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[actix_web::test]
+async fn status_reports_whether_processing_is_paused() {
+    let (state, _receiver) = state();
+    state.paused.send_replace(true);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let request = TestRequest::get().uri("/status").to_request();
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+
+    assert_eq!(body["paused"], true);
+}
+
+#[actix_web::test]
+async fn processing_can_be_paused_and_resumed() {
+    let (state, _receiver) = state();
+    let paused = Arc::clone(&state.paused);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let request = TestRequest::post()
+        .uri("/pause")
+        .insert_header(("Authorization", "Bearer admin"))
+        .to_request();
+
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::OK
+    );
+    assert!(*paused.borrow());
+
+    let request = TestRequest::post()
+        .uri("/resume")
+        .insert_header(("Authorization", "Bearer admin"))
+        .to_request();
+
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::OK
+    );
+    assert!(!*paused.borrow());
+}
+
+#[actix_web::test]
+async fn pausing_requires_the_admin_token() {
+    let (state, _receiver) = state();
+    let paused = Arc::clone(&state.paused);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let request = TestRequest::post()
+        .uri("/pause")
+        .insert_header(("Authorization", "Bearer wrong"))
+        .to_request();
+
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert!(!*paused.borrow());
+}
+
+#[actix_web::test]
+async fn received_webhooks_can_be_replayed() {
+    let (state, mut receiver) = state();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let request = TestRequest::post()
+        .uri("/")
+        .insert_header(("X-GitHub-Event", "ping"))
+        .insert_header(("X-GitHub-Delivery", "72d3162e"))
+        .insert_header(("X-Hub-Signature-256", sign(SAMPLE_PAYLOAD, SECRET)))
+        .set_payload(SAMPLE_PAYLOAD)
+        .to_request();
+
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::ACCEPTED
+    );
+    assert!(receiver.try_recv().is_ok());
+
+    let request = TestRequest::post()
+        .uri("/replay/72d3162e")
+        .insert_header(("Authorization", "Bearer admin"))
+        .to_request();
+
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::ACCEPTED
+    );
+
+    let delivery = receiver.try_recv().unwrap();
+    assert_eq!(delivery.webhook.get_full_name(), "alexander-jackson/simple");
+    assert_eq!(delivery.correlation, "72d3162e");
+
+    let request = TestRequest::post()
+        .uri("/replay/unknown")
+        .insert_header(("Authorization", "Bearer admin"))
+        .to_request();
+
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[actix_web::test]
+async fn repositories_without_their_own_secret_can_be_rejected() {
+    let mut config = Config::from_str(CONFIG).unwrap();
+    config.default.secret = Some(String::from(SECRET));
+    config.default.require_specific_secrets = Some(true);
+    config.specific = None;
+
+    let (state, mut receiver) = state_with(config);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let request = TestRequest::post()
+        .uri("/")
+        .insert_header(("X-GitHub-Event", "ping"))
+        .insert_header(("X-Hub-Signature-256", sign(SAMPLE_PAYLOAD, SECRET)))
+        .set_payload(SAMPLE_PAYLOAD)
+        .to_request();
+
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert!(receiver.try_recv().is_err());
+}
+
+#[actix_web::test]
+async fn events_that_are_not_allowed_are_skipped() {
+    let config = format!("{}        allowed_events: [\"push\"]\n", CONFIG);
+    let (state, mut receiver) = state_with(Config::from_str(&config).unwrap());
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let request = TestRequest::post()
+        .uri("/")
+        .insert_header(("X-GitHub-Event", "ping"))
+        .insert_header(("X-Hub-Signature-256", sign(SAMPLE_PAYLOAD, SECRET)))
+        .set_payload(SAMPLE_PAYLOAD)
+        .to_request();
+
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::OK
+    );
+    assert!(receiver.try_recv().is_err());
+}
+
+#[actix_web::test]
+async fn events_can_be_listed_and_filtered() {
+    let (state, _receiver) = state();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let request = TestRequest::post()
+        .uri("/")
+        .insert_header(("X-GitHub-Event", "ping"))
+        .insert_header(("X-GitHub-Delivery", "72d3162e"))
+        .insert_header(("X-Hub-Signature-256", sign(SAMPLE_PAYLOAD, SECRET)))
+        .set_payload(SAMPLE_PAYLOAD)
+        .to_request();
+
+    test::call_service(&app, request).await;
+
+    let request = TestRequest::get()
+        .uri("/events?repository=alexander-jackson/simple&kind=ping")
+        .to_request();
+
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["events"][0]["delivery_id"], "72d3162e");
+    assert_eq!(body["events"][0]["kind"], "ping");
+
+    let request = TestRequest::get().uri("/events?offset=1").to_request();
+
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["offset"], 1);
+    assert_eq!(body["events"], Value::Array(Vec::new()));
+
+    let request = TestRequest::get()
+        .uri("/events?correlation=72d3162e")
+        .to_request();
+
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["events"][0]["correlation"], "72d3162e");
+
+    let request = TestRequest::get().uri("/events?kind=push").to_request();
+
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+
+    assert_eq!(body["total"], 0);
+    assert_eq!(body["events"], Value::Array(Vec::new()));
+
+    let request = TestRequest::get().uri("/events?limit=many").to_request();
+
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+}
+
+#[actix_web::test]
+async fn configs_can_be_validated_remotely() {
+    let mut config = Config::from_str(CONFIG).unwrap();
+    config.default.ssh_private_key = Some(PathBuf::from("/nonexistent/id_rsa"));
+
+    let (state, _receiver) = state_with(config);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let request = TestRequest::post().uri("/validate").to_request();
+
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    let request = TestRequest::post()
+        .uri("/validate")
+        .insert_header(("Authorization", "Bearer admin"))
+        .to_request();
+
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+
+    assert_eq!(body["valid"], false);
+    assert_eq!(body["mistakes"][0]["key"], Value::Null);
+    assert!(body["mistakes"][0]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("`ssh_private_key`"));
+}
+
+#[actix_web::test]
+async fn liveness_is_reported_without_authentication() {
+    let (state, _receiver) = state();
+    state.ready.store(false, Ordering::Release);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let request = TestRequest::get().uri("/healthz").to_request();
+    let response = test::call_service(&app, request).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body, serde_json::json!({ "status": "ok" }));
+}
+
+#[actix_web::test]
+async fn readiness_is_reported_once_startup_finishes() {
+    let (state, _receiver) = state();
+    state.ready.store(false, Ordering::Release);
+
+    let ready = Arc::clone(&state.ready);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let request = TestRequest::get().uri("/ready").to_request();
+
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    ready.store(true, Ordering::Release);
+
+    let request = TestRequest::get().uri("/ready").to_request();
+
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::OK
+    );
+}
+
+#[actix_web::test]
+async fn manual_deploys_reject_disallowed_references() {
+    let (state, _receiver) = state();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let cases = [
+        (
+            "/deploy/alexander-jackson/simple",
+            "wrong",
+            StatusCode::UNAUTHORIZED,
+        ),
+        (
+            "/deploy/alexander-jackson/unknown",
+            "admin",
+            StatusCode::NOT_FOUND,
+        ),
+        (
+            "/deploy/alexander-jackson/simple?ref=..%2Fmaster",
+            "admin",
+            StatusCode::BAD_REQUEST,
+        ),
+    ];
+
+    for (uri, token, expected) in cases {
+        let request = TestRequest::post()
+            .uri(uri)
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+
+        assert_eq!(test::call_service(&app, request).await.status(), expected);
+    }
+}
+
+#[actix_web::test]
+async fn manual_deploys_are_queued_for_the_repository_worker() {
+    let (state, mut receiver) = state();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let request = TestRequest::post()
+        .uri("/deploy/alexander-jackson/simple?ref=hotfix")
+        .insert_header(("Authorization", "Bearer admin"))
+        .to_request();
+
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::ACCEPTED
+    );
+
+    // The deploy waits its turn behind webhooks rather than starting straight away
+    let delivery = receiver.recv().await.unwrap();
+    assert_eq!(delivery.webhook.variant(), WebhookVariant::Deploy);
+    assert_eq!(delivery.webhook.get_full_name(), "alexander-jackson/simple");
+}
+
+#[actix_web::test]
+async fn reconciles_wait_for_the_repository_worker() {
+    let (state, mut receiver) = state();
+    let paused = Arc::clone(&state.paused);
+    paused.send_replace(true);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let request = || {
+        TestRequest::post()
+            .uri("/reconcile/alexander-jackson/simple")
+            .insert_header(("Authorization", "Bearer admin"))
+            .to_request()
+    };
+
+    // Waiting for a paused worker would hold the request open indefinitely
+    assert_eq!(
+        test::call_service(&app, request()).await.status(),
+        StatusCode::CONFLICT
+    );
+
+    paused.send_replace(false);
+
+    let worker = async {
+        let delivery = receiver.recv().await.unwrap();
+        assert_eq!(delivery.webhook.variant(), WebhookVariant::Deploy);
+
+        for reply in delivery.replies {
+            reply.send(Outcome::Deployed).unwrap();
+        }
+    };
+
+    let (response, ()) = tokio::join!(test::call_service(&app, request()), worker);
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["deployed"], true);
+    assert_eq!(body["branch"], "master");
+}
+
+#[actix_web::test]
+async fn reconciling_requires_a_configured_repository() {
+    let (state, _receiver) = state();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let cases = [
+        (
+            "/reconcile/alexander-jackson/simple",
+            "wrong",
+            StatusCode::UNAUTHORIZED,
+        ),
+        (
+            "/reconcile/alexander-jackson/unknown",
+            "admin",
+            StatusCode::NOT_FOUND,
+        ),
+    ];
+
+    for (uri, token, expected) in cases {
+        let request = TestRequest::post()
+            .uri(uri)
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+
+        assert_eq!(test::call_service(&app, request).await.status(), expected);
+    }
+}
+
+#[actix_web::test]
+async fn gitlab_pushes_are_verified_by_their_token() {
+    let payload = serde_json::json!({
+        "ref": "refs/heads/master",
+        "checkout_sha": "0123456789abcdef",
+        "project": { "path_with_namespace": "alexander-jackson/simple" },
+        "commits": [{
+            "id": "0123456789abcdef",
+            "message": "Update the README",
+            "author": { "name": "Alexander Jackson" },
+        }],
+    });
+
+    let bytes = serde_json::to_vec(&payload).unwrap();
+
+    for (token, expected) in [
+        (SECRET, StatusCode::ACCEPTED),
+        ("wrong", StatusCode::UNAUTHORIZED),
+    ] {
+        let request = TestRequest::post()
+            .insert_header(("X-Gitlab-Event", "Push Hook"))
+            .insert_header(("X-Gitlab-Token", token))
+            .set_payload(bytes.clone());
+
+        let (status, mut receiver) = post(request).await;
+
+        assert_eq!(status, expected);
+        assert_eq!(
+            receiver.try_recv().is_ok(),
+            expected == StatusCode::ACCEPTED
+        );
+    }
+}
+
+#[actix_web::test]
+async fn legacy_sha1_signatures_are_accepted_without_sha256_ones() {
+    let request = TestRequest::post()
+        .insert_header(("X-GitHub-Event", "ping"))
+        .insert_header(("X-Hub-Signature", sign_sha1(SAMPLE_PAYLOAD, SECRET)))
+        .set_payload(SAMPLE_PAYLOAD);
+
+    let (status, mut receiver) = post(request).await;
+
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert!(receiver.try_recv().is_ok());
+
+    // The SHA-256 signature is preferred if both are sent
+    let request = TestRequest::post()
+        .insert_header(("X-GitHub-Event", "ping"))
+        .insert_header(("X-Hub-Signature", sign_sha1(SAMPLE_PAYLOAD, SECRET)))
+        .insert_header(("X-Hub-Signature-256", sign(SAMPLE_PAYLOAD, "wrong")))
+        .set_payload(SAMPLE_PAYLOAD);
+
+    let (status, _) = post(request).await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn truncated_signatures_are_rejected() {
+    for signature in ["xx", "", "sha256="] {
+        let request = TestRequest::post()
+            .insert_header(("X-GitHub-Event", "ping"))
+            .insert_header(("X-Hub-Signature-256", signature))
+            .set_payload(SAMPLE_PAYLOAD);
+
+        let (status, mut receiver) = post(request).await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(receiver.try_recv().is_err());
+    }
+}
+
+#[actix_web::test]
+async fn webhooks_are_queued_in_the_background_after_the_queue_timeout() {
+    let config = CONFIG.replace(
+        "    admin_token",
+        "    queue_capacity: 2\n    queue_timeout_ms: 10\n    admin_token",
+    );
+    let (state, mut receiver) = state_with(Config::from_str(&config).unwrap());
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let request = |delivery: usize| {
+        TestRequest::post()
+            .uri("/")
+            .insert_header(("X-GitHub-Event", "ping"))
+            .insert_header(("X-GitHub-Delivery", format!("{:08}-delivery", delivery)))
+            .insert_header(("X-Hub-Signature-256", sign(SAMPLE_PAYLOAD, SECRET)))
+            .set_payload(SAMPLE_PAYLOAD)
+            .to_request()
+    };
+
+    // The later webhooks are accepted despite the queue being full
+    for delivery in 0..4 {
+        assert_eq!(
+            test::call_service(&app, request(delivery)).await.status(),
+            StatusCode::ACCEPTED
+        );
+    }
+
+    // Only as many webhooks as the queue holds can wait in the background
+    assert_eq!(
+        test::call_service(&app, request(4)).await.status(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    for delivery in 0..4 {
+        let received = receiver.recv().await.unwrap();
+        assert_eq!(received.correlation, format!("{:08}", delivery));
+    }
+}
+
+#[actix_web::test]
+async fn webhooks_are_rejected_when_the_queue_is_full() {
+    let config = CONFIG.replace("    admin_token", "    queue_capacity: 1\n    admin_token");
+    let (state, mut receiver) = state_with(Config::from_str(&config).unwrap());
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let request = || {
+        TestRequest::post()
+            .uri("/")
+            .insert_header(("X-GitHub-Event", "ping"))
+            .insert_header(("X-Hub-Signature-256", sign(SAMPLE_PAYLOAD, SECRET)))
+            .set_payload(SAMPLE_PAYLOAD)
+            .to_request()
+    };
+
+    assert_eq!(
+        test::call_service(&app, request()).await.status(),
+        StatusCode::ACCEPTED
+    );
+    assert_eq!(
+        test::call_service(&app, request()).await.status(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    assert!(receiver.try_recv().is_ok());
+    assert_eq!(
+        test::call_service(&app, request()).await.status(),
+        StatusCode::ACCEPTED
+    );
+
+    drop(receiver);
+    assert_eq!(
+        test::call_service(&app, request()).await.status(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+}
+
+#[actix_web::test]
+async fn processing_finishes_queued_webhooks_once_the_sender_is_dropped() {
+    let request = TestRequest::post()
+        .insert_header(("X-GitHub-Event", "ping"))
+        .insert_header(("X-Hub-Signature-256", sign(SAMPLE_PAYLOAD, SECRET)))
+        .set_payload(SAMPLE_PAYLOAD);
+
+    let (status, mut received) = post(request).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let delivery = received.try_recv().unwrap();
+    let config = Arc::new(Config::from_str(CONFIG).unwrap());
+    let (sender, receiver) = mpsc::channel(config.resolve_queue_capacity());
+
+    let copy = Delivery::new(delivery.correlation.clone(), delivery.webhook.clone());
+
+    sender.send(copy).await.unwrap();
+    sender.send(delivery).await.unwrap();
+    drop(sender);
+
+    let (_paused, paused_receiver) = watch::channel(false);
+    let processing = fisherman::process_webhooks(config, receiver, paused_receiver);
+
+    tokio::time::timeout(std::time::Duration::from_secs(5), processing)
+        .await
+        .unwrap();
+}
+
+#[actix_web::test]
+async fn synchronous_webhooks_wait_for_the_repository_worker() {
+    let config = CONFIG.replace(
+        "        secret:",
+        "        synchronous: true\n        secret:",
+    );
+    let (state, receiver) = state_with(Config::from_str(&config).unwrap());
+
+    let (paused, paused_receiver) = watch::channel(true);
+    let processing = tokio::spawn(fisherman::process_webhooks(
+        Arc::clone(&state.config),
+        receiver,
+        paused_receiver,
+    ));
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let request = TestRequest::post()
+        .uri("/")
+        .insert_header(("X-GitHub-Event", "ping"))
+        .insert_header(("X-Hub-Signature-256", sign(SAMPLE_PAYLOAD, SECRET)))
+        .set_payload(SAMPLE_PAYLOAD)
+        .to_request();
+
+    let response = test::call_service(&app, request);
+    tokio::pin!(response);
+
+    // Nothing is processed while paused, so the response is still waiting
+    assert!(
+        tokio::time::timeout(std::time::Duration::from_millis(100), &mut response)
+            .await
+            .is_err()
+    );
+
+    paused.send(false).unwrap();
+
+    let response = tokio::time::timeout(std::time::Duration::from_secs(5), response)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = test::read_body(response).await;
+    assert!(String::from_utf8_lossy(&body).contains("alexander-jackson/simple"));
+
+    processing.abort();
+}
+
+#[actix_web::test]
+async fn deploys_without_a_reference_reject_followed_patterns() {
+    let config = CONFIG.replace(
+        "        secret:",
+        "        follow: \"release/*\"\n        match_mode: \"glob\"\n        secret:",
+    );
+    let (state, _receiver) = state_with(Config::from_str(&config).unwrap());
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    for uri in [
+        "/deploy/alexander-jackson/simple",
+        "/reconcile/alexander-jackson/simple",
+    ] {
+        let request = TestRequest::post()
+            .uri(uri)
+            .insert_header(("Authorization", "Bearer admin"))
+            .to_request();
+
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = test::read_body(response).await;
+        assert!(String::from_utf8_lossy(&body).contains("release/*"));
+    }
+}
+
+#[actix_web::test]
+async fn webhooks_for_different_repositories_are_processed_concurrently() {
+    let repo_root =
+        std::env::temp_dir().join(format!("fisherman-concurrent-{}", std::process::id()));
+
+    for name in ["first", "second"] {
+        std::fs::create_dir_all(repo_root.join(name)).unwrap();
+    }
+
+    // Each repository waits for the other to start, so this only finishes if they run together
+    let waits_for = |name: &str, other: &str| {
+        format!(
+            r#"
+    alexander-jackson/{name}:
+        always_commands:
+            - program: "sh"
+              args: ["-c", "touch {name}.ready; while [ ! -f ../{other}/{other}.ready ]; do sleep 0.05; done"]"#
+        )
+    };
+
+    let config = format!(
+        r#"
+default:
+    repo_root: "{}"
+    cargo_path: "/root/.cargo/bin/cargo"
+    command_timeout_secs: 30
+
+specific:{}{}
+"#,
+        repo_root.display(),
+        waits_for("first", "second"),
+        waits_for("second", "first"),
+    );
+
+    let config = Arc::new(Config::from_str(&config).unwrap());
+    let (sender, receiver) = mpsc::channel(config.resolve_queue_capacity());
+
+    for name in ["first", "second"] {
+        let payload = serde_json::json!({
+            "ref": "refs/heads/develop",
+            "repository": {
+                "name": name,
+                "full_name": format!("alexander-jackson/{}", name),
+            },
+            "head_commit": {
+                "id": "0123456789abcdef",
+                "message": "Update the README",
+                "author": { "name": "Alexander Jackson" },
+            },
+        });
+
+        let webhook =
+            Webhook::from_slice(WebhookVariant::Push, payload.to_string().as_bytes()).unwrap();
+
+        sender
+            .send(Delivery::new(name.to_owned(), webhook))
+            .await
+            .unwrap();
+    }
+
+    drop(sender);
+
+    let (_paused, paused_receiver) = watch::channel(false);
+    let processing = fisherman::process_webhooks(config, receiver, paused_receiver);
+
+    let result = tokio::time::timeout(std::time::Duration::from_secs(10), processing).await;
+
+    assert!(repo_root.join("first/first.ready").exists());
+    assert!(repo_root.join("second/second.ready").exists());
+    std::fs::remove_dir_all(&repo_root).unwrap();
+
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn deploys_record_their_stages_as_events() {
+    let repo_root = std::env::temp_dir().join(format!("fisherman-stages-{}", std::process::id()));
+    std::fs::create_dir_all(&repo_root).unwrap();
+
+    // Deploy a clone of a local repository, building and restarting with commands that succeed
+    let setup = std::process::Command::new("sh")
+        .args([
+            "-c",
+            "git init -q -b master origin \
+             && git -C origin -c user.name=test -c user.email=test@example.com commit -q --allow-empty -m init \
+             && git clone -q origin simple",
+        ])
+        .current_dir(&repo_root)
+        .status()
+        .unwrap();
+
+    assert!(setup.success());
+
+    let config = format!(
+        r#"
+default:
+    repo_root: "{}"
+    cargo_path: "true"
+    restart_command:
+        program: "true"
+
+specific:
+    alexander-jackson/simple:
+        binaries: ["app"]
+"#,
+        repo_root.display(),
+    );
+
+    let config = Arc::new(Config::from_str(&config).unwrap());
+    let (sender, receiver) = mpsc::channel(config.resolve_queue_capacity());
+
+    let payload = serde_json::json!({
+        "ref": "refs/heads/master",
+        "repository": {
+            "name": "simple",
+            "full_name": "alexander-jackson/simple",
+        },
+        "head_commit": {
+            "id": "0123456789abcdef",
+            "message": "Update the README",
+            "author": { "name": "Alexander Jackson" },
+        },
+    });
+
+    let webhook =
+        Webhook::from_slice(WebhookVariant::Push, payload.to_string().as_bytes()).unwrap();
+
+    sender
+        .send(Delivery::new(String::from("5ca1ab1e"), webhook))
+        .await
+        .unwrap();
+
+    drop(sender);
+
+    let (_paused, paused_receiver) = watch::channel(false);
+    let processing = fisherman::process_webhooks(Arc::clone(&config), receiver, paused_receiver);
+    let result = tokio::time::timeout(std::time::Duration::from_secs(10), processing).await;
+
+    std::fs::remove_dir_all(&repo_root).unwrap();
+    assert!(result.is_ok());
+
+    let (mut state, _receiver) = state();
+    state.config = config;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(Data::new(state))
+            .configure(server::configure),
+    )
+    .await;
+
+    let request = TestRequest::get()
+        .uri("/events?correlation=5ca1ab1e")
+        .to_request();
+
+    let body: Value = test::call_and_read_body_json(&app, request).await;
+    let stages: Vec<_> = body["stages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|stage| (stage["stage"].as_str(), stage["binary"].as_str()))
+        .collect();
+
+    assert_eq!(
+        stages,
+        [
+            (Some("pull"), None),
+            (Some("build"), Some("app")),
+            (Some("restart"), Some("app")),
+        ]
+    );
+
+    let request = TestRequest::get()
+        .uri("/events?correlation=5ca1ab1e")
+        .insert_header(("Accept", "text/plain"))
+        .to_request();
+
+    let body = test::call_and_read_body(&app, request).await;
+    let lines: Vec<_> = std::str::from_utf8(&body)
+        .unwrap()
+        .lines()
+        .map(|line| line.split_once(" 5ca1ab1e ").unwrap().1)
+        .collect();
+
+    assert_eq!(
+        lines,
+        [
+            "alexander-jackson/simple pulled",
+            "alexander-jackson/simple built app",
+            "alexander-jackson/simple restarted app",
+        ]
+    );
+}